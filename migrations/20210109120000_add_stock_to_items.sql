@@ -0,0 +1,2 @@
+ALTER TABLE items
+  ADD COLUMN stock INT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE customers
+  ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE customers
+  ADD COLUMN last_login_at timestamptz DEFAULT NULL;
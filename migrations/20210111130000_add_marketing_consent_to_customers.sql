@@ -0,0 +1,3 @@
+ALTER TABLE customers
+  ADD COLUMN marketing_opt_in BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN marketing_opt_in_at timestamptz;
@@ -0,0 +1,2 @@
+ALTER TABLE customers
+  ADD COLUMN avatar_url TEXT DEFAULT NULL;
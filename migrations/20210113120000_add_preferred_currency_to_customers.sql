@@ -0,0 +1,2 @@
+ALTER TABLE customers
+  ADD COLUMN preferred_currency currency_type NOT NULL DEFAULT 'GBP';
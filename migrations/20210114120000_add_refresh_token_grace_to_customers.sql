@@ -0,0 +1,3 @@
+-- After a refresh token is rotated, the previous count is still accepted until this time
+ALTER TABLE customers
+  ADD COLUMN refresh_token_grace_until timestamptz DEFAULT NULL;
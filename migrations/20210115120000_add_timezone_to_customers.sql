@@ -0,0 +1,2 @@
+ALTER TABLE customers
+  ADD COLUMN timezone TEXT DEFAULT NULL;
@@ -0,0 +1,3 @@
+-- An email change that hasn't been verified yet
+ALTER TABLE customers
+  ADD COLUMN pending_email TEXT DEFAULT NULL;
@@ -0,0 +1,14 @@
+CREATE TABLE saved_carts(
+  id uuid NOT NULL,
+  customer_id uuid NOT NULL,
+  PRIMARY KEY (id),
+  name TEXT NOT NULL,
+  items JSONB NOT NULL DEFAULT '[]'::JSONB,
+  created_at timestamptz NOT NULL DEFAULT NOW(),
+  CONSTRAINT fk_customer
+    FOREIGN KEY(customer_id)
+    REFERENCES customers(id)
+    ON DELETE CASCADE
+);
+
+CREATE INDEX saved_carts_customer_id_idx ON saved_carts (customer_id);
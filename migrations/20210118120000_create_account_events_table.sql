@@ -0,0 +1,17 @@
+CREATE TYPE account_event_kind AS ENUM ('LOGIN', 'EMAIL_CHANGE', 'TOKENS_INVALIDATED');
+
+CREATE TABLE account_events(
+  id uuid NOT NULL,
+  customer_id uuid NOT NULL,
+  PRIMARY KEY (id),
+  kind account_event_kind NOT NULL,
+  ip_address TEXT,
+  user_agent TEXT,
+  created_at timestamptz NOT NULL DEFAULT NOW(),
+  CONSTRAINT fk_customer
+    FOREIGN KEY(customer_id)
+    REFERENCES customers(id)
+    ON DELETE CASCADE
+);
+
+CREATE INDEX account_events_customer_id_idx ON account_events (customer_id, created_at DESC);
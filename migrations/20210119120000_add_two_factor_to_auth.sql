@@ -0,0 +1,3 @@
+ALTER TABLE auth
+  ADD COLUMN two_factor_secret BYTEA,
+  ADD COLUMN two_factor_enabled BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+ALTER TABLE customers
+  ADD COLUMN consented_at timestamptz DEFAULT NULL,
+  ADD COLUMN consent_policy_version TEXT DEFAULT NULL;
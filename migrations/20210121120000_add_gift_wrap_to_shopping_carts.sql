@@ -0,0 +1,2 @@
+ALTER TABLE shopping_carts
+  ADD COLUMN gift_wrap BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE customers
+  ADD COLUMN preferred_language TEXT DEFAULT NULL;
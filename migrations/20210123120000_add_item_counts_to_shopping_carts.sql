@@ -0,0 +1,9 @@
+ALTER TABLE shopping_carts
+  ADD COLUMN item_count INT NOT NULL DEFAULT 0,
+  ADD COLUMN distinct_item_count INT NOT NULL DEFAULT 0;
+
+UPDATE shopping_carts SET
+  item_count = COALESCE(
+    (SELECT SUM((item->>'quantity')::INT) FROM jsonb_array_elements(items) item), 0
+  ),
+  distinct_item_count = COALESCE(jsonb_array_length(items), 0);
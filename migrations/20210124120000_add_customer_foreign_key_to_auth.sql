@@ -0,0 +1,10 @@
+-- Fails if there are logins without a customer, they can be found with
+-- SELECT id FROM auth a WHERE NOT EXISTS (SELECT 1 FROM customers c WHERE c.id = a.id);
+
+-- Deferred as the login is inserted before the customer when signing up
+ALTER TABLE auth
+  ADD CONSTRAINT fk_customer
+    FOREIGN KEY(id)
+    REFERENCES customers(id)
+    ON DELETE CASCADE
+    DEFERRABLE INITIALLY DEFERRED;
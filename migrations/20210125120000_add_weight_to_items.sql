@@ -0,0 +1,2 @@
+ALTER TABLE items
+  ADD COLUMN weight_grams INT NOT NULL DEFAULT 0;
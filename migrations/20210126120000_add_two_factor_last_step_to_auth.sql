@@ -0,0 +1,4 @@
+-- The time step of the last two factor code that was accepted, codes from that step
+-- or earlier are rejected so they can't be replayed
+ALTER TABLE auth
+  ADD COLUMN two_factor_last_step BIGINT DEFAULT NULL;
@@ -0,0 +1,12 @@
+#!/usr/bin/env bash
+set -x
+set -eo pipefail
+
+# Confirms that the crate compiles purely from `sqlx-data.json`, without
+# needing a live database. `DATABASE_URL` is unset so the macros can't fall
+# back to connecting to a local instance. Only the library is checked, as that's
+# what `cargo sqlx prepare -- --lib` generates the metadata for, the integration
+# tests seed the database with `query_file!` and always need one
+env -u DATABASE_URL SQLX_OFFLINE=true cargo check --lib
+
+>&2 echo "Crate compiles against the offline SQLx metadata"
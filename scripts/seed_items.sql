@@ -1,7 +1,7 @@
-INSERT INTO items (sku, name, description, price)
+INSERT INTO items (sku, name, description, price, stock, tags, weight_grams)
   VALUES 
-    ('12345678', 'Item 1', 'Description 1', 0.99),
-    ('22345678', 'Item 2', 'Description 2', 10.50),
-    ('32345678', 'Item 3', 'Description 3', 100.30),
-    ('42345678', 'Item 4', 'Description 4', 15.98),
-    ('52345678', 'Item 5', 'Description 5', 100.249);
+    ('12345678', 'Item 1', 'Description 1', 0.99, 100, ARRAY[]::VARCHAR[], 100),
+    ('22345678', 'Item 2', 'Description 2', 10.50, 100, ARRAY[]::VARCHAR[], 500),
+    ('32345678', 'Item 3', 'Description 3', 100.30, 50, ARRAY['electronics'], 2500),
+    ('42345678', 'Item 4', 'Description 4', 15.98, 25, ARRAY['electronics', 'gifts'], 300),
+    ('52345678', 'Item 5', 'Description 5', 100.249, 0, ARRAY['electronics'], 1500);
@@ -109,8 +109,22 @@ mod tests {
                 id: Uuid::new_v4(),
                 public_id: Uuid::new_v4(),
                 hashed_password: email.to_string(),
+                two_factor_secret: None,
+                two_factor_enabled: false,
             })
         }
+        async fn set_two_factor_secret(_: Uuid, _: Vec<u8>, _: &PgPool) -> Result<bool> {
+            unimplemented!()
+        }
+        async fn find_two_factor_secret(_: Uuid, _: &PgPool) -> Result<Option<Vec<u8>>> {
+            unimplemented!()
+        }
+        async fn enable_two_factor(_: Uuid, _: &PgPool) -> Result<()> {
+            unimplemented!()
+        }
+        async fn use_two_factor_step(_: Uuid, _: i64, _: &PgPool) -> Result<bool> {
+            unimplemented!()
+        }
     }
 
     fn set_up_env_vars() {
@@ -4,17 +4,33 @@ use jsonwebtoken::{
 };
 use lazy_static::lazy_static;
 use sqlx::PgPool;
-use std::env;
-use tracing::error;
+use std::{collections::HashMap, env, mem};
+use tracing::{debug, error};
 use uuid::Uuid;
 
 use crate::{
-    auth::{ACCESS_TOKEN_DURATION, REFRESH_TOKEN_DURATION},
-    database::AuthRepository,
-    models::{BazaarToken, Claims, CustomerType, TokenType},
+    auth::{
+        ACCESS_TOKEN_DURATION, CART_SHARE_TOKEN_DURATION, EMAIL_CHANGE_TOKEN_DURATION,
+        REFRESH_TOKEN_DURATION,
+    },
+    database::{AuthRepository, CustomerRepository},
+    graphql::GraphqlContext,
+    models::{
+        BazaarToken, Claims, Customer, CustomerType, EmailChangeClaims, TokenType, CLAIMS_VERSION,
+        EMAIL_CHANGE_AUDIENCE,
+    },
     BazaarError,
 };
 
+/// `leeway` is how many seconds past its `exp` a token is still accepted, to tolerate
+/// clock skew between the servers issuing and verifying tokens. It comes from
+/// `auth.token_leeway_seconds`
+fn validation(leeway: u64) -> Validation {
+    let mut validation = Validation::new(Algorithm::PS256);
+    validation.leeway = leeway;
+    validation
+}
+
 // @TODO - check these are actually okay being `lazy_static` - if the server
 // is left up and running for a long time, but we wanted to cycle keys every x
 // days, would this pick up on the changes? or would it store a constant value
@@ -50,21 +66,90 @@ lazy_static! {
     };
 }
 
+/// Remembers the public -> private ID lookups made while verifying the tokens on a
+/// single request. The access and refresh tokens share the same subject, so when both
+/// are verified only one lookup needs to hit the database
+#[derive(Debug, Default)]
+pub struct IdMappingCache {
+    ids: HashMap<Option<Uuid>, Option<Uuid>>,
+}
+
+impl IdMappingCache {
+    pub async fn map_id<DB: AuthRepository>(
+        &mut self,
+        public_id: Option<Uuid>,
+        pool: &PgPool,
+    ) -> Result<Option<Uuid>, BazaarError> {
+        if let Some(id) = self.ids.get(&public_id) {
+            return Ok(*id);
+        }
+        let id = DB::map_id(public_id, pool).await?;
+        self.ids.insert(public_id, id);
+        Ok(id)
+    }
+}
+
 #[tracing::instrument(skip(token, pool))]
 pub async fn verify_and_deserialize_token<DB: AuthRepository>(
     token: &str,
     token_type: TokenType,
+    leeway: u64,
+    pool: &PgPool,
+) -> Result<BazaarToken, BazaarError> {
+    let mut cache = IdMappingCache::default();
+    verify_and_deserialize_token_with_cache::<DB>(token, token_type, leeway, &mut cache, pool).await
+}
+
+/// Same as `verify_and_deserialize_token`, but re-uses any ID mappings already
+/// made for this request
+#[tracing::instrument(skip(token, cache, pool))]
+pub async fn verify_and_deserialize_token_with_cache<DB: AuthRepository>(
+    token: &str,
+    token_type: TokenType,
+    leeway: u64,
+    cache: &mut IdMappingCache,
     pool: &PgPool,
 ) -> Result<BazaarToken, BazaarError> {
     if token.is_empty() {
         return Err(BazaarError::InvalidToken("No token was found".to_owned()));
     }
-    let mut token_data = decode_token(token, token_type)?;
-    let id = DB::map_id(token_data.claims.sub, pool).await?;
+    let mut token_data = decode_token(token, token_type, leeway)?;
+    let id = cache.map_id::<DB>(token_data.claims.sub, pool).await?;
     token_data.claims.id = id;
     Ok(BazaarToken::from(token_data))
 }
 
+/// Returns the private ID of the known customer making the request, or the error
+/// that should be returned when there's no valid token or it belongs to an
+/// anonymous customer
+pub fn authorize_known(context: &GraphqlContext<'_>) -> Result<Uuid, BazaarError> {
+    let token = context.access_token()?;
+    known_customer_id(&token)
+}
+
+fn known_customer_id(token: &BazaarToken) -> Result<Uuid, BazaarError> {
+    match token.id {
+        Some(id) => Ok(id),
+        None if token.customer_type == CustomerType::Anonymous => Err(BazaarError::AnonymousError),
+        None => Err(BazaarError::Unauthorized),
+    }
+}
+
+/// Checks that the customer who holds this token is an admin, returning their
+/// private ID if they are
+#[tracing::instrument(skip(token, pool))]
+pub async fn verify_admin<C: CustomerRepository>(
+    token: &BazaarToken,
+    pool: &PgPool,
+) -> Result<Uuid, BazaarError> {
+    let id = known_customer_id(token)?;
+    if Customer::is_admin::<C>(id, pool).await? {
+        return Ok(id);
+    }
+    error!(?id, "customer attempted to access an admin only resource");
+    Err(BazaarError::Forbidden)
+}
+
 /// The `user_id` here should always be their public ID, so it should never be logged
 #[tracing::instrument(skip(user_id))]
 pub fn encode_token(
@@ -87,6 +172,7 @@ pub fn encode_token(
     };
 
     let claims = Claims {
+        ver: CLAIMS_VERSION,
         sub: user_id,
         customer_type,
         cart_id,
@@ -107,33 +193,96 @@ pub(crate) fn encode_jwt(claims: &Claims, token_type: TokenType) -> Result<Strin
     } else {
         REFRESH_TOKEN_PRIVATE_KEY.as_bytes()
     };
-    let encoding_key = EncodingKey::from_rsa_pem(key).map_err(|e| {
-        error!(err = ?e, "failed to parse the jwt encoding key");
-        BazaarError::UnexpectedError
-    })?;
-    encode(&headers, claims, &encoding_key).map_err(|e| {
-        error!(err= ?e, "failed to encode json web token");
-        BazaarError::UnexpectedError
-    })
+    let encoding_key = EncodingKey::from_rsa_pem(key)?;
+    Ok(encode(&headers, claims, &encoding_key)?)
 }
 
 #[tracing::instrument(skip(token))]
-pub fn decode_token(token: &str, token_type: TokenType) -> Result<TokenData<Claims>, BazaarError> {
+pub fn decode_token(
+    token: &str,
+    token_type: TokenType,
+    leeway: u64,
+) -> Result<TokenData<Claims>, BazaarError> {
     let key = if token_type == TokenType::Access {
         ACCESS_TOKEN_PUBLIC_KEY.as_bytes()
     } else {
         REFRESH_TOKEN_PUBLIC_KEY.as_bytes()
     };
-    let decoding_key = DecodingKey::from_rsa_pem(key).map_err(|e| {
-        error!(err= ?e, "failed to retrieve the decoding key");
-        BazaarError::UnexpectedError
-    })?;
-    let validation = Validation::new(Algorithm::PS256);
-    decode(token, &decoding_key, &validation).map_err(|e| {
-        error!(err= ?e, "failed to decode json web token");
-        // @TODO - Separate out errors and invalid tokens
-        BazaarError::InvalidToken("Token did not match what was expected".to_string())
-    })
+    let decoding_key = DecodingKey::from_rsa_pem(key)?;
+    let token_data = decode::<Claims>(token, &decoding_key, &validation(leeway))?;
+    // Tokens other than access tokens share a key, so one kind can't be used as another
+    if mem::discriminant(&token_data.claims.token_type) != mem::discriminant(&token_type) {
+        return Err(BazaarError::InvalidToken(
+            "Token did not match what was expected".to_string(),
+        ));
+    }
+    // Any fields missing from older tokens have already been defaulted by serde
+    if token_data.claims.ver != CLAIMS_VERSION {
+        debug!(
+            version = token_data.claims.ver,
+            current_version = CLAIMS_VERSION,
+            "decoded a token issued with a different claims version"
+        );
+    }
+    Ok(token_data)
+}
+
+/// Creates the token sent to a customer's new email address, the `public_id` is the
+/// only ID that should ever be put in it
+#[tracing::instrument(skip(public_id, email))]
+pub fn encode_email_change_token(public_id: Uuid, email: &str) -> Result<String, BazaarError> {
+    let iat = Utc::now();
+    let claims = EmailChangeClaims {
+        sub: public_id,
+        email: email.to_string(),
+        aud: EMAIL_CHANGE_AUDIENCE.to_string(),
+        exp: (iat + *EMAIL_CHANGE_TOKEN_DURATION).timestamp() as usize,
+        iat: iat.timestamp() as usize,
+    };
+    let encoding_key = EncodingKey::from_rsa_pem(ACCESS_TOKEN_PRIVATE_KEY.as_bytes())?;
+    Ok(encode(
+        &Header::new(Algorithm::PS256),
+        &claims,
+        &encoding_key,
+    )?)
+}
+
+#[tracing::instrument(skip(token))]
+pub fn decode_email_change_token(
+    token: &str,
+    leeway: u64,
+) -> Result<EmailChangeClaims, BazaarError> {
+    let decoding_key = DecodingKey::from_rsa_pem(ACCESS_TOKEN_PUBLIC_KEY.as_bytes())?;
+    let mut validation = validation(leeway);
+    validation.set_audience(&[EMAIL_CHANGE_AUDIENCE]);
+    Ok(decode::<EmailChangeClaims>(token, &decoding_key, &validation)?.claims)
+}
+
+/// Creates the token for a shared cart link, it only carries the cart's ID so gives no
+/// access to the customer who owns it
+#[tracing::instrument]
+pub fn encode_cart_share_token(cart_id: Uuid) -> Result<String, BazaarError> {
+    let iat = Utc::now();
+    let claims = Claims {
+        ver: CLAIMS_VERSION,
+        sub: None,
+        customer_type: CustomerType::Anonymous,
+        cart_id,
+        exp: (iat + *CART_SHARE_TOKEN_DURATION).timestamp() as usize,
+        iat: iat.timestamp() as usize,
+        count: None,
+        id: None,
+        token_type: TokenType::CartShare,
+    };
+    encode_jwt(&claims, TokenType::CartShare)
+}
+
+/// Returns the ID of the cart that was shared
+#[tracing::instrument(skip(token))]
+pub fn decode_cart_share_token(token: &str, leeway: u64) -> Result<Uuid, BazaarError> {
+    Ok(decode_token(token, TokenType::CartShare, leeway)?
+        .claims
+        .cart_id)
 }
 
 #[cfg(test)]
@@ -142,6 +291,7 @@ mod tests {
     use async_trait::async_trait;
     use chrono::Duration;
     use claim::{assert_err, assert_ok, assert_some};
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     use crate::{
         models::auth::AuthCustomer,
@@ -155,6 +305,7 @@ mod tests {
         let iat = Utc::now();
         let exp = iat + Duration::minutes(15);
         let claims = Claims {
+            ver: CLAIMS_VERSION,
             sub: Some(Uuid::new_v4()),
             customer_type: CustomerType::Known,
             cart_id: Uuid::new_v4(),
@@ -194,12 +345,39 @@ mod tests {
         set_token_env_vars_for_tests();
         let (token, claims) =
             create_valid_jwt_token(Uuid::new_v4(), Uuid::new_v4(), TokenType::Access);
-        let decoded_token = decode_token(&token, TokenType::Access);
+        let decoded_token = decode_token(&token, TokenType::Access, 0);
         assert_ok!(&decoded_token);
         let decoded_token = decoded_token.unwrap();
         assert_eq!(claims, decoded_token.claims);
     }
 
+    #[test]
+    fn decode_accepts_v1_claims_without_a_version() {
+        set_token_env_vars_for_tests();
+        let iat = Utc::now();
+        let exp = iat + Duration::minutes(15);
+        let sub = Uuid::new_v4();
+        let cart_id = Uuid::new_v4();
+        // Claims as they were issued before `ver` was added
+        let v1_claims = serde_json::json!({
+            "sub": sub,
+            "customer_type": CustomerType::Known,
+            "cart_id": cart_id,
+            "exp": exp.timestamp(),
+            "iat": iat.timestamp(),
+            "token_type": TokenType::Access,
+        });
+        let encoding_key = EncodingKey::from_rsa_pem(ACCESS_TOKEN_PRIVATE_KEY.as_bytes()).unwrap();
+        let token = encode(&Header::new(Algorithm::PS256), &v1_claims, &encoding_key).unwrap();
+
+        let decoded_token = decode_token(&token, TokenType::Access, 0)
+            .expect("v1 tokens should still decode")
+            .claims;
+        assert_eq!(decoded_token.ver, 1);
+        assert_eq!(decoded_token.sub, Some(sub));
+        assert_eq!(decoded_token.cart_id, cart_id);
+    }
+
     struct MockAuthRepo;
 
     #[async_trait]
@@ -211,6 +389,21 @@ mod tests {
         async fn get_auth_customer(_: &str, _: &PgPool) -> Result<AuthCustomer> {
             unimplemented!("Not used for these tests");
         }
+
+        async fn set_two_factor_secret(_: Uuid, _: Vec<u8>, _: &PgPool) -> Result<bool> {
+            unimplemented!("Not used for these tests");
+        }
+
+        async fn find_two_factor_secret(_: Uuid, _: &PgPool) -> Result<Option<Vec<u8>>> {
+            unimplemented!("Not used for these tests");
+        }
+
+        async fn enable_two_factor(_: Uuid, _: &PgPool) -> Result<()> {
+            unimplemented!("Not used for these tests");
+        }
+        async fn use_two_factor_step(_: Uuid, _: i64, _: &PgPool) -> Result<bool> {
+            unimplemented!("Not used for these tests");
+        }
     }
 
     #[tokio::test]
@@ -221,14 +414,81 @@ mod tests {
         let config = crate::get_configuration().expect("failed to read config");
         let pool = PgPool::connect_lazy(&config.database.raw_pg_url())
             .expect("failed to create fake connection");
-        let result = verify_and_deserialize_token::<MockAuthRepo>(&token, TokenType::Access, &pool)
-            .await
-            .expect("should successfully parse a valid token");
+        let result =
+            verify_and_deserialize_token::<MockAuthRepo>(&token, TokenType::Access, 0, &pool)
+                .await
+                .expect("should successfully parse a valid token");
         assert_some!(result.id);
         assert_eq!(claims.iat, result.iat);
         assert_eq!(claims.exp, result.exp);
     }
 
+    static MAP_ID_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct CountingAuthRepo;
+
+    #[async_trait]
+    impl AuthRepository for CountingAuthRepo {
+        async fn map_id(id: Option<Uuid>, _: &PgPool) -> Result<Option<Uuid>> {
+            MAP_ID_CALLS.fetch_add(1, Ordering::SeqCst);
+            Ok(id.map(|_| Uuid::nil()))
+        }
+
+        async fn get_auth_customer(_: &str, _: &PgPool) -> Result<AuthCustomer> {
+            unimplemented!("Not used for these tests");
+        }
+
+        async fn set_two_factor_secret(_: Uuid, _: Vec<u8>, _: &PgPool) -> Result<bool> {
+            unimplemented!("Not used for these tests");
+        }
+
+        async fn find_two_factor_secret(_: Uuid, _: &PgPool) -> Result<Option<Vec<u8>>> {
+            unimplemented!("Not used for these tests");
+        }
+
+        async fn enable_two_factor(_: Uuid, _: &PgPool) -> Result<()> {
+            unimplemented!("Not used for these tests");
+        }
+        async fn use_two_factor_step(_: Uuid, _: i64, _: &PgPool) -> Result<bool> {
+            unimplemented!("Not used for these tests");
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_sharing_a_subject_only_map_the_id_once() {
+        set_token_env_vars_for_tests();
+        let (public_id, cart_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let (access, _) = create_valid_jwt_token(public_id, cart_id, TokenType::Access);
+        let (refresh, _) = create_valid_jwt_token(public_id, cart_id, TokenType::Refresh(0));
+        let config = crate::get_configuration().expect("failed to read config");
+        let pool = PgPool::connect_lazy(&config.database.raw_pg_url())
+            .expect("failed to create fake connection");
+
+        let mut cache = IdMappingCache::default();
+        let access = verify_and_deserialize_token_with_cache::<CountingAuthRepo>(
+            &access,
+            TokenType::Access,
+            0,
+            &mut cache,
+            &pool,
+        )
+        .await
+        .expect("should successfully parse a valid access token");
+        let refresh = verify_and_deserialize_token_with_cache::<CountingAuthRepo>(
+            &refresh,
+            TokenType::Refresh(0),
+            0,
+            &mut cache,
+            &pool,
+        )
+        .await
+        .expect("should successfully parse a valid refresh token");
+
+        assert_eq!(MAP_ID_CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(access.id, Some(Uuid::nil()));
+        assert_eq!(refresh.id, Some(Uuid::nil()));
+    }
+
     #[tokio::test]
     async fn rejects_when_no_token_is_provided() {
         set_token_env_vars_for_tests();
@@ -237,7 +497,7 @@ mod tests {
         let pool = PgPool::connect_lazy(&config.database.raw_pg_url())
             .expect("failed to create fake connection");
         let result =
-            verify_and_deserialize_token::<MockAuthRepo>(&token, TokenType::Access, &pool).await;
+            verify_and_deserialize_token::<MockAuthRepo>(&token, TokenType::Access, 0, &pool).await;
 
         assert_err!(&result);
         let err = result.unwrap_err();
@@ -248,6 +508,154 @@ mod tests {
         );
     }
 
+    fn build_token(id: Option<Uuid>, customer_type: CustomerType) -> BazaarToken {
+        let iat = Utc::now();
+        let claims = Claims {
+            ver: CLAIMS_VERSION,
+            sub: id.map(|_| Uuid::new_v4()),
+            customer_type,
+            cart_id: Uuid::new_v4(),
+            exp: (iat + Duration::minutes(15)).timestamp() as usize,
+            iat: iat.timestamp() as usize,
+            count: None,
+            id,
+            token_type: TokenType::Access,
+        };
+        BazaarToken::from(TokenData {
+            header: Header::new(Algorithm::PS256),
+            claims,
+        })
+    }
+
+    fn fake_pool() -> PgPool {
+        let config = crate::get_configuration().expect("failed to read config");
+        PgPool::connect_lazy(&config.database.raw_pg_url())
+            .expect("failed to create fake connection")
+    }
+
+    #[tokio::test]
+    async fn authorize_known_rejects_a_missing_token() {
+        let pool = fake_pool();
+        let context = GraphqlContext::with_access_token(&pool, None);
+
+        let err = assert_err!(authorize_known(&context));
+        assert_eq!(err, BazaarError::InvalidToken("Not found".to_string()));
+    }
+
+    #[tokio::test]
+    async fn authorize_known_rejects_an_anonymous_token() {
+        let pool = fake_pool();
+        let token = build_token(None, CustomerType::Anonymous);
+        let context = GraphqlContext::with_access_token(&pool, Some(Ok(token)));
+
+        let err = assert_err!(authorize_known(&context));
+        assert_eq!(err, BazaarError::AnonymousError);
+    }
+
+    #[tokio::test]
+    async fn authorize_known_returns_the_private_id_of_a_known_customer() {
+        let pool = fake_pool();
+        let id = Uuid::new_v4();
+        let token = build_token(Some(id), CustomerType::Known);
+        let context = GraphqlContext::with_access_token(&pool, Some(Ok(token)));
+
+        assert_eq!(assert_ok!(authorize_known(&context)), id);
+    }
+
+    #[test]
+    fn email_change_tokens_round_trip() {
+        set_token_env_vars_for_tests();
+        let public_id = Uuid::new_v4();
+        let token = encode_email_change_token(public_id, "new@test.com").unwrap();
+
+        let claims = decode_email_change_token(&token, 0).expect("should decode a valid token");
+        assert_eq!(claims.sub, public_id);
+        assert_eq!(claims.email, "new@test.com");
+    }
+
+    #[test]
+    fn access_tokens_are_not_accepted_as_email_change_tokens() {
+        set_token_env_vars_for_tests();
+        let (token, _) = create_valid_jwt_token(Uuid::new_v4(), Uuid::new_v4(), TokenType::Access);
+        assert_err!(decode_email_change_token(&token, 0));
+    }
+
+    #[test]
+    fn cart_share_tokens_round_trip() {
+        set_token_env_vars_for_tests();
+        let cart_id = Uuid::new_v4();
+        let token = encode_cart_share_token(cart_id).unwrap();
+
+        assert_eq!(assert_ok!(decode_cart_share_token(&token, 0)), cart_id);
+    }
+
+    #[test]
+    fn cart_share_tokens_are_not_accepted_as_refresh_tokens() {
+        set_token_env_vars_for_tests();
+        let token = encode_cart_share_token(Uuid::new_v4()).unwrap();
+        assert_err!(decode_token(&token, TokenType::Refresh(0), 0));
+
+        let (refresh, _) =
+            create_valid_jwt_token(Uuid::new_v4(), Uuid::new_v4(), TokenType::Refresh(0));
+        assert_err!(decode_cart_share_token(&refresh, 0));
+    }
+
+    fn access_token_expired_for(expired_for: Duration) -> String {
+        let exp = Utc::now() - expired_for;
+        let iat = exp - Duration::minutes(15);
+        let claims = Claims {
+            ver: CLAIMS_VERSION,
+            sub: Some(Uuid::new_v4()),
+            customer_type: CustomerType::Known,
+            cart_id: Uuid::new_v4(),
+            exp: exp.timestamp() as usize,
+            iat: iat.timestamp() as usize,
+            count: None,
+            id: None,
+            token_type: TokenType::Access,
+        };
+        encode_jwt(&claims, TokenType::Access).unwrap()
+    }
+
+    #[test]
+    fn decode_accepts_a_token_expired_within_the_leeway() {
+        set_token_env_vars_for_tests();
+        let token = access_token_expired_for(Duration::seconds(10));
+        assert_ok!(decode_token(&token, TokenType::Access, 30));
+    }
+
+    #[test]
+    fn decode_rejects_a_token_expired_beyond_the_leeway() {
+        set_token_env_vars_for_tests();
+        let token = access_token_expired_for(Duration::seconds(90));
+        assert_eq!(
+            decode_token(&token, TokenType::Access, 30).unwrap_err(),
+            BazaarError::ExpiredToken
+        );
+    }
+
+    #[test]
+    fn decode_rejects_an_expired_token() {
+        set_token_env_vars_for_tests();
+        let iat = Utc::now() - Duration::minutes(30);
+        let exp = iat + Duration::minutes(15);
+        let claims = Claims {
+            ver: CLAIMS_VERSION,
+            sub: Some(Uuid::new_v4()),
+            customer_type: CustomerType::Known,
+            cart_id: Uuid::new_v4(),
+            exp: exp.timestamp() as usize,
+            iat: iat.timestamp() as usize,
+            count: None,
+            id: None,
+            token_type: TokenType::Access,
+        };
+        let token = encode_jwt(&claims, TokenType::Access).unwrap();
+        let result = decode_token(&token, TokenType::Access, 0);
+        assert_err!(&result);
+        assert_eq!(result.unwrap_err(), BazaarError::ExpiredToken);
+    }
+
     #[tokio::test]
     async fn rejects_an_invalid_token_token() {
         set_token_env_vars_for_tests();
@@ -256,7 +664,7 @@ mod tests {
         let pool = PgPool::connect_lazy(&config.database.raw_pg_url())
             .expect("failed to create fake connection");
         let result =
-            verify_and_deserialize_token::<MockAuthRepo>(&token, TokenType::Access, &pool).await;
+            verify_and_deserialize_token::<MockAuthRepo>(&token, TokenType::Access, 0, &pool).await;
         assert_err!(&result);
         let err = result.unwrap_err();
 
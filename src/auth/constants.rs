@@ -7,8 +7,15 @@ pub const TOKEN_TYPE: &str = "http-only cookie";
 
 lazy_static! {
     pub static ref TIME_TO_REFRESH: Duration = Duration::days(7);
+    /// How long the previous refresh token is still accepted after it has been rotated,
+    /// so concurrent refreshes from the same client don't invalidate each other
+    pub static ref REFRESH_TOKEN_GRACE_PERIOD: Duration = Duration::seconds(30);
     pub static ref ACCESS_TOKEN_DURATION: Duration =
         Duration::seconds(ACCESS_TOKEN_DURATION_SECONDS);
     pub static ref REFRESH_TOKEN_DURATION: Duration =
         Duration::seconds(REFRESH_TOKEN_DURATION_SECONDS);
+    /// How long a customer has to verify a new email address
+    pub static ref EMAIL_CHANGE_TOKEN_DURATION: Duration = Duration::hours(24);
+    /// How long a shared cart link can be viewed for
+    pub static ref CART_SHARE_TOKEN_DURATION: Duration = Duration::days(7);
 }
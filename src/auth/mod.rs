@@ -2,8 +2,16 @@ mod authenticate;
 pub(crate) mod authorize;
 mod constants;
 mod token;
+mod two_factor;
+mod validate;
 
 pub use authenticate::{hash_password, verify_password_and_fetch_details};
-pub use authorize::{decode_token, encode_token, verify_and_deserialize_token};
+pub use authorize::{
+    authorize_known, decode_cart_share_token, decode_email_change_token, decode_token,
+    encode_cart_share_token, encode_email_change_token, encode_token, verify_admin,
+    verify_and_deserialize_token, verify_and_deserialize_token_with_cache, IdMappingCache,
+};
 pub use constants::*;
 pub use token::*;
+pub use two_factor::*;
+pub use validate::validate_keys;
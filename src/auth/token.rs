@@ -6,7 +6,7 @@ use uuid::Uuid;
 use crate::{
     auth::{
         encode_token, ACCESS_TOKEN_DURATION_SECONDS, REFRESH_TOKEN_DURATION_SECONDS,
-        TIME_TO_REFRESH, TOKEN_TYPE,
+        REFRESH_TOKEN_GRACE_PERIOD, TIME_TO_REFRESH, TOKEN_TYPE,
     },
     database::{AuthRepository, CustomerRepository},
     models::{BazaarToken, BazaarTokens, Customer, CustomerType, TokenType},
@@ -35,6 +35,14 @@ pub async fn generate_new_tokens<C: CustomerRepository>(
         // In the case of anonymous refresh tokens
         1
     };
+    issue_tokens(public_id, cart_id, refresh_counter)
+}
+
+fn issue_tokens(
+    public_id: Option<Uuid>,
+    cart_id: Uuid,
+    refresh_counter: i32,
+) -> Result<BazaarTokens> {
     let access_token = encode_token(public_id, cart_id, TokenType::Access)?;
     let refresh_token = encode_token(public_id, cart_id, TokenType::Refresh(refresh_counter))?;
 
@@ -97,13 +105,35 @@ pub async fn refresh_tokens<A: AuthRepository, C: CustomerRepository>(
     }
 
     // Otherwise, also refresh the refresh token
-    generate_new_tokens::<C>(
+    let refresh_counter = match (refresh_token.id, refresh_token.count) {
+        (Some(id), Some(count)) => rotate_refresh_token_counter::<C>(id, count, pool).await?,
+        // In the case of anonymous refresh tokens
+        _ => 1,
+    };
+    issue_tokens(
         refresh_token.public_id(),
-        refresh_token.id,
         refresh_token.cart_id,
-        pool,
+        refresh_counter,
     )
-    .await
+}
+
+/// Rotates the customer's refresh token counter, leaving the previous count valid for a
+/// short grace period. If a concurrent refresh has already rotated it, the counter it
+/// rotated to is re-used rather than rotating again and invalidating that request's token
+async fn rotate_refresh_token_counter<C: CustomerRepository>(
+    id: Uuid,
+    count: i32,
+    pool: &PgPool,
+) -> Result<i32> {
+    let grace_until = Utc::now() + *REFRESH_TOKEN_GRACE_PERIOD;
+    if let Some(counter) =
+        Customer::rotate_refresh_token_counter::<C>(id, count, grace_until, pool).await?
+    {
+        return Ok(counter);
+    }
+    // The counter may also have moved because the tokens were invalidated, so check again
+    check_refresh_token_is_not_invalidated::<C>(Some(id), Some(count), pool).await?;
+    Customer::fetch_refresh_token_counter::<C>(id, pool).await
 }
 
 async fn check_refresh_token_is_not_invalidated<C: CustomerRepository>(
@@ -112,8 +142,11 @@ async fn check_refresh_token_is_not_invalidated<C: CustomerRepository>(
     pool: &PgPool,
 ) -> Result<()> {
     if let Some(id) = private_id {
-        let current_refresh_counter = Customer::fetch_refresh_token_counter::<C>(id, pool).await?;
-        if Some(current_refresh_counter) != count {
+        let is_valid = match count {
+            Some(count) => Customer::is_refresh_token_count_valid::<C>(id, count, pool).await?,
+            None => false,
+        };
+        if !is_valid {
             return Err(BazaarError::InvalidToken(
                 "Token has been invalidated".to_owned(),
             ));
@@ -0,0 +1,262 @@
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    constant_time, hkdf, hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use tracing::error;
+
+use crate::{auth::authenticate::SECRET_KEY, models::auth::AuthCustomer, BazaarError, Result};
+
+const ISSUER: &str = "Bazaar";
+const SECRET_LENGTH: usize = 20;
+const STEP_SECONDS: i64 = 30;
+const DIGITS: usize = 6;
+/// Codes from the neighbouring time steps are accepted to allow for clock drift
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const ENCRYPTION_KEY_INFO: &[&[u8]] = &[b"secret-encryption"];
+
+lazy_static! {
+    /// Two factor secrets are stored encrypted with a key derived from the `SECRET_KEY`
+    static ref ENCRYPTION_KEY: LessSafeKey = {
+        let salt = hkdf::Salt::new(hkdf::HKDF_SHA256, b"bazaar-two-factor");
+        LessSafeKey::new(UnboundKey::from(
+            salt.extract(SECRET_KEY.as_bytes())
+                .expand(ENCRYPTION_KEY_INFO, &AES_256_GCM)
+                .expect("two factor encryption key should be a valid length"),
+        ))
+    };
+}
+
+/// Generates a new secret for a customer's authenticator app
+pub fn generate_two_factor_secret() -> Result<Vec<u8>> {
+    let mut secret = vec![0u8; SECRET_LENGTH];
+    SystemRandom::new().fill(&mut secret).map_err(|err| {
+        error!(?err, "failed to generate two factor secret");
+        BazaarError::UnexpectedError
+    })?;
+    Ok(secret)
+}
+
+/// The nonce is prepended to the encrypted secret
+pub fn encrypt_two_factor_secret(secret: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|err| {
+        error!(?err, "failed to generate two factor nonce");
+        BazaarError::UnexpectedError
+    })?;
+    let mut sealed = secret.to_vec();
+    ENCRYPTION_KEY
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut sealed,
+        )
+        .map_err(|err| {
+            error!(?err, "failed to encrypt two factor secret");
+            BazaarError::UnexpectedError
+        })?;
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend(sealed);
+    Ok(encrypted)
+}
+
+pub fn decrypt_two_factor_secret(encrypted: &[u8]) -> Result<Vec<u8>> {
+    if encrypted.len() < NONCE_LEN {
+        error!("stored two factor secret is too short");
+        return Err(BazaarError::UnexpectedError);
+    }
+    let (nonce, sealed) = encrypted.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|err| {
+        error!(?err, "stored two factor nonce is invalid");
+        BazaarError::UnexpectedError
+    })?;
+    let mut sealed = sealed.to_vec();
+    let secret = ENCRYPTION_KEY
+        .open_in_place(nonce, Aad::empty(), &mut sealed)
+        .map_err(|err| {
+            error!(?err, "failed to decrypt two factor secret");
+            BazaarError::UnexpectedError
+        })?;
+    Ok(secret.to_vec())
+}
+
+/// The secret as it's entered into an authenticator app
+pub fn encode_two_factor_secret(secret: &[u8]) -> String {
+    let mut encoded = String::with_capacity((secret.len() * 8 + 4) / 5);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in secret {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// The `otpauth://` URI authenticator apps can be set up with (ie. via a QR code)
+pub fn two_factor_provisioning_uri(secret: &[u8], email: &str) -> String {
+    let label: String = url::form_urlencoded::byte_serialize(email.as_bytes()).collect();
+    format!(
+        "otpauth://totp/{issuer}:{label}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = ISSUER,
+        label = label,
+        secret = encode_two_factor_secret(secret),
+        digits = DIGITS,
+        period = STEP_SECONDS
+    )
+}
+
+/// The TOTP code (RFC 6238) for the time step containing `at`
+pub fn two_factor_code(secret: &[u8], at: DateTime<Utc>) -> String {
+    hotp(secret, (at.timestamp() / STEP_SECONDS) as u64)
+}
+
+/// The time step `code` was generated in, if it's valid at `at`. The step needs to be
+/// recorded once the code is accepted so the code can't be used again
+pub fn matching_two_factor_step(secret: &[u8], code: &str, at: DateTime<Utc>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let step = at.timestamp() / STEP_SECONDS;
+    (step - ALLOWED_DRIFT_STEPS..=step + ALLOWED_DRIFT_STEPS).find(|step| {
+        let expected = hotp(secret, *step as u64);
+        constant_time::verify_slices_are_equal(expected.as_bytes(), code.as_bytes()).is_ok()
+    })
+}
+
+/// Customers with two factor enabled need to provide a valid code alongside their
+/// password, those without it don't need to provide anything. Returns the time step
+/// of the accepted code
+pub fn verify_second_factor(customer: &AuthCustomer, code: Option<&str>) -> Result<Option<i64>> {
+    let encrypted = match (&customer.two_factor_secret, customer.two_factor_enabled) {
+        (Some(encrypted), true) => encrypted,
+        _ => return Ok(None),
+    };
+    let code = code.ok_or(BazaarError::TwoFactorRequired)?;
+    let secret = decrypt_two_factor_secret(encrypted)?;
+    matching_two_factor_step(&secret, code, Utc::now())
+        .map(Some)
+        .ok_or(BazaarError::IncorrectCredentials)
+}
+
+/// HOTP (RFC 4226) truncated to `DIGITS` digits
+fn hotp(secret: &[u8], counter: u64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let hash = tag.as_ref();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(DIGITS as u32),
+        width = DIGITS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // The SHA1 secret from the RFC 6238 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn customer(two_factor_secret: Option<Vec<u8>>, two_factor_enabled: bool) -> AuthCustomer {
+        AuthCustomer {
+            public_id: uuid::Uuid::new_v4(),
+            id: uuid::Uuid::new_v4(),
+            hashed_password: String::new(),
+            two_factor_secret,
+            two_factor_enabled,
+        }
+    }
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        // The RFC vectors are 8 digits, these are their last 6
+        assert_eq!(two_factor_code(RFC_SECRET, Utc.timestamp(59, 0)), "287082");
+        assert_eq!(
+            two_factor_code(RFC_SECRET, Utc.timestamp(1_111_111_109, 0)),
+            "081804"
+        );
+        assert_eq!(
+            two_factor_code(RFC_SECRET, Utc.timestamp(1_234_567_890, 0)),
+            "005924"
+        );
+    }
+
+    #[test]
+    fn codes_from_neighbouring_steps_are_accepted() {
+        let now = Utc.timestamp(1_111_111_109, 0);
+        let previous = two_factor_code(RFC_SECRET, now - chrono::Duration::seconds(30));
+        let stale = two_factor_code(RFC_SECRET, now - chrono::Duration::seconds(90));
+
+        assert_eq!(
+            matching_two_factor_step(RFC_SECRET, "081804", now),
+            Some(37_037_036)
+        );
+        assert_eq!(
+            matching_two_factor_step(RFC_SECRET, &previous, now),
+            Some(37_037_035)
+        );
+        assert_eq!(matching_two_factor_step(RFC_SECRET, &stale, now), None);
+        assert_eq!(matching_two_factor_step(RFC_SECRET, "81804", now), None);
+    }
+
+    #[test]
+    fn secrets_are_base32_encoded() {
+        assert_eq!(
+            encode_two_factor_secret(RFC_SECRET),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+        assert_eq!(encode_two_factor_secret(b"f"), "MY");
+    }
+
+    #[test]
+    fn encrypted_secrets_round_trip() {
+        std::env::set_var("SECRET_KEY", "TEST KEY");
+        let secret = generate_two_factor_secret().unwrap();
+        let encrypted = encrypt_two_factor_secret(&secret).unwrap();
+
+        assert_ne!(&encrypted[NONCE_LEN..], &secret[..]);
+        assert_eq!(decrypt_two_factor_secret(&encrypted).unwrap(), secret);
+    }
+
+    #[test]
+    fn a_code_is_only_required_once_two_factor_is_enabled() {
+        std::env::set_var("SECRET_KEY", "TEST KEY");
+        let encrypted = encrypt_two_factor_secret(RFC_SECRET).unwrap();
+
+        assert_eq!(verify_second_factor(&customer(None, false), None), Ok(None));
+        assert_eq!(
+            verify_second_factor(&customer(Some(encrypted.clone()), false), None),
+            Ok(None)
+        );
+        assert_eq!(
+            verify_second_factor(&customer(Some(encrypted.clone()), true), None),
+            Err(BazaarError::TwoFactorRequired)
+        );
+
+        let now = Utc::now();
+        let code = two_factor_code(RFC_SECRET, now);
+        let enabled = customer(Some(encrypted), true);
+        assert_eq!(
+            verify_second_factor(&enabled, Some(&code)),
+            Ok(Some(now.timestamp() / STEP_SECONDS))
+        );
+    }
+}
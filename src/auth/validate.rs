@@ -0,0 +1,96 @@
+use jsonwebtoken::{DecodingKey, EncodingKey};
+use std::env::var;
+use tracing::error;
+
+use crate::{BazaarError, Result};
+
+/// Verifies that all of the keys used for hashing passwords and signing tokens
+/// are present and usable.
+///
+/// This should be called on start up, so that a missing or weak key fails fast
+/// instead of panicking the first time one of the `lazy_static` keys is accessed
+pub fn validate_keys(min_secret_key_length: usize) -> Result<()> {
+    validate_secret_key(&read_key("SECRET_KEY")?, min_secret_key_length)?;
+    for name in &["ACCESS_TOKEN_PRIVATE_KEY", "REFRESH_TOKEN_PRIVATE_KEY"] {
+        validate_private_key(name, &read_key(name)?)?;
+    }
+    for name in &["ACCESS_TOKEN_PUBLIC_KEY", "REFRESH_TOKEN_PUBLIC_KEY"] {
+        validate_public_key(name, &read_key(name)?)?;
+    }
+    Ok(())
+}
+
+fn read_key(name: &str) -> Result<String> {
+    var(name).map_err(|e| {
+        error!(err = ?e, key = name, "failed to retrieve key");
+        BazaarError::ConfigError(format!("{} was not provided", name))
+    })
+}
+
+fn validate_secret_key(key: &str, min_length: usize) -> Result<()> {
+    if key.len() < min_length {
+        error!(
+            key_length = key.len(),
+            min_length, "secret key is shorter than the minimum length"
+        );
+        return Err(BazaarError::ConfigError(format!(
+            "SECRET_KEY must be at least {} characters long",
+            min_length
+        )));
+    }
+    Ok(())
+}
+
+fn validate_private_key(name: &str, key: &str) -> Result<()> {
+    EncodingKey::from_rsa_pem(key.as_bytes()).map_err(|e| {
+        error!(err = ?e, key = name, "failed to parse private key");
+        BazaarError::ConfigError(format!("{} is not a valid RSA private key", name))
+    })?;
+    Ok(())
+}
+
+fn validate_public_key(name: &str, key: &str) -> Result<()> {
+    DecodingKey::from_rsa_pem(key.as_bytes()).map_err(|e| {
+        error!(err = ?e, key = name, "failed to parse public key");
+        BazaarError::ConfigError(format!("{} is not a valid RSA public key", name))
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn rejects_a_secret_key_that_is_too_short() {
+        let result = validate_secret_key("TEST KEY", 32);
+        assert_err!(&result);
+        assert_eq!(
+            result.unwrap_err(),
+            BazaarError::ConfigError("SECRET_KEY must be at least 32 characters long".to_string())
+        );
+    }
+
+    #[test]
+    fn accepts_a_secret_key_of_the_minimum_length() {
+        assert_ok!(validate_secret_key("KbPeShVmYq3t6w9y$B&E)H@McQfTjWnZ", 32));
+    }
+
+    #[test]
+    fn rejects_a_malformed_private_key() {
+        let result = validate_private_key("ACCESS_TOKEN_PRIVATE_KEY", "not a key");
+        assert_err!(&result);
+        assert_eq!(
+            result.unwrap_err(),
+            BazaarError::ConfigError(
+                "ACCESS_TOKEN_PRIVATE_KEY is not a valid RSA private key".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_a_malformed_public_key() {
+        assert_err!(validate_public_key("ACCESS_TOKEN_PUBLIC_KEY", ""));
+    }
+}
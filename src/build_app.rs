@@ -10,13 +10,21 @@ use async_graphql::{EmptySubscription, Schema};
 use async_graphql_telemetry_extension::OpenTelemetryExtension;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::sync::Arc;
+use tracing::error;
 
 use crate::{
-    auth::REFRESH_TOKEN_DURATION_SECONDS, routes::*, AppConfig, BazaarSchema, MutationRoot,
-    QueryRoot,
+    auth::REFRESH_TOKEN_DURATION_SECONDS, integrations::email::EmailSender, routes::*, AppConfig,
+    AppMetrics, BazaarError, BazaarSchema, MutationRoot, QueryRoot, Result,
+    SHUTDOWN_TIMEOUT_SECONDS,
 };
 
-pub fn generate_schema(connection: Option<PgPool>, config: Option<AppConfig>) -> BazaarSchema {
+pub fn generate_schema(
+    connection: Option<PgPool>,
+    config: Option<AppConfig>,
+    metrics: Option<AppMetrics>,
+    email_sender: Option<Arc<dyn EmailSender>>,
+) -> BazaarSchema {
     let mut schema =
         Schema::build(QueryRoot, MutationRoot, EmptySubscription).extension(OpenTelemetryExtension);
     if let Some(connection) = connection {
@@ -25,6 +33,12 @@ pub fn generate_schema(connection: Option<PgPool>, config: Option<AppConfig>) ->
     if let Some(config) = config {
         schema = schema.data(config);
     }
+    if let Some(metrics) = metrics {
+        schema = schema.data(metrics);
+    }
+    if let Some(email_sender) = email_sender {
+        schema = schema.data(email_sender);
+    }
     schema.finish()
 }
 
@@ -32,10 +46,22 @@ pub fn build_app(
     listener: TcpListener,
     connection: PgPool,
     configuration: AppConfig,
-) -> Result<Server, Box<dyn std::error::Error + Send + Sync>> {
-    let schema = generate_schema(Some(connection.clone()), Some(configuration.clone()));
+    email_sender: Arc<dyn EmailSender>,
+) -> Result<Server> {
+    let metrics = AppMetrics::new().map_err(|e| {
+        error!(err = ?e, "failed to register metrics");
+        BazaarError::ServerError(e.to_string())
+    })?;
+    let schema = generate_schema(
+        Some(connection.clone()),
+        Some(configuration.clone()),
+        Some(metrics.clone()),
+        Some(email_sender),
+    );
+    let allow_list = OperationAllowList::from_settings(&configuration.graphql)?;
+    let options = configuration.application.server_options();
 
-    let server = HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
             .wrap(RequestTracing::new())
             .wrap(
@@ -51,15 +77,35 @@ pub fn build_app(
             .data(schema.clone())
             .data(connection.clone())
             .data(configuration.clone())
+            .data(allow_list.clone())
+            .data(metrics.clone())
             .service(web::resource("/").guard(guard::Post()).to(graphql_index))
             .service(
                 web::resource("/")
                     .guard(guard::Get())
                     .to(graphql_playground),
             )
+            .service(
+                web::resource("/metrics")
+                    .guard(guard::Get())
+                    .to(prometheus_metrics),
+            )
+            .service(web::resource("/ready").guard(guard::Get()).to(readiness))
     })
-    .listen(listener)?
-    .run();
+    // Signals are handled by `shutdown_on_signal` so the rest of the application
+    // can be cleaned up once the server has stopped
+    .disable_signals()
+    .shutdown_timeout(SHUTDOWN_TIMEOUT_SECONDS);
+
+    if let Some(workers) = options.workers {
+        server = server.workers(workers);
+    }
+    if let Some(keep_alive) = options.keep_alive {
+        server = server.keep_alive(keep_alive);
+    }
+    if let Some(client_timeout) = options.client_timeout {
+        server = server.client_timeout(client_timeout);
+    }
 
-    Ok(server)
+    Ok(server.listen(listener)?.run())
 }
@@ -1,15 +1,67 @@
 use config::{Config, File};
+use opentelemetry::sdk::trace::Sampler;
 use serde::Deserialize;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use std::convert::{TryFrom, TryInto};
 use std::env::{set_var, var};
 use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    models::{Currency, ShippingMethod},
+    BazaarError,
+};
+
+/// Numeric settings, these can be overridden via environment variables which are always
+/// strings, so are validated up front to give a clear error
+const NUMERIC_KEYS: [&str; 22] = [
+    "application.port",
+    "application.workers",
+    "application.keep_alive",
+    "application.client_timeout",
+    "auth.min_secret_key_length",
+    "auth.token_leeway_seconds",
+    "cart.max_distinct_items",
+    "database.port",
+    "database.max_connections",
+    "email.smtp_port",
+    "shipping.standard.min_business_days",
+    "shipping.standard.max_business_days",
+    "shipping.standard.price.gbp",
+    "shipping.standard.price.usd",
+    "shipping.standard.max_weight_grams",
+    "shipping.express.min_business_days",
+    "shipping.express.max_business_days",
+    "shipping.express.price.gbp",
+    "shipping.express.price.usd",
+    "shipping.express.max_weight_grams",
+    "telemetry.port",
+    "telemetry.sampler.ratio",
+];
+
+/// Secrets which are read straight from the environment by the auth module, rather
+/// than through the configuration
+const SECRET_ENV_VARS: [&str; 5] = [
+    "SECRET_KEY",
+    "ACCESS_TOKEN_PRIVATE_KEY",
+    "ACCESS_TOKEN_PUBLIC_KEY",
+    "REFRESH_TOKEN_PRIVATE_KEY",
+    "REFRESH_TOKEN_PUBLIC_KEY",
+];
 
 #[derive(Deserialize)]
 pub struct Configuration {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
+    pub auth: AuthSettings,
+    pub cart: CartSettings,
+    pub email: EmailSettings,
+    pub graphql: GraphqlSettings,
+    pub shipping: ShippingSettings,
     telemetry: TelemetrySettings,
     pub env: Environment,
 }
@@ -19,6 +71,100 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    /// The `Domain` set on the auth cookies, so they're shared across subdomains.
+    /// Ignored in local and test environments
+    pub cookie_domain: Option<String>,
+    /// The number of worker threads, defaults to the number of logical CPUs
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub workers: Option<usize>,
+    /// How long (in seconds) an idle connection is kept open for
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub keep_alive: Option<usize>,
+    /// How long (in milliseconds) a client has to send its request headers
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub client_timeout: Option<u64>,
+}
+
+/// Tuning for the HTTP server, anything that isn't set uses actix's defaults
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ServerOptions {
+    pub workers: Option<usize>,
+    pub keep_alive: Option<usize>,
+    pub client_timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct AuthSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_secret_key_length: usize,
+    /// How far past their expiry tokens are still accepted, to tolerate clock skew
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub token_leeway_seconds: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CartSettings {
+    /// The maximum number of distinct items (skus) a cart can hold
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_distinct_items: usize,
+    /// When enabled, adding or setting skus that don't exist is rejected rather than
+    /// the sku being kept in the cart without a price
+    pub strict_skus: bool,
+}
+
+#[derive(Deserialize)]
+pub struct EmailSettings {
+    /// When disabled emails are only logged rather than sent, ie. when running locally
+    pub enabled: bool,
+    pub smtp_host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub smtp_port: u16,
+    pub smtp_username: String,
+    pub smtp_password: String,
+    /// ie. `Bazaar <noreply@bazaar.com>`
+    pub from_address: String,
+}
+
+#[derive(Deserialize)]
+pub struct GraphqlSettings {
+    /// When enabled only the operations listed in `allow_list_path` can be executed
+    pub allow_list_enabled: bool,
+    pub allow_list_path: Option<String>,
+}
+
+/// How long each shipping method takes to deliver and what it costs
+#[derive(Deserialize)]
+pub struct ShippingSettings {
+    pub standard: ShippingMethodSettings,
+    pub express: ShippingMethodSettings,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq)]
+pub struct ShippingMethodSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_business_days: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_business_days: u32,
+    pub price: ShippingPrices,
+    /// Carts heavier than this can't be sent with the method, there's no limit if unset
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_weight_grams: Option<u32>,
+}
+
+/// What a shipping method costs in each currency a cart can be in
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq)]
+pub struct ShippingPrices {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub gbp: f64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub usd: f64,
+}
+
+/// The number of business days, after today, an order is expected to arrive within
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LeadTime {
+    pub min_business_days: u32,
+    pub max_business_days: u32,
 }
 
 #[derive(Deserialize)]
@@ -30,6 +176,8 @@ pub struct DatabaseSettings {
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
 }
 
 #[derive(Deserialize)]
@@ -37,6 +185,21 @@ pub struct TelemetrySettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     port: u16,
     host: String,
+    sampler: SamplerSettings,
+}
+
+/// Which traces get exported to the telemetry agent
+#[derive(Debug, Deserialize, Copy, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SamplerSettings {
+    AlwaysOn,
+    AlwaysOff,
+    /// Follows the sampling decision of the parent span if there is one, otherwise
+    /// samples the given ratio (between `0.0` and `1.0`) of traces
+    ParentBasedRatio {
+        #[serde(deserialize_with = "deserialize_number_from_string")]
+        ratio: f64,
+    },
 }
 
 #[derive(Debug, Deserialize, Copy, Clone)]
@@ -48,7 +211,7 @@ pub enum Environment {
     Production,
 }
 
-pub fn get_configuration() -> Result<Configuration, config::ConfigError> {
+pub fn get_configuration() -> Result<Configuration, BazaarError> {
     let mut settings = Config::default();
     let base_path = std::env::current_dir().expect("failed to determine current directory");
     let configuration_directory = base_path.join("configuration");
@@ -68,7 +231,64 @@ pub fn get_configuration() -> Result<Configuration, config::ConfigError> {
 
     settings.merge(config::Environment::with_prefix("app").separator("__"))?;
 
-    settings.try_into()
+    if let Ok(secrets_directory) = var("SECRETS_DIR") {
+        merge_secrets_directory(&mut settings, Path::new(&secrets_directory))?;
+    }
+
+    validate_numeric_settings(&settings)?;
+    Ok(settings.try_into()?)
+}
+
+/// Reads secrets from a mounted directory (e.g. docker or kubernetes secrets), these take
+/// precedence over both the environment and the configuration files.
+///
+/// Each file holds a single secret, named either after one of the `SECRET_ENV_VARS` or
+/// after the configuration key it overrides, using `__` as the separator
+/// (e.g. `database__password`)
+fn merge_secrets_directory(settings: &mut Config, directory: &Path) -> Result<(), BazaarError> {
+    let entries = fs::read_dir(directory).map_err(|e| {
+        BazaarError::ConfigError(format!(
+            "failed to read secrets directory {}: {}",
+            directory.display(),
+            e
+        ))
+    })?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| BazaarError::ConfigError(format!("failed to read secret: {}", e)))?
+            .path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            // Mounted secrets are often accompanied by hidden bookkeeping files
+            Some(name) if path.is_file() && !name.starts_with('.') => name.to_string(),
+            _ => continue,
+        };
+        let value = fs::read_to_string(&path).map_err(|e| {
+            BazaarError::ConfigError(format!("failed to read secret {}: {}", name, e))
+        })?;
+        let value = value.trim_end();
+
+        if SECRET_ENV_VARS.contains(&name.as_str()) {
+            set_var(&name, value);
+        } else {
+            settings.set(&name.to_lowercase().replace("__", "."), value)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_numeric_settings(settings: &Config) -> Result<(), BazaarError> {
+    for key in NUMERIC_KEYS.iter() {
+        // Missing keys are reported when the configuration is deserialized
+        if let Ok(value) = settings.get_str(key) {
+            if value.trim().parse::<f64>().is_err() {
+                return Err(BazaarError::ConfigError(format!(
+                    "{} must be a number, got '{}'",
+                    key, value
+                )));
+            }
+        }
+    }
+    Ok(())
 }
 
 impl Configuration {
@@ -83,6 +303,63 @@ impl Configuration {
     pub fn get_telemetry_agent_endpoint(&self) -> String {
         format!("https://{}:{}", self.telemetry.host, self.telemetry.port)
     }
+
+    pub fn get_telemetry_sampler(&self) -> Sampler {
+        self.telemetry.sampler.into()
+    }
+}
+
+impl From<SamplerSettings> for Sampler {
+    fn from(settings: SamplerSettings) -> Self {
+        match settings {
+            SamplerSettings::AlwaysOn => Sampler::AlwaysOn,
+            SamplerSettings::AlwaysOff => Sampler::AlwaysOff,
+            SamplerSettings::ParentBasedRatio { ratio } => {
+                Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio)))
+            }
+        }
+    }
+}
+
+impl ShippingSettings {
+    pub fn method(&self, method: ShippingMethod) -> ShippingMethodSettings {
+        match method {
+            ShippingMethod::Standard => self.standard,
+            ShippingMethod::Express => self.express,
+        }
+    }
+
+    pub fn lead_time(&self, method: ShippingMethod) -> LeadTime {
+        self.method(method).lead_time()
+    }
+}
+
+impl ShippingMethodSettings {
+    pub fn lead_time(&self) -> LeadTime {
+        LeadTime {
+            min_business_days: self.min_business_days,
+            max_business_days: self.max_business_days,
+        }
+    }
+}
+
+impl ShippingPrices {
+    pub fn in_currency(&self, currency: Currency) -> f64 {
+        match currency {
+            Currency::GBP => self.gbp,
+            Currency::USD => self.usd,
+        }
+    }
+}
+
+impl ApplicationSettings {
+    pub fn server_options(&self) -> ServerOptions {
+        ServerOptions {
+            workers: self.workers,
+            keep_alive: self.keep_alive,
+            client_timeout: self.client_timeout,
+        }
+    }
 }
 
 impl DatabaseSettings {
@@ -149,3 +426,156 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::FileFormat;
+
+    fn parse_sampler(yaml: &str) -> SamplerSettings {
+        let mut settings = Config::default();
+        settings
+            .merge(File::from_str(yaml, FileFormat::Yaml))
+            .expect("sampler yaml should be valid");
+        settings
+            .try_into()
+            .expect("should deserialize sampler settings")
+    }
+
+    #[test]
+    fn non_numeric_settings_are_rejected_with_the_offending_key() {
+        let mut settings = Config::default();
+        settings
+            .merge(File::from_str(
+                "application:\n  port: abc\ndatabase:\n  port: 5432",
+                FileFormat::Yaml,
+            ))
+            .unwrap();
+
+        let err = validate_numeric_settings(&settings).unwrap_err();
+        assert_eq!(
+            err,
+            BazaarError::ConfigError("application.port must be a number, got 'abc'".to_string())
+        );
+    }
+
+    #[test]
+    fn numeric_settings_provided_as_strings_are_accepted() {
+        let mut settings = Config::default();
+        settings
+            .merge(File::from_str(
+                "application:\n  port: \"8000\"\ntelemetry:\n  sampler:\n    ratio: \"0.1\"",
+                FileFormat::Yaml,
+            ))
+            .unwrap();
+
+        assert!(validate_numeric_settings(&settings).is_ok());
+    }
+
+    #[test]
+    fn secrets_from_the_environment_override_the_configuration_files() {
+        let mut settings = Config::default();
+        settings
+            .merge(File::from_str(
+                "database:\n  password: from_file\n  username: postgres",
+                FileFormat::Yaml,
+            ))
+            .unwrap();
+
+        set_var("APP_OVERRIDE_TEST_DATABASE__PASSWORD", "from_env");
+        settings
+            .merge(config::Environment::with_prefix("app_override_test").separator("__"))
+            .unwrap();
+
+        assert_eq!(settings.get_str("database.password").unwrap(), "from_env");
+        assert_eq!(settings.get_str("database.username").unwrap(), "postgres");
+    }
+
+    #[test]
+    fn secrets_directory_overrides_the_environment() {
+        let mut settings = Config::default();
+        settings
+            .merge(File::from_str(
+                "database:\n  password: from_file",
+                FileFormat::Yaml,
+            ))
+            .unwrap();
+        set_var("APP_DIRECTORY_TEST_DATABASE__PASSWORD", "from_env");
+        settings
+            .merge(config::Environment::with_prefix("app_directory_test").separator("__"))
+            .unwrap();
+
+        let directory =
+            std::env::temp_dir().join(format!("bazaar-secrets-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("database__password"), "from_secret\n").unwrap();
+
+        merge_secrets_directory(&mut settings, &directory).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            settings.get_str("database.password").unwrap(),
+            "from_secret"
+        );
+    }
+
+    fn parse_application(yaml: &str) -> ApplicationSettings {
+        let mut settings = Config::default();
+        settings
+            .merge(File::from_str(yaml, FileFormat::Yaml))
+            .expect("application yaml should be valid");
+        settings
+            .try_into()
+            .expect("should deserialize application settings")
+    }
+
+    #[test]
+    fn server_options_are_taken_from_the_application_settings() {
+        let application = parse_application(
+            "port: 8000\nhost: 0.0.0.0\nworkers: \"4\"\nkeep_alive: 75\nclient_timeout: 5000",
+        );
+        assert_eq!(
+            application.server_options(),
+            ServerOptions {
+                workers: Some(4),
+                keep_alive: Some(75),
+                client_timeout: Some(5000),
+            }
+        );
+    }
+
+    #[test]
+    fn server_options_default_to_unset() {
+        let application = parse_application("port: 8000\nhost: 0.0.0.0");
+        assert_eq!(application.server_options(), ServerOptions::default());
+    }
+
+    #[test]
+    fn always_on_sampler_is_parsed() {
+        let sampler = parse_sampler("kind: always_on");
+        assert_eq!(sampler, SamplerSettings::AlwaysOn);
+        assert!(matches!(Sampler::from(sampler), Sampler::AlwaysOn));
+    }
+
+    #[test]
+    fn always_off_sampler_is_parsed() {
+        let sampler = parse_sampler("kind: always_off");
+        assert_eq!(sampler, SamplerSettings::AlwaysOff);
+        assert!(matches!(Sampler::from(sampler), Sampler::AlwaysOff));
+    }
+
+    #[test]
+    fn parent_based_ratio_sampler_is_parsed() {
+        // Environment variable overrides are always strings
+        let sampler = parse_sampler("kind: parent_based_ratio\nratio: \"0.1\"");
+        assert_eq!(sampler, SamplerSettings::ParentBasedRatio { ratio: 0.1 });
+
+        match Sampler::from(sampler) {
+            Sampler::ParentBased(root) => match *root {
+                Sampler::TraceIdRatioBased(ratio) => assert!((ratio - 0.1).abs() < f64::EPSILON),
+                other => panic!("expected a ratio based root sampler, got {:?}", other),
+            },
+            other => panic!("expected a parent based sampler, got {:?}", other),
+        }
+    }
+}
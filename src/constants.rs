@@ -1 +1,3 @@
 pub const DOMAIN_URL: &str = "localhost:8000";
+/// The version of the terms and privacy policy customers consent to when signing up
+pub const POLICY_VERSION: &str = "2021-01-20";
@@ -0,0 +1,71 @@
+use async_trait::async_trait;
+use sqlx::{query, query_as, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        account_event::{AccountEventKind, RequestOrigin},
+        AccountEvent,
+    },
+    Result,
+};
+
+#[async_trait]
+pub trait AccountEventRepository {
+    async fn create(
+        id: Uuid,
+        customer_id: Uuid,
+        kind: AccountEventKind,
+        origin: &RequestOrigin,
+        pool: &PgPool,
+    ) -> Result<()>;
+    async fn find_by_customer_id(customer_id: Uuid, pool: &PgPool) -> Result<Vec<AccountEvent>>;
+}
+
+pub struct AccountEventDatabase;
+
+#[async_trait]
+impl AccountEventRepository for AccountEventDatabase {
+    #[tracing::instrument(skip(origin, pool), fields(repository = "account_event"))]
+    async fn create(
+        id: Uuid,
+        customer_id: Uuid,
+        kind: AccountEventKind,
+        origin: &RequestOrigin,
+        pool: &PgPool,
+    ) -> Result<()> {
+        query!(
+            r#"
+            INSERT INTO account_events (id, customer_id, kind, ip_address, user_agent)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            id,
+            customer_id,
+            kind as AccountEventKind,
+            origin.ip_address,
+            origin.user_agent
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "account_event"))]
+    async fn find_by_customer_id(customer_id: Uuid, pool: &PgPool) -> Result<Vec<AccountEvent>> {
+        let events = query_as!(
+            AccountEvent,
+            r#"
+            SELECT
+                id, customer_id,
+                kind as "kind!: AccountEventKind",
+                ip_address, user_agent, created_at
+            FROM account_events WHERE customer_id = $1
+            ORDER BY created_at DESC
+            "#,
+            customer_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(events)
+    }
+}
@@ -8,6 +8,12 @@ use crate::{models::auth::AuthCustomer, Result};
 pub trait AuthRepository {
     async fn map_id(id: Option<Uuid>, pool: &PgPool) -> Result<Option<Uuid>>;
     async fn get_auth_customer(email: &str, pool: &PgPool) -> Result<AuthCustomer>;
+    /// Returns `false` if the customer has already enabled two factor authentication
+    async fn set_two_factor_secret(id: Uuid, secret: Vec<u8>, pool: &PgPool) -> Result<bool>;
+    async fn find_two_factor_secret(id: Uuid, pool: &PgPool) -> Result<Option<Vec<u8>>>;
+    async fn enable_two_factor(id: Uuid, pool: &PgPool) -> Result<()>;
+    /// Returns `false` if a code from this time step, or a later one, has already been used
+    async fn use_two_factor_step(id: Uuid, step: i64, pool: &PgPool) -> Result<bool>;
 }
 
 pub struct AuthDatabase;
@@ -35,7 +41,8 @@ impl AuthRepository for AuthDatabase {
         let customer = query_as!(
             AuthCustomer,
             r#"
-            SELECT public_id, id, hashed_password FROM auth WHERE email = $1
+            SELECT public_id, id, hashed_password, two_factor_secret, two_factor_enabled
+            FROM auth WHERE email = $1
             "#,
             email
         )
@@ -43,4 +50,62 @@ impl AuthRepository for AuthDatabase {
         .await?;
         Ok(customer)
     }
+
+    #[tracing::instrument(skip(pool, secret), fields(repository = "auth"))]
+    async fn set_two_factor_secret(id: Uuid, secret: Vec<u8>, pool: &PgPool) -> Result<bool> {
+        let updated = query!(
+            r#"
+            UPDATE auth SET two_factor_secret = $1
+            WHERE id = $2 AND NOT two_factor_enabled
+            RETURNING id
+            "#,
+            secret,
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(updated.is_some())
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "auth"))]
+    async fn find_two_factor_secret(id: Uuid, pool: &PgPool) -> Result<Option<Vec<u8>>> {
+        let auth = query!(
+            r#"
+            SELECT two_factor_secret FROM auth WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(auth.two_factor_secret)
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "auth"))]
+    async fn enable_two_factor(id: Uuid, pool: &PgPool) -> Result<()> {
+        query!(
+            r#"
+            UPDATE auth SET two_factor_enabled = TRUE WHERE id = $1
+            "#,
+            id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "auth"))]
+    async fn use_two_factor_step(id: Uuid, step: i64, pool: &PgPool) -> Result<bool> {
+        let updated = query!(
+            r#"
+            UPDATE auth SET two_factor_last_step = $1
+            WHERE id = $2 AND (two_factor_last_step IS NULL OR two_factor_last_step < $1)
+            RETURNING id
+            "#,
+            step,
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(updated.is_some())
+    }
 }
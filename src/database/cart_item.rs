@@ -6,6 +6,16 @@ use crate::{models::CartItem, Result};
 #[async_trait]
 pub trait CartItemRepository {
     async fn find_multiple(items: &[String], pool: &PgPool) -> Result<Vec<CartItem>>;
+    /// In stock items with any of the `tags`, other than those in `excluded_skus`
+    async fn find_by_tags(
+        tags: &[String],
+        excluded_skus: &[String],
+        limit: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<CartItem>>;
+    /// Returns the new stock level, or `None` if the item doesn't exist or the
+    /// adjustment would leave it with negative stock
+    async fn adjust_stock(sku: &str, delta: i32, pool: &PgPool) -> Result<Option<i32>>;
 }
 
 pub struct CartItemDatabase;
@@ -30,7 +40,65 @@ impl CartItemRepository for CartItemDatabase {
                 description: item.description,
                 img_src: item.img_src,
                 tags: item.tags,
+                available: item.stock,
+                weight_grams: item.weight_grams,
+                added_at: None,
+                gift_message: None,
             })
             .collect())
     }
+
+    async fn find_by_tags(
+        tags: &[String],
+        excluded_skus: &[String],
+        limit: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<CartItem>> {
+        let items = query!(
+            r#"
+            SELECT * FROM items
+            WHERE tags::TEXT[] && $1 AND NOT (sku = ANY ($2)) AND stock > 0
+            ORDER BY sku ASC
+            LIMIT $3
+            "#,
+            tags,
+            excluded_skus,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(items
+            .into_iter()
+            .map(|item| CartItem {
+                sku: item.sku,
+                quantity: 0,
+                price_per_unit: item.price,
+                name: item.name,
+                description: item.description,
+                img_src: item.img_src,
+                tags: item.tags,
+                available: item.stock,
+                weight_grams: item.weight_grams,
+                added_at: None,
+                gift_message: None,
+            })
+            .collect())
+    }
+
+    async fn adjust_stock(sku: &str, delta: i32, pool: &PgPool) -> Result<Option<i32>> {
+        let stock = query!(
+            r#"
+            UPDATE items SET stock = stock + $1
+            WHERE sku = $2 AND stock + $1 >= 0
+            RETURNING stock
+            "#,
+            delta,
+            sku
+        )
+        .fetch_optional(pool)
+        .await?
+        .map(|row| row.stock);
+        Ok(stock)
+    }
 }
@@ -1,17 +1,21 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use sqlx::{query, query_as, PgPool};
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    database::ShoppingCartDatabase,
+    database::{ShoppingCartDatabase, TracedTransaction},
     models::{
         customer::NewCustomer, shopping_cart::CartType, Currency, Customer, CustomerUpdate,
         ShoppingCart,
     },
-    Result,
+    BazaarError, Result,
 };
 
+/// Postgres' error code for a unique constraint violation
+const UNIQUE_VIOLATION: &str = "23505";
+
 #[async_trait]
 pub trait CustomerRepository {
     async fn create_new_user(
@@ -23,6 +27,12 @@ pub trait CustomerRepository {
     async fn find_all(pool: &PgPool) -> Result<Vec<Customer>>;
     async fn find_by_id(id: Uuid, pool: &PgPool) -> Result<Customer>;
     async fn find_by_email(email: String, pool: &PgPool) -> Result<Customer>;
+    async fn search(
+        pattern: String,
+        limit: i64,
+        offset: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Customer>>;
     async fn check_cart(id: Uuid, pool: &PgPool) -> Result<Uuid>;
     async fn update(id: Uuid, update: Vec<CustomerUpdate>, pool: &PgPool) -> Result<()>;
     async fn add_new_cart(
@@ -33,6 +43,22 @@ pub trait CustomerRepository {
     ) -> Result<ShoppingCart>;
     async fn fetch_refresh_token_counter(id: Uuid, pool: &PgPool) -> Result<i32>;
     async fn increment_refresh_token_counter(id: Uuid, pool: &PgPool) -> Result<i32>;
+    async fn rotate_refresh_token_counter(
+        id: Uuid,
+        current_count: i32,
+        grace_until: DateTime<Utc>,
+        pool: &PgPool,
+    ) -> Result<Option<i32>>;
+    async fn is_refresh_token_count_valid(id: Uuid, count: i32, pool: &PgPool) -> Result<bool>;
+    async fn is_admin(id: Uuid, pool: &PgPool) -> Result<bool>;
+    async fn touch_last_login(id: Uuid, pool: &PgPool) -> Result<()>;
+    /// Returns whether the customer's consent changed
+    async fn set_marketing_consent(id: Uuid, opt_in: bool, pool: &PgPool) -> Result<bool>;
+    async fn update_cart_id(id: Uuid, cart_id: Uuid, pool: &PgPool) -> Result<()>;
+    async fn confirm_pending_email(id: Uuid, email: String, pool: &PgPool) -> Result<bool>;
+    /// Whether the address belongs to, or is pending confirmation by, a customer other
+    /// than `id`
+    async fn is_email_in_use(email: String, id: Uuid, pool: &PgPool) -> Result<bool>;
 }
 
 pub struct CustomerDatabase;
@@ -44,7 +70,13 @@ impl CustomerRepository for CustomerDatabase {
         let customer = query_as!(
             Customer,
             r#"
-            SELECT * FROM customers
+            SELECT
+                id, email, first_name, last_name, created_at, last_modified, cart_id,
+                refresh_token_count, is_admin, last_login_at, avatar_url, timezone,
+                marketing_opt_in, marketing_opt_in_at,
+                consented_at, consent_policy_version, preferred_language,
+                preferred_currency as "preferred_currency!: Currency"
+            FROM customers
             "#
         )
         .fetch_all(pool)
@@ -57,7 +89,13 @@ impl CustomerRepository for CustomerDatabase {
         let customer = query_as!(
             Customer,
             r#"
-            SELECT * FROM customers WHERE id = $1
+            SELECT
+                id, email, first_name, last_name, created_at, last_modified, cart_id,
+                refresh_token_count, is_admin, last_login_at, avatar_url, timezone,
+                marketing_opt_in, marketing_opt_in_at,
+                consented_at, consent_policy_version, preferred_language,
+                preferred_currency as "preferred_currency!: Currency"
+            FROM customers WHERE id = $1
             "#,
             id
         )
@@ -71,7 +109,13 @@ impl CustomerRepository for CustomerDatabase {
         let customer = query_as!(
             Customer,
             r#"
-            SELECT * FROM customers WHERE email = $1;
+            SELECT
+                id, email, first_name, last_name, created_at, last_modified, cart_id,
+                refresh_token_count, is_admin, last_login_at, avatar_url, timezone,
+                marketing_opt_in, marketing_opt_in_at,
+                consented_at, consent_policy_version, preferred_language,
+                preferred_currency as "preferred_currency!: Currency"
+            FROM customers WHERE email = $1;
             "#,
             email
         )
@@ -80,6 +124,40 @@ impl CustomerRepository for CustomerDatabase {
         Ok(customer)
     }
 
+    /// Matches the `ILIKE` pattern against the customers' email and names. Unlike the
+    /// other lookups the customers are returned with their **public** ID
+    #[tracing::instrument(skip(pool), fields(repository = "customer"))]
+    async fn search(
+        pattern: String,
+        limit: i64,
+        offset: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<Customer>> {
+        let customers = query_as!(
+            Customer,
+            r#"
+            SELECT
+                a.public_id as "id!", c.email, c.first_name, c.last_name, c.created_at,
+                c.last_modified, c.cart_id, c.refresh_token_count, c.is_admin,
+                c.last_login_at, c.avatar_url, c.timezone,
+                c.marketing_opt_in, c.marketing_opt_in_at,
+                c.consented_at, c.consent_policy_version, c.preferred_language,
+                c.preferred_currency as "preferred_currency!: Currency"
+            FROM customers c
+            JOIN auth a ON a.id = c.id
+            WHERE c.email ILIKE $1 OR c.first_name ILIKE $1 OR c.last_name ILIKE $1
+            ORDER BY c.email ASC
+            LIMIT $2 OFFSET $3
+            "#,
+            pattern,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(customers)
+    }
+
     // Must not trace customer - includes password hash
     #[tracing::instrument(skip(pool, customer), fields(repository = "customer"))]
     async fn create_new_user(
@@ -88,7 +166,7 @@ impl CustomerRepository for CustomerDatabase {
         currency: Currency,
         pool: &PgPool,
     ) -> Result<()> {
-        let mut tx = pool.begin().await?;
+        let mut tx = TracedTransaction::begin("create_new_user", pool).await?;
 
         query!(
             r#"
@@ -100,21 +178,26 @@ impl CustomerRepository for CustomerDatabase {
             customer.hashed_password,
             customer.email
         )
-        .execute(&mut tx)
+        .execute(&mut *tx)
         .await?;
 
         query!(
             r#"
-            INSERT INTO customers ( id, email, first_name, last_name, cart_id )
-            VALUES ( $1, $2, $3, $4, $5)
+            INSERT INTO customers (
+                id, email, first_name, last_name, cart_id, preferred_currency,
+                consented_at, consent_policy_version
+            )
+            VALUES ( $1, $2, $3, $4, $5, $6, NOW(), $7)
             "#,
             customer.private_id,
             customer.email,
             customer.first_name,
             customer.last_name,
-            customer.cart_id
+            customer.cart_id,
+            currency as Currency,
+            customer.consent_policy_version
         )
-        .execute(&mut tx)
+        .execute(&mut *tx)
         .await?;
 
         if create_new_cart {
@@ -126,21 +209,25 @@ impl CustomerRepository for CustomerDatabase {
                 customer.cart_id,
                 customer.private_id,
                 CartType::Known as CartType,
-                Currency::GBP as Currency
+                currency as Currency
             )
-            .execute(&mut tx)
+            .execute(&mut *tx)
             .await?;
         } else {
+            // The anonymous cart is adopted as the customer's known cart, in their preferred
+            // currency (callers make sure the currency of a cart with items doesn't change)
             query!(
                 r#"
                 UPDATE shopping_carts
-                SET cart_type = $1
-                WHERE id = $2
+                SET cart_type = $1, customer_id = $2, currency = $3
+                WHERE id = $4
                 "#,
                 CartType::Known as CartType,
+                customer.private_id,
+                currency as Currency,
                 customer.cart_id,
             )
-            .execute(&mut tx)
+            .execute(&mut *tx)
             .await?;
         }
 
@@ -150,14 +237,23 @@ impl CustomerRepository for CustomerDatabase {
 
     #[tracing::instrument(skip(pool, update), fields(repository = "customer"))]
     async fn update(id: Uuid, update: Vec<CustomerUpdate>, pool: &PgPool) -> Result<()> {
-        let mut tx = pool.begin().await?;
+        let mut tx = TracedTransaction::begin("update_customer", pool).await?;
         let updates: Vec<(&str, String)> = update
             .into_iter()
             .filter_map(|update| {
                 if let Some(query) = match update.key.to_lowercase().as_str() {
                     "firstname" => Some("UPDATE customers SET first_name = $1 WHERE id = $2"),
                     "lastname" => Some("UPDATE customers SET last_name = $1 WHERE id = $2"),
-                    "email" => Some("UPDATE customers SET email = $1 WHERE id = $2"),
+                    // Email changes are held until the new address has been verified
+                    "email" => Some("UPDATE customers SET pending_email = $1 WHERE id = $2"),
+                    "avatarurl" => Some("UPDATE customers SET avatar_url = $1 WHERE id = $2"),
+                    "timezone" => Some("UPDATE customers SET timezone = $1 WHERE id = $2"),
+                    "preferredlanguage" => {
+                        Some("UPDATE customers SET preferred_language = $1 WHERE id = $2")
+                    }
+                    "preferredcurrency" => Some(
+                        "UPDATE customers SET preferred_currency = $1::currency_type WHERE id = $2",
+                    ),
                     err => {
                         error!(
                             key = err,
@@ -177,7 +273,7 @@ impl CustomerRepository for CustomerDatabase {
             sqlx::query(query)
                 .bind(value)
                 .bind(id)
-                .execute(&mut tx)
+                .execute(&mut *tx)
                 .await?;
         }
         tx.commit().await?;
@@ -253,7 +349,7 @@ impl CustomerRepository for CustomerDatabase {
         let count = query!(
             r#"
             UPDATE customers
-            SET refresh_token_count = refresh_token_count + 1
+            SET refresh_token_count = refresh_token_count + 1, refresh_token_grace_until = NULL
             WHERE id = $1
             RETURNING refresh_token_count
             "#,
@@ -263,4 +359,173 @@ impl CustomerRepository for CustomerDatabase {
         .await?;
         Ok(count.refresh_token_count)
     }
+
+    /// Only increments the counter if it still matches `current_count`, returning `None`
+    /// if another request has already rotated it
+    #[tracing::instrument(skip(pool), fields(repository = "customer"))]
+    async fn rotate_refresh_token_counter(
+        id: Uuid,
+        current_count: i32,
+        grace_until: DateTime<Utc>,
+        pool: &PgPool,
+    ) -> Result<Option<i32>> {
+        let count = query!(
+            r#"
+            UPDATE customers
+            SET refresh_token_count = refresh_token_count + 1, refresh_token_grace_until = $3
+            WHERE id = $1 AND refresh_token_count = $2
+            RETURNING refresh_token_count
+            "#,
+            id,
+            current_count,
+            grace_until
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(count.map(|count| count.refresh_token_count))
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "customer"))]
+    async fn is_refresh_token_count_valid(id: Uuid, count: i32, pool: &PgPool) -> Result<bool> {
+        let result = query!(
+            r#"
+            SELECT (
+                refresh_token_count = $2
+                OR (
+                    refresh_token_count - 1 = $2
+                    AND refresh_token_grace_until IS NOT NULL
+                    AND refresh_token_grace_until > NOW()
+                )
+            ) AS "is_valid!"
+            FROM customers WHERE id = $1
+            "#,
+            id,
+            count
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(result.is_valid)
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "customer"))]
+    async fn is_admin(id: Uuid, pool: &PgPool) -> Result<bool> {
+        let customer = query!(
+            r#"
+            SELECT is_admin FROM customers WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(customer.is_admin)
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "customer"))]
+    async fn touch_last_login(id: Uuid, pool: &PgPool) -> Result<()> {
+        query!(
+            r#"
+            UPDATE customers
+            SET last_login_at = NOW()
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "customer"))]
+    async fn set_marketing_consent(id: Uuid, opt_in: bool, pool: &PgPool) -> Result<bool> {
+        let result = query!(
+            r#"
+            UPDATE customers
+            SET marketing_opt_in = $1, marketing_opt_in_at = NOW()
+            WHERE id = $2 AND marketing_opt_in <> $1
+            "#,
+            opt_in,
+            id
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "customer"))]
+    async fn update_cart_id(id: Uuid, cart_id: Uuid, pool: &PgPool) -> Result<()> {
+        query!(
+            r#"
+            UPDATE customers SET cart_id = $1 WHERE id = $2
+            "#,
+            cart_id,
+            id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Swaps the customer's email for the pending one, as long as it's still the address
+    /// that was verified. Returns whether there was a matching change to confirm
+    #[tracing::instrument(skip(pool, email), fields(repository = "customer"))]
+    async fn confirm_pending_email(id: Uuid, email: String, pool: &PgPool) -> Result<bool> {
+        let mut tx = TracedTransaction::begin("confirm_pending_email", pool).await?;
+        let confirmed = query!(
+            r#"
+            UPDATE customers
+            SET email = pending_email, pending_email = NULL
+            WHERE id = $1 AND pending_email = $2
+            RETURNING id
+            "#,
+            id,
+            email
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(map_email_in_use)?;
+        if confirmed.is_none() {
+            return Ok(false);
+        }
+        query!(
+            r#"
+            UPDATE auth SET email = $1 WHERE id = $2
+            "#,
+            email,
+            id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(map_email_in_use)?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(skip(pool, email), fields(repository = "customer"))]
+    async fn is_email_in_use(email: String, id: Uuid, pool: &PgPool) -> Result<bool> {
+        let in_use = query!(
+            r#"
+            SELECT (
+                EXISTS (SELECT 1 FROM customers WHERE email = $1)
+                OR EXISTS (SELECT 1 FROM auth WHERE email = $1)
+                OR EXISTS (SELECT 1 FROM customers WHERE pending_email = $1 AND id <> $2)
+            ) AS "in_use!"
+            "#,
+            email,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(in_use.in_use)
+    }
+}
+
+/// Another customer may have taken the address since the change was requested
+fn map_email_in_use(e: sqlx::Error) -> BazaarError {
+    match &e {
+        sqlx::Error::Database(err) if err.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            error!(err = ?e, "pending email is now in use by another customer");
+            BazaarError::BadRequest("Email address is already in use".to_string())
+        }
+        _ => e.into(),
+    }
 }
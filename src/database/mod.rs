@@ -1,9 +1,16 @@
+mod account_event;
 mod auth;
 mod cart_item;
 mod customer;
+mod saved_cart;
 mod shopping_cart;
+mod transaction;
 
+pub use account_event::{AccountEventDatabase, AccountEventRepository};
 pub use auth::{AuthDatabase, AuthRepository};
 pub use cart_item::{CartItemDatabase, CartItemRepository};
 pub use customer::{CustomerDatabase, CustomerRepository};
+pub use saved_cart::{SavedCartDatabase, SavedCartRepository};
 pub use shopping_cart::{ShoppingCartDatabase, ShoppingCartRepository};
+
+pub(crate) use transaction::TracedTransaction;
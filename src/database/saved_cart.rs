@@ -0,0 +1,97 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use sqlx::{query_as, types::Json, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        cart_item::InternalCartItem,
+        saved_cart::{SavedCart, SqlxSavedCart},
+    },
+    Result,
+};
+
+#[async_trait]
+pub trait SavedCartRepository {
+    async fn create(
+        id: Uuid,
+        customer_id: Uuid,
+        name: String,
+        items_array: Value,
+        pool: &PgPool,
+    ) -> Result<SavedCart>;
+    async fn find_by_customer_id(customer_id: Uuid, pool: &PgPool) -> Result<Vec<SavedCart>>;
+    async fn find_by_id(id: Uuid, customer_id: Uuid, pool: &PgPool) -> Result<SavedCart>;
+}
+
+pub struct SavedCartDatabase;
+
+#[async_trait]
+impl SavedCartRepository for SavedCartDatabase {
+    #[tracing::instrument(skip(pool), fields(repository = "saved_cart"))]
+    async fn create(
+        id: Uuid,
+        customer_id: Uuid,
+        name: String,
+        items_array: Value,
+        pool: &PgPool,
+    ) -> Result<SavedCart> {
+        let saved_cart = query_as!(
+            SqlxSavedCart,
+            r#"
+            INSERT INTO saved_carts (id, customer_id, name, items)
+            VALUES ($1, $2, $3, $4::jsonb)
+            RETURNING
+                id, customer_id, name,
+                items as "items!: Json<Vec<InternalCartItem>>",
+                created_at
+            "#,
+            id,
+            customer_id,
+            name,
+            items_array
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(saved_cart.into())
+    }
+
+    #[tracing::instrument(skip(pool), fields(repository = "saved_cart"))]
+    async fn find_by_customer_id(customer_id: Uuid, pool: &PgPool) -> Result<Vec<SavedCart>> {
+        let saved_carts = query_as!(
+            SqlxSavedCart,
+            r#"
+            SELECT
+                id, customer_id, name,
+                items as "items!: Json<Vec<InternalCartItem>>",
+                created_at
+            FROM saved_carts WHERE customer_id = $1
+            ORDER BY created_at DESC
+            "#,
+            customer_id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(saved_carts.into_iter().map(Into::into).collect())
+    }
+
+    /// Saved carts are only found if they belong to the given customer
+    #[tracing::instrument(skip(pool), fields(repository = "saved_cart"))]
+    async fn find_by_id(id: Uuid, customer_id: Uuid, pool: &PgPool) -> Result<SavedCart> {
+        let saved_cart = query_as!(
+            SqlxSavedCart,
+            r#"
+            SELECT
+                id, customer_id, name,
+                items as "items!: Json<Vec<InternalCartItem>>",
+                created_at
+            FROM saved_carts WHERE id = $1 AND customer_id = $2
+            "#,
+            id,
+            customer_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(saved_cart.into())
+    }
+}
@@ -4,10 +4,11 @@ use sqlx::{query, query_as, types::Json, PgPool};
 use uuid::Uuid;
 
 use crate::{
+    database::TracedTransaction,
     models::{
         cart_item::InternalCartItem,
         shopping_cart::{CartType, SqlxShoppingCart},
-        Currency, ShoppingCart,
+        CartSummary, Currency, ShoppingCart,
     },
     Result,
 };
@@ -15,6 +16,7 @@ use crate::{
 #[async_trait]
 pub trait ShoppingCartRepository {
     async fn find_by_id(id: Uuid, pool: &PgPool) -> Result<ShoppingCart>;
+    async fn find_summary(id: Uuid, pool: &PgPool) -> Result<CartSummary>;
     async fn find_by_customer_id(id: Uuid, pool: &PgPool) -> Result<ShoppingCart>;
     async fn find_cart_id_by_customer_id(id: Uuid, pool: &PgPool) -> Result<Uuid>;
     async fn create_new_cart(
@@ -30,6 +32,7 @@ pub trait ShoppingCartRepository {
         pool: &PgPool,
     ) -> Result<ShoppingCart>;
     async fn update_cart_type(id: Uuid, cart_type: CartType, pool: &PgPool) -> Result<Uuid>;
+    async fn delete(id: Uuid, replacement_id: Uuid, pool: &PgPool) -> Result<()>;
 }
 
 pub struct ShoppingCartDatabase;
@@ -45,7 +48,9 @@ impl ShoppingCartRepository for ShoppingCartDatabase {
                 id, customer_id,
                 cart_type as "cart_type!: CartType", 
                 items as "items!: Json<Vec<InternalCartItem>>",
+                item_count, distinct_item_count,
                 currency as "currency!: Currency",
+                gift_wrap,
                 discounts, price_before_discounts, price_after_discounts,
                 created_at, last_modified
             FROM shopping_carts WHERE id = $1
@@ -57,6 +62,23 @@ impl ShoppingCartRepository for ShoppingCartDatabase {
         Ok(cart.into())
     }
 
+    #[tracing::instrument(skip(pool), fields(repository = "shopping_cart"))]
+    async fn find_summary(id: Uuid, pool: &PgPool) -> Result<CartSummary> {
+        let summary = query_as!(
+            CartSummary,
+            r#"
+            SELECT
+                item_count, distinct_item_count, price_after_discounts as total,
+                currency as "currency!: Currency"
+            FROM shopping_carts WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(summary)
+    }
+
     #[tracing::instrument(skip(pool), fields(repository = "shopping_cart"))]
     async fn find_by_customer_id(id: Uuid, pool: &PgPool) -> Result<ShoppingCart> {
         let cart = query_as!(
@@ -66,7 +88,9 @@ impl ShoppingCartRepository for ShoppingCartDatabase {
                 id, customer_id,
                 cart_type as "cart_type!: CartType", 
                 items as "items!: Json<Vec<InternalCartItem>>",
+                item_count, distinct_item_count,
                 currency as "currency!: Currency",
+                gift_wrap,
                 discounts, price_before_discounts, price_after_discounts,
                 created_at, last_modified
             FROM shopping_carts WHERE customer_id = $1
@@ -108,7 +132,9 @@ impl ShoppingCartRepository for ShoppingCartDatabase {
                 id, customer_id, 
                 cart_type as "cart_type!: CartType", 
                 items as "items!: Json<Vec<InternalCartItem>>",
+                item_count, distinct_item_count,
                 currency as "currency!: Currency",
+                gift_wrap,
                 discounts, price_before_discounts, price_after_discounts,
                 created_at, last_modified
             "#,
@@ -132,19 +158,25 @@ impl ShoppingCartRepository for ShoppingCartDatabase {
             SqlxShoppingCart,
             r#"
             UPDATE shopping_carts
-            SET price_before_discounts = $1, price_after_discounts = $2, items = $3::jsonb
-            WHERE id = $4
+            SET price_before_discounts = $1, price_after_discounts = $2, items = $3::jsonb, gift_wrap = $4,
+                item_count = $5, distinct_item_count = $6, last_modified = NOW()
+            WHERE id = $7
             RETURNING 
                 id, customer_id, 
                 cart_type as "cart_type!: CartType", 
                 items as "items!: Json<Vec<InternalCartItem>>",
+                item_count, distinct_item_count,
                 currency as "currency!: Currency",
+                gift_wrap,
                 discounts, price_before_discounts, price_after_discounts,
                 created_at, last_modified
             "#,
             cart.price_before_discounts,
             cart.price_after_discounts,
             items_array,
+            cart.gift_wrap,
+            cart.item_count,
+            cart.distinct_item_count,
             cart.id
         )
         .fetch_one(pool)
@@ -168,4 +200,51 @@ impl ShoppingCartRepository for ShoppingCartDatabase {
         .await?;
         Ok(cart.id)
     }
+
+    /// `customers.cart_id` can't be null, so a known cart is replaced with an empty one
+    /// (`replacement_id`) in the same currency, which the customer is pointed at
+    #[tracing::instrument(skip(pool), fields(repository = "shopping_cart"))]
+    async fn delete(id: Uuid, replacement_id: Uuid, pool: &PgPool) -> Result<()> {
+        let mut tx = TracedTransaction::begin("delete_cart", pool).await?;
+
+        let deleted = query!(
+            r#"
+            DELETE FROM shopping_carts WHERE id = $1
+            RETURNING customer_id, currency as "currency!: Currency"
+            "#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(customer_id) = deleted.customer_id {
+            query!(
+                r#"
+            INSERT INTO shopping_carts (id, customer_id, cart_type, currency)
+            VALUES ( $1, $2, $3, $4)
+            "#,
+                replacement_id,
+                customer_id,
+                CartType::Known as CartType,
+                deleted.currency as Currency
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            query!(
+                r#"
+                UPDATE customers
+                SET cart_id = $1
+                WHERE id = $2
+                "#,
+                replacement_id,
+                customer_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
 }
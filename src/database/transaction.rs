@@ -0,0 +1,129 @@
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{debug, field, info_span, warn, Span};
+
+use std::ops::{Deref, DerefMut};
+
+use crate::Result;
+
+/// A database transaction which traces when it begins, and whether it was committed
+/// or rolled back
+///
+/// Any transaction that is dropped without being committed is rolled back by SQLx,
+/// so returning early (ie. via `?`) will record the rollback
+pub(crate) struct TracedTransaction {
+    tx: Transaction<'static, Postgres>,
+    trace: TransactionTrace,
+}
+
+impl TracedTransaction {
+    pub async fn begin(name: &'static str, pool: &PgPool) -> Result<Self> {
+        let trace = TransactionTrace::begin(name);
+        let tx = pool.begin().await?;
+        Ok(Self { tx, trace })
+    }
+
+    pub async fn commit(self) -> Result<()> {
+        let Self { tx, trace } = self;
+        tx.commit().await?;
+        trace.committed();
+        Ok(())
+    }
+}
+
+impl Deref for TracedTransaction {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl DerefMut for TracedTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+struct TransactionTrace {
+    span: Span,
+    committed: bool,
+}
+
+impl TransactionTrace {
+    fn begin(name: &'static str) -> Self {
+        let span = info_span!(
+            "db_transaction",
+            transaction = name,
+            rolled_back = field::Empty
+        );
+        span.in_scope(|| debug!("transaction began"));
+        Self {
+            span,
+            committed: false,
+        }
+    }
+
+    fn committed(mut self) {
+        self.committed = true;
+        self.span.record("rolled_back", &false);
+        self.span.in_scope(|| debug!("transaction committed"));
+    }
+}
+
+impl Drop for TransactionTrace {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        self.span.record("rolled_back", &true);
+        self.span.in_scope(|| warn!("transaction rolled back"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::RecordedFields;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    /// Runs `f` and returns the fields recorded while it ran
+    fn capture_fields(f: impl FnOnce()) -> Vec<String> {
+        let recorded = RecordedFields::default();
+        let subscriber = Registry::default().with(recorded.clone());
+        tracing::subscriber::with_default(subscriber, f);
+        recorded.recorded()
+    }
+
+    fn messages(fields: &[String]) -> Vec<&str> {
+        fields
+            .iter()
+            .filter_map(|field| field.strip_prefix("message="))
+            .collect()
+    }
+
+    #[test]
+    fn dropping_an_uncommitted_transaction_records_a_rollback() {
+        let fields = capture_fields(|| {
+            let trace = TransactionTrace::begin("test");
+            drop(trace);
+        });
+        assert_eq!(
+            messages(&fields),
+            vec!["transaction began", "transaction rolled back"]
+        );
+        assert!(fields.contains(&"rolled_back=true".to_string()));
+    }
+
+    #[test]
+    fn committing_a_transaction_does_not_record_a_rollback() {
+        let fields = capture_fields(|| {
+            let trace = TransactionTrace::begin("test");
+            trace.committed();
+        });
+        assert_eq!(
+            messages(&fields),
+            vec!["transaction began", "transaction committed"]
+        );
+        assert!(fields.contains(&"rolled_back=false".to_string()));
+    }
+}
@@ -9,6 +9,9 @@ pub enum BazaarError {
     #[error("Could not find resource")]
     NotFound,
 
+    #[error("Could not find item")]
+    ItemNotFound(String),
+
     #[error("User is not authorized")]
     Unauthorized,
 
@@ -18,15 +21,30 @@ pub enum BazaarError {
     #[error("Incorrect credentials provided")]
     IncorrectCredentials,
 
+    #[error("Two factor authentication code required")]
+    TwoFactorRequired,
+
     #[error("Anonymous users do not have access to this resource")]
     AnonymousError,
 
     #[error("Bad Request: {0}")]
     BadRequest(String),
 
+    #[error("Bad Request: {0}")]
+    CurrencyMismatch(String),
+
     #[error("Invalid token provided")]
     InvalidToken(String),
 
+    #[error("Invalid token provided")]
+    ExpiredToken,
+
+    #[error("Invalid token provided")]
+    InvalidTokenSignature,
+
+    #[error("Unexpected error occurred")]
+    KeyError(String),
+
     #[error("A server error occurred")]
     DatabaseError,
 
@@ -42,20 +60,42 @@ pub enum BazaarError {
     #[error("Provided data was malformed")]
     MalformedData,
 
-    #[error(transparent)]
-    CryptoError(#[from] argon2::Error),
+    #[error("Unexpected error occurred")]
+    CryptoError(#[source] argon2::Error),
 
     #[error("Rand Error: {0}")]
     RandError(String),
 
     #[error(transparent)]
     StrConversion(#[from] std::str::Utf8Error),
+
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
+
+    #[error("IO error: {0}")]
+    IoError(String),
+
+    #[error("Integration error: {1}")]
+    IntegrationError(IntegrationErrorKind, String),
+}
+
+/// Why a request to an external service failed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IntegrationErrorKind {
+    /// The service didn't respond in time
+    Timeout,
+    /// A connection to the service couldn't be established
+    Connection,
+    /// The service responded with an error status
+    Status,
+    /// The request couldn't be built or the response couldn't be read
+    Request,
 }
 
 impl ErrorExtensions for BazaarError {
     fn extend(&self) -> async_graphql::Error {
         async_graphql::Error::new(format!("{}", self)).extend_with(|_, e| match self {
-            Self::BadRequest(error) => {
+            Self::BadRequest(error) | Self::CurrencyMismatch(error) => {
                 e.set("status", 400);
                 e.set("statusText", "BAD_REQUEST");
                 e.set("details", error.to_string());
@@ -64,11 +104,25 @@ impl ErrorExtensions for BazaarError {
                 e.set("status", 401);
                 e.set("statusText", "UNAUTHORIZED");
             }
+            Self::TwoFactorRequired => {
+                e.set("status", 401);
+                e.set("statusText", "TWO_FACTOR_REQUIRED");
+            }
             Self::InvalidToken(error) => {
                 e.set("status", 401);
                 e.set("statusText", "INVALID_TOKEN");
                 e.set("details", error.to_string());
             }
+            Self::ExpiredToken => {
+                e.set("status", 401);
+                e.set("statusText", "TOKEN_EXPIRED");
+                e.set("details", "Token has expired");
+            }
+            Self::InvalidTokenSignature => {
+                e.set("status", 401);
+                e.set("statusText", "INVALID_TOKEN_SIGNATURE");
+                e.set("details", "Token signature is invalid");
+            }
             Self::Forbidden => {
                 e.set("status", 403);
                 e.set("statusText", "FORBIDDEN");
@@ -77,15 +131,35 @@ impl ErrorExtensions for BazaarError {
                 e.set("status", 404);
                 e.set("statusText", "NOT_FOUND");
             }
+            Self::ItemNotFound(sku) => {
+                e.set("status", 404);
+                e.set("statusText", "NOT_FOUND");
+                e.set("details", sku.to_string());
+            }
             Self::ServerError(error) => {
                 e.set("status", 500);
                 e.set("statusText", "SERVER_ERROR");
                 e.set("context", error.to_string());
             }
-            Self::UnexpectedError | Self::PoisonConcurrencyError(_) => {
+            Self::UnexpectedError
+            | Self::DatabaseError
+            | Self::PoisonConcurrencyError(_)
+            | Self::ConfigError(_)
+            | Self::IoError(_)
+            | Self::KeyError(_)
+            | Self::CryptoError(_) => {
                 e.set("status", 500);
                 e.set("statusText", "SERVER_ERROR");
             }
+            Self::IntegrationError(IntegrationErrorKind::Timeout, _)
+            | Self::IntegrationError(IntegrationErrorKind::Connection, _) => {
+                e.set("status", 503);
+                e.set("statusText", "SERVICE_UNAVAILABLE");
+            }
+            Self::IntegrationError(_, _) => {
+                e.set("status", 502);
+                e.set("statusText", "BAD_GATEWAY");
+            }
             _ => {}
         })
     }
@@ -103,10 +177,12 @@ impl From<Vec<&String>> for Messages {
 impl ResponseError for BazaarError {
     fn error_response(&self) -> HttpResponse {
         match self {
-            Self::NotFound => HttpResponse::NotFound().finish(),
-            Self::Unauthorized | Self::IncorrectCredentials => {
-                HttpResponse::Unauthorized().finish()
-            }
+            Self::NotFound | Self::ItemNotFound(_) => HttpResponse::NotFound().finish(),
+            Self::Unauthorized
+            | Self::IncorrectCredentials
+            | Self::TwoFactorRequired
+            | Self::ExpiredToken
+            | Self::InvalidTokenSignature => HttpResponse::Unauthorized().finish(),
             Self::Forbidden => HttpResponse::Forbidden().finish(),
             Self::InvalidToken(error) => {
                 HttpResponse::Unauthorized().json::<Messages>(vec![error].into())
@@ -115,6 +191,11 @@ impl ResponseError for BazaarError {
                 HttpResponse::InternalServerError().json::<Messages>(vec![error].into())
             }
             Self::UnexpectedError => HttpResponse::InternalServerError().finish(),
+            Self::IntegrationError(IntegrationErrorKind::Timeout, _)
+            | Self::IntegrationError(IntegrationErrorKind::Connection, _) => {
+                HttpResponse::ServiceUnavailable().finish()
+            }
+            Self::IntegrationError(_, _) => HttpResponse::BadGateway().finish(),
             // Catch all, as most of the time we should be using GraphQL errors
             _ => HttpResponse::InternalServerError().finish(),
         }
@@ -160,6 +241,48 @@ impl From<tokio::task::JoinError> for BazaarError {
     }
 }
 
+impl From<jsonwebtoken::errors::Error> for BazaarError {
+    fn from(e: jsonwebtoken::errors::Error) -> BazaarError {
+        use jsonwebtoken::errors::ErrorKind::*;
+
+        match e.kind() {
+            ExpiredSignature => BazaarError::ExpiredToken,
+            InvalidSignature => {
+                error!(err = ?e, "json web token signature did not verify");
+                BazaarError::InvalidTokenSignature
+            }
+            InvalidRsaKey | InvalidEcdsaKey | InvalidKeyFormat | Crypto(_) => {
+                error!(err = ?e, "failed to use the json web token keys");
+                BazaarError::KeyError(e.to_string())
+            }
+            _ => {
+                error!(err = ?e, "failed to validate json web token");
+                BazaarError::InvalidToken("Token did not match what was expected".to_string())
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for BazaarError {
+    fn from(e: std::io::Error) -> BazaarError {
+        error!(err = ?e, kind = ?e.kind(), "IO error occurred");
+        BazaarError::IoError(e.to_string())
+    }
+}
+
+impl From<config::ConfigError> for BazaarError {
+    fn from(e: config::ConfigError) -> BazaarError {
+        BazaarError::ConfigError(e.to_string())
+    }
+}
+
+impl From<argon2::Error> for BazaarError {
+    fn from(e: argon2::Error) -> BazaarError {
+        error!(err = ?e, "Argon2 error occurred");
+        BazaarError::CryptoError(e)
+    }
+}
+
 impl From<rand::Error> for BazaarError {
     fn from(e: rand::Error) -> BazaarError {
         error!(
@@ -170,3 +293,168 @@ impl From<rand::Error> for BazaarError {
         BazaarError::RandError(e.to_string())
     }
 }
+
+impl From<reqwest::Error> for BazaarError {
+    fn from(e: reqwest::Error) -> BazaarError {
+        error!(err = ?e, url = ?e.url(), "request to an external service failed");
+
+        let kind = if e.is_timeout() {
+            IntegrationErrorKind::Timeout
+        } else if e.is_connect() {
+            IntegrationErrorKind::Connection
+        } else if e.is_status() {
+            IntegrationErrorKind::Status
+        } else {
+            IntegrationErrorKind::Request
+        };
+        let details = match e.status() {
+            Some(status) => format!("service responded with {}", status),
+            None => e.to_string(),
+        };
+        BazaarError::IntegrationError(kind, details)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::errors::{Error as JwtError, ErrorKind};
+
+    fn integration_error_kind(err: reqwest::Error) -> IntegrationErrorKind {
+        match BazaarError::from(err) {
+            BazaarError::IntegrationError(kind, _) => kind,
+            err => panic!("expected an integration error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn reqwest_status_errors_map_to_bad_gateway() {
+        let response = http::Response::builder()
+            .status(503)
+            .body("")
+            .expect("failed to build response");
+        let err = reqwest::Response::from(response)
+            .error_for_status()
+            .expect_err("a 503 should be an error");
+
+        let err = BazaarError::from(err);
+        assert_eq!(
+            err,
+            BazaarError::IntegrationError(
+                IntegrationErrorKind::Status,
+                "service responded with 503 Service Unavailable".to_string()
+            )
+        );
+        let extensions = serde_json::to_value(&err.extend().extensions).unwrap();
+        assert_eq!(extensions["status"], 502);
+        assert_eq!(extensions["statusText"], "BAD_GATEWAY");
+    }
+
+    #[test]
+    fn reqwest_builder_errors_map_to_request_errors() {
+        let err = reqwest::Client::new()
+            .get("not a url")
+            .build()
+            .expect_err("an invalid url should fail to build");
+        assert_eq!(integration_error_kind(err), IntegrationErrorKind::Request);
+    }
+
+    #[tokio::test]
+    async fn reqwest_connection_errors_map_to_connection_errors() {
+        // Bind then drop a listener so the port is known to be closed
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("failed to bind a random port");
+        let err = reqwest::get(&format!("http://{}", addr))
+            .await
+            .expect_err("nothing should be listening");
+        assert_eq!(
+            integration_error_kind(err),
+            IntegrationErrorKind::Connection
+        );
+    }
+
+    #[tokio::test]
+    async fn reqwest_timeouts_map_to_timeout_errors() {
+        // Accepts connections but never responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+        let addr = listener.local_addr().expect("failed to read address");
+        let err = reqwest::Client::new()
+            .get(&format!("http://{}", addr))
+            .timeout(std::time::Duration::from_millis(50))
+            .send()
+            .await
+            .expect_err("the request should time out");
+        assert_eq!(integration_error_kind(err), IntegrationErrorKind::Timeout);
+    }
+
+    #[test]
+    fn crypto_errors_are_masked_as_server_errors() {
+        let err = BazaarError::from(argon2::Error::SaltTooShort).extend();
+        assert_eq!(err.message, "Unexpected error occurred");
+
+        let extensions = serde_json::to_value(&err.extensions).unwrap();
+        assert_eq!(
+            extensions,
+            serde_json::json!({ "status": 500, "statusText": "SERVER_ERROR" })
+        );
+    }
+
+    #[test]
+    fn io_errors_map_to_io_errors() {
+        let err = std::net::TcpListener::bind("not an address")
+            .expect_err("an invalid address shouldn't be bound");
+        let err = BazaarError::from(err);
+        assert!(matches!(err, BazaarError::IoError(_)));
+
+        let extensions = serde_json::to_value(&err.extend().extensions).unwrap();
+        assert_eq!(extensions["status"], 500);
+    }
+
+    #[test]
+    fn expired_jwt_maps_to_expired_token() {
+        let err: BazaarError = JwtError::from(ErrorKind::ExpiredSignature).into();
+        assert_eq!(err, BazaarError::ExpiredToken);
+
+        let extensions = serde_json::to_value(&err.extend().extensions).unwrap();
+        assert_eq!(
+            extensions,
+            serde_json::json!({
+                "status": 401,
+                "statusText": "TOKEN_EXPIRED",
+                "details": "Token has expired"
+            })
+        );
+    }
+
+    #[test]
+    fn tampered_jwt_maps_to_invalid_token() {
+        for kind in vec![ErrorKind::InvalidToken, ErrorKind::InvalidAlgorithm] {
+            let err: BazaarError = JwtError::from(kind).into();
+            assert!(matches!(err, BazaarError::InvalidToken(_)));
+        }
+    }
+
+    #[test]
+    fn jwt_signature_failures_map_to_invalid_token_signature() {
+        let err: BazaarError = JwtError::from(ErrorKind::InvalidSignature).into();
+        assert_eq!(err, BazaarError::InvalidTokenSignature);
+
+        let extensions = serde_json::to_value(&err.extend().extensions).unwrap();
+        assert_eq!(extensions["status"], 401);
+        assert_eq!(extensions["statusText"], "INVALID_TOKEN_SIGNATURE");
+    }
+
+    #[test]
+    fn jwt_key_errors_map_to_key_errors() {
+        for kind in vec![ErrorKind::InvalidRsaKey, ErrorKind::InvalidKeyFormat] {
+            let err: BazaarError = JwtError::from(kind).into();
+            assert!(matches!(err, BazaarError::KeyError(_)));
+
+            let err = err.extend();
+            assert_eq!(err.message, "Unexpected error occurred");
+            let extensions = serde_json::to_value(&err.extensions).unwrap();
+            assert_eq!(extensions["statusText"], "SERVER_ERROR");
+        }
+    }
+}
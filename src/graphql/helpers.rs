@@ -5,10 +5,11 @@ use std::sync::Arc;
 use tracing::error;
 
 use crate::{
-    auth::verify_and_deserialize_token,
+    auth::{verify_and_deserialize_token_with_cache, IdMappingCache},
     database::AuthDatabase,
-    models::{BazaarCookies, BazaarToken, BazaarTokens, TokenType},
-    AppConfig, BazaarError, Environment, Result,
+    integrations::email::EmailSender,
+    models::{BazaarCookies, BazaarToken, BazaarTokens, RequestOrigin, TokenType},
+    AppConfig, AppMetrics, BazaarError, Environment, Result,
 };
 
 /// An internal struct that holds state that is pulled off the
@@ -45,6 +46,22 @@ impl<'a> GraphqlContext<'a> {
     }
 }
 
+#[cfg(test)]
+impl<'a> GraphqlContext<'a> {
+    pub(crate) fn with_access_token(
+        pool: &'a PgPool,
+        access_token: Option<Result<BazaarToken>>,
+    ) -> Self {
+        Self {
+            pool,
+            access_token,
+            access_token_raw: None,
+            refresh_token: None,
+            refresh_token_raw: None,
+        }
+    }
+}
+
 /// The most common call signature for this function will be:
 /// `extract_token_and_database_pool(ctx, true, false).await?;`
 /// which will extract the database pool and the access token from
@@ -63,6 +80,7 @@ pub async fn extract_token_and_database_pool<'a>(
         error!(err=?e, "failed to retrieve request cookies from graphql context");
         BazaarError::BadRequest("Failed to validate access cookies".to_owned())
     })?;
+    let leeway = extract_configuration(context)?.auth.token_leeway_seconds;
     let mut result = GraphqlContext {
         pool,
         access_token: None,
@@ -70,13 +88,31 @@ pub async fn extract_token_and_database_pool<'a>(
         refresh_token: None,
         refresh_token_raw: cookies.get_refresh_cookie()?,
     };
+    // Both tokens belong to the same customer, so share the ID lookups between them
+    let mut ids = IdMappingCache::default();
     if extract_access_token {
-        result.access_token =
-            Some(extract_token(&result.access_token_raw, TokenType::Access, pool).await);
+        result.access_token = Some(
+            extract_token(
+                &result.access_token_raw,
+                TokenType::Access,
+                leeway,
+                &mut ids,
+                pool,
+            )
+            .await,
+        );
     }
     if extract_refresh_token {
-        result.refresh_token =
-            Some(extract_token(&result.refresh_token_raw, TokenType::Refresh(0), pool).await);
+        result.refresh_token = Some(
+            extract_token(
+                &result.refresh_token_raw,
+                TokenType::Refresh(0),
+                leeway,
+                &mut ids,
+                pool,
+            )
+            .await,
+        );
     }
 
     Ok(result)
@@ -85,10 +121,15 @@ pub async fn extract_token_and_database_pool<'a>(
 pub async fn extract_token(
     cookie_raw: &Option<String>,
     token_type: TokenType,
+    leeway: u64,
+    ids: &mut IdMappingCache,
     pool: &PgPool,
 ) -> Result<BazaarToken> {
     if let Some(cookie) = cookie_raw {
-        return verify_and_deserialize_token::<AuthDatabase>(cookie, token_type, pool).await;
+        return verify_and_deserialize_token_with_cache::<AuthDatabase>(
+            cookie, token_type, leeway, ids, pool,
+        )
+        .await;
     }
     Err(BazaarError::InvalidToken("No token was found".to_owned()))
 }
@@ -100,24 +141,56 @@ pub fn extract_database_pool<'a>(context: &'a Context<'_>) -> Result<&'a PgPool>
     })
 }
 
+/// Schemas built outside of the HTTP handler (ie. in tests) won't have an origin, so
+/// this falls back to an unknown one
+pub fn extract_request_origin(context: &Context<'_>) -> RequestOrigin {
+    context
+        .data_opt::<RequestOrigin>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn extract_metrics<'a>(context: &'a Context<'_>) -> Result<&'a AppMetrics> {
+    context.data::<AppMetrics>().map_err(|err| {
+        error!(err = ?err, "failed to extract metrics from graphql context");
+        BazaarError::ServerError(err.message)
+    })
+}
+
+pub fn extract_configuration<'a>(context: &'a Context<'_>) -> Result<&'a AppConfig> {
+    context.data::<AppConfig>().map_err(|err| {
+        error!(err = ?err, "failed to extract configuration from graphql context");
+        BazaarError::ServerError(err.message)
+    })
+}
+
+pub fn extract_email_sender<'a>(context: &'a Context<'_>) -> Result<&'a Arc<dyn EmailSender>> {
+    context.data::<Arc<dyn EmailSender>>().map_err(|err| {
+        error!(err = ?err, "failed to extract email sender from graphql context");
+        BazaarError::ServerError(err.message)
+    })
+}
+
 #[tracing::instrument(skip(ctx, tokens))]
 pub fn set_auth_cookies_on_response(ctx: &Context<'_>, tokens: &BazaarTokens) {
-    let app_env = ctx
+    let config = ctx
         .data::<AppConfig>()
-        .expect("configuration should always be present in context")
-        .env;
+        .expect("configuration should always be present in context");
+    let cookie_domain = config.application.cookie_domain.as_deref();
     let access = generate_auth_cookie_string(
         &tokens.access_token,
         TokenType::Access,
         tokens.access_token_expires_in,
-        app_env,
+        config.env,
+        cookie_domain,
     );
     ctx.append_http_header(SET_COOKIE, access);
     let refresh = generate_auth_cookie_string(
         &tokens.refresh_token,
         TokenType::Refresh(0),
         tokens.refresh_token_expires_in,
-        app_env,
+        config.env,
+        cookie_domain,
     );
     ctx.append_http_header(SET_COOKIE, refresh);
 }
@@ -129,19 +202,72 @@ fn generate_auth_cookie_string(
     token_type: TokenType,
     expiry: i64,
     env: Environment,
+    domain: Option<&str>,
 ) -> String {
     // This is hacky, and ideally we'd be able to get rid of it, but with `Secure` set on the
     // cookies, and no TLS cert on the server, none of the cookies get set within the tests.
     // Ideally we'd push all the traffic to https even on tests
-    let secure = match env {
-        Environment::Local | Environment::Test => "",
-        _ => "Secure; ",
+    let (secure, domain) = match env {
+        Environment::Local | Environment::Test => (String::new(), String::new()),
+        _ => (
+            "Secure; ".to_string(),
+            domain
+                .map(|domain| format!("Domain={}; ", domain))
+                .unwrap_or_default(),
+        ),
     };
     format!(
-        "{}={}; {}HttpOnly; Max-Age={}",
+        "{}={}; {}{}HttpOnly; Max-Age={}",
         token_type.as_str(),
         cookie,
+        domain,
         secure,
         expiry
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookie_domain_is_set_in_production() {
+        let cookie = generate_auth_cookie_string(
+            "token",
+            TokenType::Access,
+            900,
+            Environment::Production,
+            Some("example.com"),
+        );
+        assert_eq!(
+            cookie,
+            "ACCESS=token; Domain=example.com; Secure; HttpOnly; Max-Age=900"
+        );
+    }
+
+    #[test]
+    fn cookie_domain_is_omitted_locally() {
+        for env in vec![Environment::Local, Environment::Test] {
+            let cookie = generate_auth_cookie_string(
+                "token",
+                TokenType::Access,
+                900,
+                env,
+                Some("example.com"),
+            );
+            assert_eq!(cookie, "ACCESS=token; HttpOnly; Max-Age=900");
+        }
+    }
+
+    #[test]
+    fn no_domain_is_set_when_none_is_configured() {
+        let cookie = generate_auth_cookie_string(
+            "token",
+            TokenType::Refresh(0),
+            900,
+            Environment::Production,
+            None,
+        );
+        assert_eq!(cookie, "REFRESH=token; Secure; HttpOnly; Max-Age=900");
+    }
+}
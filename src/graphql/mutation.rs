@@ -2,34 +2,50 @@ use async_graphql::{
     validators::{Email, StringMinLength},
     Context, ErrorExtensions, Object, Result,
 };
-use tracing::error;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    auth::{generate_new_tokens, refresh_tokens, verify_password_and_fetch_details},
-    database::{AuthDatabase, CartItemDatabase, CustomerDatabase, ShoppingCartDatabase},
+    auth::{
+        authorize_known, encode_email_change_token, generate_new_tokens, refresh_tokens,
+        verify_admin, verify_password_and_fetch_details,
+    },
+    database::{
+        AccountEventDatabase, AuthDatabase, CartItemDatabase, CustomerDatabase, SavedCartDatabase,
+        ShoppingCartDatabase,
+    },
     graphql::{
-        extract_token_and_database_pool, set_auth_cookies_on_response,
-        validators::ValidCustomerUpdateType,
+        extract_configuration, extract_database_pool, extract_email_sender, extract_metrics,
+        extract_request_origin, extract_token_and_database_pool, set_auth_cookies_on_response,
+        validators::{validate_cart_input, ValidCustomerUpdateType},
     },
+    integrations::email::{dispatch, Email},
     models::{
-        cart_item::{InternalCartItem, UpdateCartItem},
-        BazaarTokens, Currency, Customer, CustomerType, CustomerUpdate, ShoppingCart,
+        account_event::AccountEventKind,
+        auth::AuthCustomer,
+        cart_item::{GiftMessage, InternalCartItem, UpdateCartItem},
+        AccountEvent, BazaarTokens, CartItem, Currency, Customer, CustomerType, CustomerUpdate,
+        SavedCart, SharedCart, ShoppingCart, TwoFactorSetup,
     },
-    BazaarError,
+    AuthOperation, AuthOutcome, BazaarError,
 };
 
 pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
-    #[tracing::instrument(skip(self, ctx, email, password))]
+    /// Customers who have enabled two factor authentication also need to provide the
+    /// current code from their authenticator app
+    #[tracing::instrument(skip(self, ctx, email, password, totp_code))]
     async fn login(
         &self,
         ctx: &Context<'_>,
         email: String,
         password: String,
+        totp_code: Option<String>,
     ) -> Result<BazaarTokens> {
+        let metrics = extract_metrics(ctx).map_err(|e| e.extend())?;
+        let mut attempt = metrics.auth_attempt(AuthOperation::Login);
         let context = extract_token_and_database_pool(ctx, true, false)
             .await
             .map_err(|e| e.extend())?;
@@ -41,6 +57,7 @@ impl MutationRoot {
                     id = ?token.id,
                     "customer already has valid tokens"
                 );
+                attempt.failed(AuthOutcome::AlreadyAuthenticated);
                 return Err(BazaarError::BadRequest(
                     "Customer already has valid tokens".to_string(),
                 )
@@ -53,23 +70,74 @@ impl MutationRoot {
         let customer_details =
             verify_password_and_fetch_details::<AuthDatabase>(&email, &password, pool)
                 .await
-                .map_err(|e| e.extend())?;
+                .map_err(|err| {
+                    if let BazaarError::IncorrectCredentials | BazaarError::NotFound = err {
+                        attempt.failed(AuthOutcome::IncorrectCredentials);
+                    }
+                    err.extend()
+                })?;
+        customer_details
+            .verify_second_factor::<AuthDatabase>(totp_code.as_deref(), pool)
+            .await
+            .map_err(|err| {
+                match err {
+                    BazaarError::TwoFactorRequired => {
+                        attempt.failed(AuthOutcome::TwoFactorRequired)
+                    }
+                    BazaarError::IncorrectCredentials => {
+                        attempt.failed(AuthOutcome::IncorrectCredentials)
+                    }
+                    _ => {}
+                }
+                err.extend()
+            })?;
+        // Failing to record the login shouldn't stop the customer from logging in
+        if let Err(err) =
+            Customer::touch_last_login::<CustomerDatabase>(customer_details.id, pool).await
+        {
+            error!(?err, "failed to record the customer's last login");
+        }
+        AccountEvent::record::<AccountEventDatabase>(
+            customer_details.id,
+            AccountEventKind::Login,
+            &extract_request_origin(ctx),
+            pool,
+        )
+        .await;
         let cart_id = ShoppingCart::find_cart_id_by_customer_id::<ShoppingCartDatabase>(
             customer_details.id,
             pool,
         )
-        .await?;
+        .await
+        .map_err(|err| {
+            error!(?err, "failed to find the customer's cart");
+            err.extend()
+        })?;
+        if let Err(err) =
+            Customer::reconcile_cart_id::<CustomerDatabase>(customer_details.id, cart_id, pool)
+                .await
+        {
+            error!(?err, "failed to reconcile the customer's cart pointer");
+        }
 
         // If the customer did some browsing while anonymous (ie. the token is valid), need
-        // to merge the two carts together
+        // to merge the two carts together. Anonymous carts are always in GBP, so for a
+        // customer whose cart is in another currency the anonymous cart is left as it is
+        // rather than stopping them from logging in
         if let Some(anonymous_cart_id) = anonymous_cart_id {
-            let id = ShoppingCart::merge_shopping_carts::<ShoppingCartDatabase, CartItemDatabase>(
+            match ShoppingCart::merge_shopping_carts::<ShoppingCartDatabase, CartItemDatabase>(
                 cart_id,
                 anonymous_cart_id,
                 pool,
             )
-            .await?;
-            assert_eq!(id, cart_id);
+            .await
+            {
+                Ok(id) => assert_eq!(id, cart_id),
+                Err(BazaarError::CurrencyMismatch(details)) => {
+                    warn!(?anonymous_cart_id, %details, "didn't merge the anonymous cart");
+                }
+                Err(err) => return Err(err.extend()),
+            }
         }
         let tokens = generate_new_tokens::<CustomerDatabase>(
             Some(customer_details.public_id),
@@ -82,6 +150,7 @@ impl MutationRoot {
 
         // @TODO - Refactor all this to avoid the cloning
         set_auth_cookies_on_response(ctx, &tokens);
+        attempt.succeeded();
         Ok(tokens)
     }
 
@@ -91,6 +160,8 @@ impl MutationRoot {
         // and both have expired. However when they access the site after that
         // time period the client they're using hasn't cleared the tokens and
         // expired tokens are sent. In this case we do want to log them in again.
+        let metrics = extract_metrics(ctx).map_err(|e| e.extend())?;
+        let mut attempt = metrics.auth_attempt(AuthOperation::AnonymousLogin);
         let context = extract_token_and_database_pool(ctx, true, false)
             .await
             .map_err(|e| e.extend())?;
@@ -98,6 +169,7 @@ impl MutationRoot {
         if token.is_ok() {
             // If the token is `Ok` it means the token is valid, in which case
             // we want them to use those tokens
+            attempt.failed(AuthOutcome::AlreadyAuthenticated);
             return Err(BazaarError::BadRequest("Valid token already exists".to_string()).extend());
         };
         let pool = context.pool;
@@ -107,16 +179,47 @@ impl MutationRoot {
             .map_err(|e| e.extend())?;
 
         // @TODO - Refactor all this to avoid the cloning
+        set_auth_cookies_on_response(ctx, &tokens);
+        attempt.succeeded();
+        Ok(tokens)
+    }
+
+    /// Starts a new anonymous session for an existing anonymous cart, so a customer who
+    /// has lost their tokens can carry on with their cart
+    #[tracing::instrument(skip(self, ctx))]
+    async fn recover_cart(&self, ctx: &Context<'_>, cart_id: Uuid) -> Result<BazaarTokens> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        if context.access_token().is_ok() {
+            return Err(BazaarError::BadRequest("Valid token already exists".to_string()).extend());
+        };
+        let pool = context.pool;
+        let cart = ShoppingCart::find_recoverable::<ShoppingCartDatabase>(cart_id, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to recover cart");
+                err.extend()
+            })?;
+        let tokens = generate_new_tokens::<CustomerDatabase>(None, None, cart.id, pool)
+            .await
+            .map_err(|e| e.extend())?;
+
         set_auth_cookies_on_response(ctx, &tokens);
         Ok(tokens)
     }
 
     #[tracing::instrument(skip(self, ctx))]
     async fn refresh(&self, ctx: &Context<'_>) -> Result<BazaarTokens> {
+        let metrics = extract_metrics(ctx).map_err(|e| e.extend())?;
+        let mut attempt = metrics.auth_attempt(AuthOperation::Refresh);
         let context = extract_token_and_database_pool(ctx, true, true)
             .await
             .map_err(|e| e.extend())?;
-        let refresh_token = context.refresh_token()?;
+        let refresh_token = context.refresh_token().map_err(|err| {
+            attempt.failed(AuthOutcome::InvalidToken);
+            err
+        })?;
         let raw_refresh_token = context.refresh_token_raw.clone().expect(
             "if the refresh token is valid then there should have been a valid raw token too",
         );
@@ -127,14 +230,153 @@ impl MutationRoot {
             raw_refresh_token,
             pool,
         )
-        .await?;
+        .await
+        .map_err(|err| {
+            if matches!(
+                err,
+                BazaarError::InvalidToken(_)
+                    | BazaarError::ExpiredToken
+                    | BazaarError::InvalidTokenSignature
+            ) {
+                attempt.failed(AuthOutcome::InvalidToken);
+            }
+            err
+        })?;
 
         // @TODO - Refactor all this to avoid the cloning
         set_auth_cookies_on_response(ctx, &tokens);
+        attempt.succeeded();
 
         Ok(tokens)
     }
 
+    #[tracing::instrument(skip(self, ctx))]
+    async fn invalidate_all_tokens(&self, ctx: &Context<'_>, customer_id: Uuid) -> Result<bool> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+        verify_admin::<CustomerDatabase>(&token, pool)
+            .await
+            .map_err(|e| e.extend())?;
+
+        let id = AuthCustomer::map_id::<AuthDatabase>(Some(customer_id), pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find customer to invalidate tokens for");
+                err.extend()
+            })?
+            .ok_or_else(|| BazaarError::NotFound.extend())?;
+        Customer::increment_refresh_token_counter::<CustomerDatabase>(id, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to invalidate customer's tokens");
+                err.extend()
+            })?;
+        AccountEvent::record::<AccountEventDatabase>(
+            id,
+            AccountEventKind::TokensInvalidated,
+            &extract_request_origin(ctx),
+            pool,
+        )
+        .await;
+        Ok(true)
+    }
+
+    /// Customers whose cart is deleted are given a new, empty cart in its place
+    #[tracing::instrument(skip(self, ctx))]
+    async fn delete_cart(&self, ctx: &Context<'_>, id: Uuid) -> Result<bool> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+        verify_admin::<CustomerDatabase>(&token, pool)
+            .await
+            .map_err(|e| e.extend())?;
+
+        ShoppingCart::delete::<ShoppingCartDatabase>(id, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to delete cart");
+                err.extend()
+            })?;
+        Ok(true)
+    }
+
+    /// Admin only, replaces the contents of a customer's cart with the provided items,
+    /// ie. when migrating customers from another store
+    #[tracing::instrument(skip(self, ctx, items))]
+    async fn import_cart(
+        &self,
+        ctx: &Context<'_>,
+        customer_id: Uuid,
+        items: Vec<UpdateCartItem>,
+    ) -> Result<ShoppingCart> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+        verify_admin::<CustomerDatabase>(&token, pool)
+            .await
+            .map_err(|e| e.extend())?;
+
+        let id = AuthCustomer::map_id::<AuthDatabase>(Some(customer_id), pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find customer to import cart for");
+                err.extend()
+            })?
+            .ok_or_else(|| BazaarError::NotFound.extend())?;
+        let max_distinct_items = extract_configuration(ctx)
+            .map_err(|e| e.extend())?
+            .cart
+            .max_distinct_items;
+        validate_cart_input(&items, max_distinct_items).map_err(|e| e.extend())?;
+        CartItem::verify_skus_exist::<CartItemDatabase>(&items, pool)
+            .await
+            .map_err(|e| e.extend())?;
+        let cart_id = ShoppingCart::find_cart_id_by_customer_id::<ShoppingCartDatabase>(id, pool)
+            .await
+            .map_err(|e| e.extend())?;
+        ShoppingCart::replace_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+            cart_id,
+            items.into_iter().map(Into::into).collect(),
+            max_distinct_items,
+            pool,
+        )
+        .await
+        .map_err(|err| {
+            error!(?err, "failed to import cart");
+            err.extend()
+        })
+    }
+
+    /// Admin only, adjusts the stock of an item by `delta` (which can be negative),
+    /// returning the new stock level
+    #[tracing::instrument(skip(self, ctx))]
+    async fn adjust_stock(&self, ctx: &Context<'_>, sku: String, delta: i32) -> Result<i32> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+        verify_admin::<CustomerDatabase>(&token, pool)
+            .await
+            .map_err(|e| e.extend())?;
+
+        CartItem::adjust_stock::<CartItemDatabase>(&sku, delta, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to adjust stock");
+                err.extend()
+            })
+    }
+
+    /// Customers must accept both the terms and the privacy policy to sign up. An anonymous
+    /// cart with items in it can't be moved into a different preferred currency
     #[tracing::instrument(skip(self, ctx, password, first_name, last_name, email))]
     async fn sign_up(
         &self,
@@ -143,7 +385,19 @@ impl MutationRoot {
         #[graphql(validator(StringMinLength(length = "8")))] password: String,
         #[graphql(validator(StringMinLength(length = "2")))] first_name: String,
         #[graphql(validator(StringMinLength(length = "2")))] last_name: String,
+        accepted_terms: bool,
+        accepted_privacy_policy: bool,
+        #[graphql(default_with = "Currency::GBP")] preferred_currency: Currency,
     ) -> Result<BazaarTokens> {
+        if !(accepted_terms && accepted_privacy_policy) {
+            return Err(BazaarError::BadRequest(
+                "The terms and privacy policy must be accepted".to_string(),
+            )
+            .extend());
+        }
+        let metrics = extract_metrics(ctx).map_err(|e| e.extend())?;
+        let mut attempt = metrics.auth_attempt(AuthOperation::SignUp);
+        let email_sender = extract_email_sender(ctx).map_err(|e| e.extend())?;
         let context = extract_token_and_database_pool(ctx, true, false)
             .await
             .map_err(|e| e.extend())?;
@@ -158,6 +412,7 @@ impl MutationRoot {
                     id = ?token.id.unwrap_or_default(),
                     "customer already has valid tokens"
                 );
+                attempt.failed(AuthOutcome::AlreadyAuthenticated);
                 return Err(
                     BazaarError::BadRequest("Customer already exists".to_string()).extend(),
                 );
@@ -167,6 +422,25 @@ impl MutationRoot {
             None
         };
 
+        // The anonymous cart is adopted in the preferred currency, which would misprice any
+        // items that are already in it. A cart that has since been removed is replaced
+        let cart_id = match cart_id {
+            Some(id) => match ShoppingCart::find_by_id::<ShoppingCartDatabase>(id, pool).await {
+                Ok(cart) => {
+                    cart.ensure_adoptable_in(preferred_currency)
+                        .map_err(|e| e.extend())?;
+                    Some(id)
+                }
+                Err(BazaarError::NotFound) => None,
+                Err(err) => {
+                    error!(?err, "failed to find the anonymous cart to adopt");
+                    return Err(err.extend());
+                }
+            },
+            None => None,
+        };
+
+        let welcome_email = Email::welcome(&email, &first_name);
         let ids = Customer::new::<CustomerDatabase>(
             Uuid::new_v4(),
             email,
@@ -174,6 +448,7 @@ impl MutationRoot {
             first_name,
             last_name,
             cart_id,
+            preferred_currency,
             pool,
         )
         .await
@@ -191,9 +466,27 @@ impl MutationRoot {
         .map_err(|e| e.extend())?;
         // @TODO - Refactor all this to avoid the cloning
         set_auth_cookies_on_response(ctx, &tokens);
+        attempt.succeeded();
+        dispatch(email_sender, welcome_email);
         Ok(tokens)
     }
 
+    /// Records when the customer's consent to marketing changes, setting it to what
+    /// it already is leaves `marketingOptInAt` untouched
+    #[tracing::instrument(skip(self, ctx))]
+    async fn set_marketing_consent(&self, ctx: &Context<'_>, opt_in: bool) -> Result<Customer> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let id = authorize_known(&context).map_err(|e| e.extend())?;
+        Customer::set_marketing_consent::<CustomerDatabase>(id, opt_in, context.pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to set marketing consent");
+                err.extend()
+            })
+    }
+
     #[tracing::instrument(skip(self, ctx, update))]
     async fn update_customer(
         &self,
@@ -203,17 +496,59 @@ impl MutationRoot {
         let context = extract_token_and_database_pool(ctx, true, false)
             .await
             .map_err(|e| e.extend())?;
-        let token = context.access_token().map_err(|e| e.extend())?;
-        let pool = context.pool;
-        if let Some(id) = token.id {
-            return Customer::update::<CustomerDatabase>(id, update, pool)
+        let id = authorize_known(&context).map_err(|e| e.extend())?;
+        let new_email = update
+            .iter()
+            .find(|u| u.key.eq_ignore_ascii_case("email"))
+            .map(|u| u.value.clone());
+        let customer = Customer::update::<CustomerDatabase>(id, update, context.pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to update customer");
+                err.extend()
+            })?;
+
+        // The email is only changed once the new address has been verified
+        if let Some(email) = new_email {
+            let token = context.access_token().map_err(|e| e.extend())?;
+            let public_id = token
+                .public_id()
+                .expect("valid token should always have public id");
+            let verification_token =
+                encode_email_change_token(public_id, &email).map_err(|e| e.extend())?;
+            let email_sender = extract_email_sender(ctx).map_err(|e| e.extend())?;
+            dispatch(
+                email_sender,
+                Email::email_change_verification(&email, &verification_token),
+            );
+            info!("email change is pending verification");
+        }
+        Ok(customer)
+    }
+
+    /// Confirms a pending email change with the token sent to the new address
+    #[tracing::instrument(skip(self, ctx, token))]
+    async fn confirm_email_change(&self, ctx: &Context<'_>, token: String) -> Result<Customer> {
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        let leeway = extract_configuration(ctx)
+            .map_err(|e| e.extend())?
+            .auth
+            .token_leeway_seconds;
+        let (id, customer) =
+            Customer::confirm_email_change::<AuthDatabase, CustomerDatabase>(&token, leeway, pool)
                 .await
                 .map_err(|err| {
-                    error!(?err, "failed to update customer");
+                    error!(?err, "failed to confirm email change");
                     err.extend()
-                });
-        }
-        Err(BazaarError::AnonymousError.extend())
+                })?;
+        AccountEvent::record::<AccountEventDatabase>(
+            id,
+            AccountEventKind::EmailChange,
+            &extract_request_origin(ctx),
+            pool,
+        )
+        .await;
+        Ok(customer)
     }
 
     #[tracing::instrument(skip(self, ctx))]
@@ -227,9 +562,21 @@ impl MutationRoot {
             .map_err(|e| e.extend())?;
         let token = context.access_token().map_err(|e| e.extend())?;
         let pool = context.pool;
+        let cart_settings = &extract_configuration(ctx).map_err(|e| e.extend())?.cart;
+        validate_cart_input(&new_items, cart_settings.max_distinct_items)
+            .map_err(|e| e.extend())?;
+        if cart_settings.strict_skus {
+            CartItem::verify_skus_exist::<CartItemDatabase>(&new_items, pool)
+                .await
+                .map_err(|e| e.extend())?;
+        }
+        CartItem::verify_expected_prices::<CartItemDatabase>(&new_items, pool)
+            .await
+            .map_err(|e| e.extend())?;
         ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
             token.cart_id,
             new_items.into_iter().map(Into::into).collect(),
+            cart_settings.max_distinct_items,
             pool,
         )
         .await
@@ -239,6 +586,42 @@ impl MutationRoot {
         })
     }
 
+    /// Sets each of the provided items to the absolute quantity given, a quantity
+    /// of `0` removes the item from the cart
+    #[tracing::instrument(skip(self, ctx))]
+    async fn set_cart_items(
+        &self,
+        ctx: &Context<'_>,
+        items: Vec<UpdateCartItem>,
+    ) -> Result<ShoppingCart> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+        let cart_settings = &extract_configuration(ctx).map_err(|e| e.extend())?.cart;
+        validate_cart_input(&items, cart_settings.max_distinct_items).map_err(|e| e.extend())?;
+        if cart_settings.strict_skus {
+            CartItem::verify_skus_exist::<CartItemDatabase>(&items, pool)
+                .await
+                .map_err(|e| e.extend())?;
+        }
+        CartItem::verify_expected_prices::<CartItemDatabase>(&items, pool)
+            .await
+            .map_err(|e| e.extend())?;
+        ShoppingCart::set_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+            token.cart_id,
+            items.into_iter().map(Into::into).collect(),
+            cart_settings.max_distinct_items,
+            pool,
+        )
+        .await
+        .map_err(|err| {
+            error!(?err, "failed to set items in cart");
+            err.extend()
+        })
+    }
+
     #[tracing::instrument(skip(self, ctx))]
     async fn remove_items_from_cart(
         &self,
@@ -250,6 +633,11 @@ impl MutationRoot {
             .map_err(|e| e.extend())?;
         let token = context.access_token().map_err(|e| e.extend())?;
         let pool = context.pool;
+        let max_distinct_items = extract_configuration(ctx)
+            .map_err(|e| e.extend())?
+            .cart
+            .max_distinct_items;
+        validate_cart_input(&removed_items, max_distinct_items).map_err(|e| e.extend())?;
         ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
             token.cart_id,
             removed_items
@@ -260,6 +648,7 @@ impl MutationRoot {
                     item
                 })
                 .collect(),
+            max_distinct_items,
             pool,
         )
         .await
@@ -268,4 +657,134 @@ impl MutationRoot {
             err.extend()
         })
     }
+
+    /// Sets whether the cart should be gift wrapped and the gift messages on individual
+    /// lines, anything left out is unchanged
+    #[tracing::instrument(skip(self, ctx, gift_messages))]
+    async fn set_cart_gift_options(
+        &self,
+        ctx: &Context<'_>,
+        gift_wrap: Option<bool>,
+        gift_messages: Option<Vec<GiftMessage>>,
+    ) -> Result<ShoppingCart> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        ShoppingCart::set_gift_options::<ShoppingCartDatabase, CartItemDatabase>(
+            token.cart_id,
+            gift_wrap,
+            gift_messages.unwrap_or_default(),
+            context.pool,
+        )
+        .await
+        .map_err(|err| {
+            error!(?err, "failed to set gift options on cart");
+            err.extend()
+        })
+    }
+
+    /// Saves a snapshot of the items currently in the cart under the given name
+    #[tracing::instrument(skip(self, ctx))]
+    async fn save_cart(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(validator(StringMinLength(length = "1")))] name: String,
+    ) -> Result<SavedCart> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let id = authorize_known(&context).map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+        let cart = ShoppingCart::find_by_id::<ShoppingCartDatabase>(token.cart_id, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find customer's cart");
+                err.extend()
+            })?;
+        SavedCart::new::<SavedCartDatabase>(id, name, &cart.items, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to save cart");
+                err.extend()
+            })
+    }
+
+    /// Adds the items from a saved cart to the customer's current cart
+    #[tracing::instrument(skip(self, ctx))]
+    async fn load_saved_cart(&self, ctx: &Context<'_>, id: Uuid) -> Result<ShoppingCart> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let customer_id = authorize_known(&context).map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+        let saved_cart = SavedCart::find_by_id::<SavedCartDatabase>(id, customer_id, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find saved cart");
+                err.extend()
+            })?;
+        let max_distinct_items = extract_configuration(ctx)
+            .map_err(|e| e.extend())?
+            .cart
+            .max_distinct_items;
+        ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+            token.cart_id,
+            saved_cart.items,
+            max_distinct_items,
+            pool,
+        )
+        .await
+        .map_err(|err| {
+            error!(?err, "failed to load saved cart");
+            err.extend()
+        })
+    }
+
+    /// Creates a token for a link which lets anyone view the items in the customer's
+    /// cart, without being able to edit it. The link expires after 7 days
+    #[tracing::instrument(skip(self, ctx))]
+    async fn create_cart_share_link(&self, ctx: &Context<'_>) -> Result<String> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        SharedCart::create_link(token.cart_id).map_err(|err| {
+            error!(?err, "failed to create cart share link");
+            err.extend()
+        })
+    }
+
+    /// Starts setting up two factor authentication, it's only required on login once
+    /// `confirmTwoFactor` has been called with a code from the customer's app
+    #[tracing::instrument(skip(self, ctx))]
+    async fn enable_two_factor(&self, ctx: &Context<'_>) -> Result<TwoFactorSetup> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let id = authorize_known(&context).map_err(|e| e.extend())?;
+        TwoFactorSetup::enable::<AuthDatabase, CustomerDatabase>(id, context.pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to enable two factor authentication");
+                err.extend()
+            })
+    }
+
+    #[tracing::instrument(skip(self, ctx, code))]
+    async fn confirm_two_factor(&self, ctx: &Context<'_>, code: String) -> Result<bool> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let id = authorize_known(&context).map_err(|e| e.extend())?;
+        TwoFactorSetup::confirm::<AuthDatabase>(id, &code, context.pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to confirm two factor authentication");
+                err.extend()
+            })?;
+        Ok(true)
+    }
 }
@@ -1,11 +1,19 @@
-use async_graphql::{Context, Error, ErrorExtensions, Object, Result};
+use async_graphql::{
+    validators::{IntRange, StringMinLength},
+    Context, Error, ErrorExtensions, Object, Result,
+};
+use chrono::{DateTime, Utc};
 use sqlx::PgPool;
 use tracing::error;
 
 use crate::{
-    database::{CustomerDatabase, ShoppingCartDatabase},
-    graphql::extract_token_and_database_pool,
-    models::{Customer, CustomerType, ShoppingCart},
+    auth::{authorize_known, verify_admin},
+    database::{AccountEventDatabase, CustomerDatabase, SavedCartDatabase, ShoppingCartDatabase},
+    graphql::{extract_configuration, extract_database_pool, extract_token_and_database_pool},
+    models::{
+        AccountEvent, CartPage, CartSummary, Customer, Metadata, SavedCart, SharedCart,
+        ShoppingCart, TokenStatus,
+    },
     BazaarError,
 };
 
@@ -17,6 +25,29 @@ impl QueryRoot {
         true
     }
 
+    /// The server's current time, so clients can work out how far their clock has drifted
+    /// and schedule token refreshes accordingly
+    async fn server_time(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    /// How long the tokens sent with this request have left, and whether the client
+    /// should refresh them now
+    #[tracing::instrument(skip(self, ctx))]
+    async fn token_status(&self, ctx: &Context<'_>) -> Result<TokenStatus> {
+        let context = extract_token_and_database_pool(ctx, true, true)
+            .await
+            .map_err(|e| e.extend())?;
+        let refresh_token = context.refresh_token().map_err(|e| e.extend())?;
+        let access_token = context.access_token().ok();
+        Ok(TokenStatus::new(access_token.as_ref(), &refresh_token))
+    }
+
+    /// The supported values of the API's enums, with labels to display them by
+    async fn metadata(&self) -> Metadata {
+        Metadata
+    }
+
     // @TODO Remove this - only here for QoL while developing
     #[tracing::instrument(name = "get_customers", skip(self, ctx))]
     async fn customers(&self, ctx: &Context<'_>) -> Result<Vec<Customer>> {
@@ -31,28 +62,48 @@ impl QueryRoot {
 
     #[tracing::instrument(skip(self, ctx))]
     async fn customer<'ctx>(&self, ctx: &'ctx Context<'_>) -> Result<Customer> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let id = authorize_known(&context).map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+
+        let mut customer = Customer::find_by_id::<CustomerDatabase>(id, context.pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find customer");
+                BazaarError::NotFound.extend()
+            })?;
+        customer.id = token
+            .public_id()
+            .expect("valid token should always have public id");
+        Ok(customer)
+    }
+
+    /// Admin only, finds customers whose email or name contains `query`
+    #[tracing::instrument(skip(self, ctx, query))]
+    async fn search_customers(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(validator(StringMinLength(length = "2")))] query: String,
+        #[graphql(default = 20, validator(IntRange(min = "1", max = "100")))] limit: u32,
+        #[graphql(default)] offset: u32,
+    ) -> Result<Vec<Customer>> {
         let context = extract_token_and_database_pool(ctx, true, false)
             .await
             .map_err(|e| e.extend())?;
         let token = context.access_token().map_err(|e| e.extend())?;
         let pool = context.pool;
+        verify_admin::<CustomerDatabase>(&token, pool)
+            .await
+            .map_err(|e| e.extend())?;
 
-        if let Some(id) = token.id {
-            let mut customer = Customer::find_by_id::<CustomerDatabase>(id, pool)
-                .await
-                .map_err(|err| {
-                    error!(?err, "failed to find customer");
-                    BazaarError::NotFound.extend()
-                })?;
-            customer.id = token
-                .public_id()
-                .expect("valid token should always have public id");
-            return Ok(customer);
-        }
-        if token.customer_type == CustomerType::Anonymous {
-            return Err(BazaarError::AnonymousError.extend());
-        }
-        Err(BazaarError::Unauthorized.extend())
+        Customer::search::<CustomerDatabase>(&query, limit, offset, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to search customers");
+                err.extend()
+            })
     }
 
     #[tracing::instrument(skip(self, ctx))]
@@ -70,4 +121,87 @@ impl QueryRoot {
                 err.extend()
             })
     }
+
+    #[tracing::instrument(skip(self, ctx))]
+    async fn cart_summary(&self, ctx: &Context<'_>) -> Result<CartSummary> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+
+        ShoppingCart::find_summary::<ShoppingCartDatabase>(token.cart_id, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find customer's cart");
+                err.extend()
+            })
+    }
+
+    /// The cart, its totals and recommendations based on what's in it, so the cart
+    /// page can be rendered with a single request
+    #[tracing::instrument(skip(self, ctx))]
+    async fn cart_page(&self, ctx: &Context<'_>) -> Result<CartPage> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let token = context.access_token().map_err(|e| e.extend())?;
+        let pool = context.pool;
+
+        let cart = ShoppingCart::find_by_id::<ShoppingCartDatabase>(token.cart_id, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find customer's cart");
+                err.extend()
+            })?;
+        Ok(CartPage::from(cart))
+    }
+
+    /// The carts the customer has saved to order again, most recent first
+    #[tracing::instrument(skip(self, ctx))]
+    async fn saved_carts(&self, ctx: &Context<'_>) -> Result<Vec<SavedCart>> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let id = authorize_known(&context).map_err(|e| e.extend())?;
+
+        SavedCart::find_by_customer_id::<SavedCartDatabase>(id, context.pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find customer's saved carts");
+                err.extend()
+            })
+    }
+
+    /// Security related activity on the customer's account, most recent first
+    #[tracing::instrument(skip(self, ctx))]
+    async fn account_activity(&self, ctx: &Context<'_>) -> Result<Vec<AccountEvent>> {
+        let context = extract_token_and_database_pool(ctx, true, false)
+            .await
+            .map_err(|e| e.extend())?;
+        let id = authorize_known(&context).map_err(|e| e.extend())?;
+
+        AccountEvent::find_by_customer_id::<AccountEventDatabase>(id, context.pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find customer's account activity");
+                err.extend()
+            })
+    }
+
+    /// The items in a cart that has been shared with `createCartShareLink`
+    #[tracing::instrument(skip(self, ctx, token))]
+    async fn cart_from_share_link(&self, ctx: &Context<'_>, token: String) -> Result<SharedCart> {
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        let leeway = extract_configuration(ctx)
+            .map_err(|e| e.extend())?
+            .auth
+            .token_leeway_seconds;
+        SharedCart::from_link::<ShoppingCartDatabase>(&token, leeway, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, "failed to find shared cart");
+                err.extend()
+            })
+    }
 }
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use crate::{
+    models::cart_item::{UpdateCartItem, MAX_LINE_QUANTITY},
+    BazaarError, Result,
+};
+
+/// The longest sku that will be accepted
+const MAX_SKU_LENGTH: usize = 64;
+
+/// Validates the items passed to any of the cart mutations, rather than stopping at the
+/// first problem every problem is listed in a single `BadRequest`
+pub fn validate_cart_input(items: &[UpdateCartItem], max_distinct_items: usize) -> Result<()> {
+    let mut problems = Vec::new();
+    // Repeated skus are applied to the same line, so their quantities are checked together
+    let mut line_quantities = HashMap::new();
+    for item in items {
+        if !is_sku(&item.sku) {
+            problems.push(format!("invalid sku: {}", item.sku));
+        }
+        let line_quantity = line_quantities.entry(&item.sku).or_insert(0u64);
+        let was_within_limit = *line_quantity <= MAX_LINE_QUANTITY as u64;
+        *line_quantity += u64::from(item.quantity);
+        if was_within_limit && *line_quantity > MAX_LINE_QUANTITY as u64 {
+            problems.push(format!(
+                "quantity of {} can't be more than {}",
+                item.sku, MAX_LINE_QUANTITY
+            ));
+        }
+    }
+
+    if line_quantities.len() > max_distinct_items {
+        problems.push(format!(
+            "a cart can't hold more than {} different items",
+            max_distinct_items
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(BazaarError::BadRequest(problems.join("; ")))
+    }
+}
+
+fn is_sku(sku: &str) -> bool {
+    (1..=MAX_SKU_LENGTH).contains(&sku.len())
+        && sku
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(sku: &str, quantity: u32) -> UpdateCartItem {
+        UpdateCartItem {
+            sku: sku.to_string(),
+            quantity,
+            expected_price_per_unit: None,
+        }
+    }
+
+    #[test]
+    fn valid_items_are_accepted() {
+        let items = vec![item("12345678", 1), item("not-a-sku", 999)];
+        assert!(validate_cart_input(&items, 2).is_ok());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let items = vec![
+            item("", 1),
+            item("12345678", 1000),
+            item("bad sku", 1),
+            item("22345678", 1),
+        ];
+        match validate_cart_input(&items, 3) {
+            Err(BazaarError::BadRequest(details)) => assert_eq!(
+                details,
+                "invalid sku: ; quantity of 12345678 can't be more than 999; invalid sku: bad sku; \
+                 a cart can't hold more than 3 different items"
+            ),
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repeated_skus_are_added_together_before_checking_the_quantity() {
+        let items = vec![item("12345678", 500), item("12345678", 500)];
+        match validate_cart_input(&items, 1) {
+            Err(BazaarError::BadRequest(details)) => {
+                assert_eq!(details, "quantity of 12345678 can't be more than 999")
+            }
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repeated_skus_only_count_once_towards_the_line_limit() {
+        let items = vec![item("12345678", 1), item("12345678", 2)];
+        assert!(validate_cart_input(&items, 1).is_ok());
+    }
+}
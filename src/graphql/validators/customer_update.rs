@@ -2,15 +2,89 @@ use async_graphql::{
     validators::{Email, InputValueValidator},
     Value,
 };
+use chrono_tz::Tz;
+use std::{collections::HashSet, str::FromStr};
+use url::Url;
+
+use crate::models::Currency;
 
 pub struct ValidCustomerUpdateType {}
 
+/// Only allows absolute `http` or `https` URLs
+pub struct HttpUrl {}
+
+impl InputValueValidator for HttpUrl {
+    fn is_valid(&self, value: &Value) -> Result<(), String> {
+        if let Value::String(value) = value {
+            match Url::parse(value) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+                _ => Err(format!("invalid url: {}", value)),
+            }
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Only allows well formed BCP 47 language tags (ie. `fr` or `fr-FR`), made up of a
+/// language, optionally followed by a script, a region and any variants
+pub struct LanguageTag {}
+
+impl InputValueValidator for LanguageTag {
+    fn is_valid(&self, value: &Value) -> Result<(), String> {
+        if let Value::String(value) = value {
+            if is_language_tag(value) {
+                Ok(())
+            } else {
+                Err(format!("invalid language tag: {}", value))
+            }
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-').peekable();
+    let is_alpha = |s: &str, len: std::ops::RangeInclusive<usize>| {
+        len.contains(&s.len()) && s.bytes().all(|b| b.is_ascii_alphabetic())
+    };
+    match subtags.next() {
+        Some(language) if is_alpha(language, 2..=3) || is_alpha(language, 5..=8) => {}
+        _ => return false,
+    }
+    if subtags.peek().map_or(false, |s| is_alpha(s, 4..=4)) {
+        subtags.next();
+    }
+    if subtags.peek().map_or(false, |s| {
+        is_alpha(s, 2..=2) || (s.len() == 3 && s.bytes().all(|b| b.is_ascii_digit()))
+    }) {
+        subtags.next();
+    }
+    subtags.all(|variant| {
+        variant.bytes().all(|b| b.is_ascii_alphanumeric())
+            && ((5..=8).contains(&variant.len())
+                || (variant.len() == 4 && variant.as_bytes()[0].is_ascii_digit()))
+    })
+}
+
 impl InputValueValidator for ValidCustomerUpdateType {
     fn is_valid(&self, value: &Value) -> Result<(), String> {
         match value {
             Value::List(list) => {
+                if list.is_empty() {
+                    return Err("update must contain at least one change".to_string());
+                }
+                let mut keys = HashSet::new();
                 for item in list {
                     let _ = self.is_valid(item)?;
+                    if let Value::Object(obj) = item {
+                        if let Some(Value::String(key)) = obj.get("key") {
+                            if !keys.insert(key) {
+                                return Err(format!("duplicate key: {}", key));
+                            }
+                        }
+                    }
                 }
                 Ok(())
             }
@@ -21,6 +95,10 @@ impl InputValueValidator for ValidCustomerUpdateType {
                             "firstName" => key,
                             "lastName" => key,
                             "email" => key,
+                            "avatarUrl" => key,
+                            "preferredCurrency" => key,
+                            "timezone" => key,
+                            "preferredLanguage" => key,
                             invalid_key => return Err(format!("invalid key: {}", invalid_key)),
                         },
                         _ => return Err("invalid object provided".to_string()),
@@ -37,6 +115,28 @@ impl InputValueValidator for ValidCustomerUpdateType {
                                 let email = Email {};
                                 let _ = email.is_valid(&value)?;
                             }
+                            "avatarUrl" => {
+                                let url = HttpUrl {};
+                                let _ = url.is_valid(&value)?;
+                            }
+                            "timezone" => {
+                                if let Value::String(timezone) = value {
+                                    if Tz::from_str(timezone).is_err() {
+                                        return Err(format!("invalid timezone: {}", timezone));
+                                    }
+                                }
+                            }
+                            "preferredLanguage" => {
+                                let language = LanguageTag {};
+                                let _ = language.is_valid(&value)?;
+                            }
+                            "preferredCurrency" => {
+                                if let Value::String(currency) = value {
+                                    if Currency::from_str(currency).is_err() {
+                                        return Err(format!("invalid currency: {}", currency));
+                                    }
+                                }
+                            }
                             _ => return Err("invalid value passed into update".to_string()),
                         },
                         _ => return Err("invalid value type passed into update".to_string()),
@@ -49,3 +149,89 @@ impl InputValueValidator for ValidCustomerUpdateType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn validate(update: serde_json::Value) -> Result<(), String> {
+        let value = Value::from_json(update).expect("should be valid graphql value");
+        ValidCustomerUpdateType {}.is_valid(&value)
+    }
+
+    #[test]
+    fn valid_updates_are_accepted() {
+        let result = validate(json!([
+            { "key": "firstName", "value": "Bruce" },
+            { "key": "email", "value": "bruce@wayne.com" }
+        ]));
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn https_avatar_urls_are_accepted() {
+        let result = validate(json!([
+            { "key": "avatarUrl", "value": "https://cdn.bazaar.com/avatars/1.png" }
+        ]));
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn non_http_avatar_urls_are_rejected() {
+        for url in &["javascript:alert(1)", "not a url", "ftp://bazaar.com/1.png"] {
+            let result = validate(json!([{ "key": "avatarUrl", "value": url }]));
+            assert_eq!(result, Err(format!("invalid url: {}", url)));
+        }
+    }
+
+    #[test]
+    fn only_supported_currencies_are_accepted() {
+        let result = validate(json!([{ "key": "preferredCurrency", "value": "USD" }]));
+        assert_eq!(result, Ok(()));
+
+        let result = validate(json!([{ "key": "preferredCurrency", "value": "DOGE" }]));
+        assert_eq!(result, Err("invalid currency: DOGE".to_string()));
+    }
+
+    #[test]
+    fn only_iana_timezones_are_accepted() {
+        let result = validate(json!([{ "key": "timezone", "value": "Europe/London" }]));
+        assert_eq!(result, Ok(()));
+
+        let result = validate(json!([{ "key": "timezone", "value": "Not/AZone" }]));
+        assert_eq!(result, Err("invalid timezone: Not/AZone".to_string()));
+    }
+
+    #[test]
+    fn only_well_formed_language_tags_are_accepted() {
+        for tag in &["fr", "fr-FR", "zh-Hant-TW", "es-419", "de-CH-1901"] {
+            let result = validate(json!([{ "key": "preferredLanguage", "value": tag }]));
+            assert_eq!(result, Ok(()));
+        }
+
+        for tag in &["", "f", "fr_FR", "fr-", "fr-F", "fr-FRA1"] {
+            let result = validate(json!([{ "key": "preferredLanguage", "value": tag }]));
+            assert_eq!(result, Err(format!("invalid language tag: {}", tag)));
+        }
+    }
+
+    #[test]
+    fn empty_updates_are_rejected() {
+        let result = validate(json!([]));
+        assert_eq!(
+            result,
+            Err("update must contain at least one change".to_string())
+        );
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        let result = validate(json!([
+            { "key": "firstName", "value": "Bruce" },
+            { "key": "lastName", "value": "Wayne" },
+            { "key": "firstName", "value": "Batman" }
+        ]));
+        assert_eq!(result, Err("duplicate key: firstName".to_string()));
+    }
+}
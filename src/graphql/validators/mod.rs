@@ -1,3 +1,5 @@
+mod cart_input;
 mod customer_update;
 
+pub use cart_input::validate_cart_input;
 pub use customer_update::ValidCustomerUpdateType;
@@ -0,0 +1,137 @@
+use async_trait::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio02Executor,
+};
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::{configuration::EmailSettings, BazaarError, IntegrationErrorKind, Result};
+
+/// The transactional emails Bazaar sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailKind {
+    Welcome,
+    EmailChangeVerification,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub kind: EmailKind,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+    pub fn welcome(to: &str, first_name: &str) -> Self {
+        Self {
+            kind: EmailKind::Welcome,
+            to: to.to_string(),
+            subject: "Welcome to Bazaar".to_string(),
+            body: format!(
+                "Hi {},\n\nThanks for signing up to Bazaar, your account is ready to use.",
+                first_name
+            ),
+        }
+    }
+
+    /// Sent to the new address, the token is used to confirm the change
+    pub fn email_change_verification(to: &str, token: &str) -> Self {
+        Self {
+            kind: EmailKind::EmailChangeVerification,
+            to: to.to_string(),
+            subject: "Confirm your new email address".to_string(),
+            body: format!(
+                "Use the code below to confirm this is your new email address for Bazaar.\n\n{}",
+                token
+            ),
+        }
+    }
+}
+
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: Email) -> Result<()>;
+}
+
+/// Sends the email in the background, a failure to send is logged rather than failing
+/// whatever triggered the email
+pub fn dispatch(sender: &Arc<dyn EmailSender>, email: Email) {
+    let sender = Arc::clone(sender);
+    tokio::spawn(async move {
+        let kind = email.kind;
+        if let Err(err) = sender.send(email).await {
+            error!(?err, ?kind, "failed to send email");
+        }
+    });
+}
+
+/// The SMTP sender when `email.enabled` is set, otherwise emails are only logged
+pub fn email_sender_from_settings(settings: &EmailSettings) -> Result<Arc<dyn EmailSender>> {
+    if settings.enabled {
+        Ok(Arc::new(SmtpEmailSender::new(settings)?))
+    } else {
+        Ok(Arc::new(NoopEmailSender))
+    }
+}
+
+pub struct SmtpEmailSender {
+    transport: AsyncSmtpTransport<Tokio02Executor>,
+    from: Mailbox,
+}
+
+impl SmtpEmailSender {
+    pub fn new(settings: &EmailSettings) -> Result<Self> {
+        let from = settings.from_address.parse().map_err(|err| {
+            BazaarError::ConfigError(format!("invalid email.from_address: {}", err))
+        })?;
+        let transport = AsyncSmtpTransport::<Tokio02Executor>::relay(&settings.smtp_host)
+            .map_err(|err| BazaarError::ConfigError(format!("invalid email.smtp_host: {}", err)))?
+            .port(settings.smtp_port)
+            .credentials(Credentials::new(
+                settings.smtp_username.clone(),
+                settings.smtp_password.clone(),
+            ))
+            .build();
+        Ok(Self { transport, from })
+    }
+}
+
+#[async_trait]
+impl EmailSender for SmtpEmailSender {
+    #[tracing::instrument(skip(self, email), fields(kind = ?email.kind))]
+    async fn send(&self, email: Email) -> Result<()> {
+        let to = email.to.parse::<Mailbox>().map_err(|err| {
+            error!(?err, "attempted to send an email to an invalid address");
+            BazaarError::BadRequest(format!("invalid email address: {}", email.to))
+        })?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject)
+            .body(email.body)
+            .map_err(|err| {
+                error!(?err, "failed to build email");
+                BazaarError::UnexpectedError
+            })?;
+        self.transport.send(message).await.map_err(|err| {
+            error!(?err, "smtp server rejected email");
+            BazaarError::IntegrationError(IntegrationErrorKind::Request, err.to_string())
+        })?;
+        Ok(())
+    }
+}
+
+/// Doesn't send anything, used when sending emails is disabled
+#[derive(Debug, Default)]
+pub struct NoopEmailSender;
+
+#[async_trait]
+impl EmailSender for NoopEmailSender {
+    #[tracing::instrument(skip(self, email), fields(kind = ?email.kind))]
+    async fn send(&self, email: Email) -> Result<()> {
+        info!("email sending is disabled, not sending email");
+        Ok(())
+    }
+}
@@ -5,14 +5,19 @@ mod constants;
 pub mod database;
 mod error;
 mod graphql;
+pub mod integrations;
+mod metrics;
 pub mod models;
 pub mod routes;
+mod shutdown;
 
 pub use build_app::{build_app, generate_schema};
 pub use configuration::{get_configuration, Environment};
 pub use constants::*;
-pub use error::BazaarError;
+pub use error::{BazaarError, IntegrationErrorKind};
 pub use graphql::{BazaarSchema, MutationRoot, QueryRoot};
+pub use metrics::{AppMetrics, AuthOperation, AuthOutcome};
+pub use shutdown::{shutdown_on_signal, termination_signal, SHUTDOWN_TIMEOUT_SECONDS};
 
 pub type Result<T> = std::result::Result<T, BazaarError>;
 pub type AppConfig = std::sync::Arc<configuration::Configuration>;
@@ -15,7 +15,10 @@ use sqlx::postgres::PgPoolOptions;
 use std::net::TcpListener;
 use std::sync::Arc;
 
-use bazaar::{build_app, get_configuration};
+use bazaar::{
+    auth::validate_keys, build_app, get_configuration,
+    integrations::email::email_sender_from_settings, shutdown_on_signal, termination_signal,
+};
 
 #[actix_rt::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
@@ -30,13 +33,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
 
     global::set_text_map_propagator(TraceContextPropagator::new());
 
-    let (tracer, _uninstall) = opentelemetry_otlp::new_pipeline()
+    let (tracer, uninstall) = opentelemetry_otlp::new_pipeline()
         .with_endpoint(configuration.get_telemetry_agent_endpoint())
-        .with_trace_config(trace::config().with_resource(Resource::new(vec![
-            SERVICE_NAME.string(app_name),
-            SERVICE_NAMESPACE.string("bazaar"),
-            DEPLOYMENT_ENVIRONMENT.string(configuration.env.to_string()),
-        ])))
+        .with_trace_config(
+            trace::config()
+                .with_default_sampler(configuration.get_telemetry_sampler())
+                .with_resource(Resource::new(vec![
+                    SERVICE_NAME.string(app_name),
+                    SERVICE_NAMESPACE.string("bazaar"),
+                    DEPLOYMENT_ENVIRONMENT.string(configuration.env.to_string()),
+                ])),
+        )
         .install()?;
 
     let otel_layer = OpenTelemetryLayer::new(tracer);
@@ -46,7 +53,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
         .with(otel_layer);
     set_global_default(registry)?;
 
+    // Validated once tracing is set up so the reason a key was rejected is logged
+    validate_keys(configuration.auth.min_secret_key_length)?;
+
     let connection = PgPoolOptions::new()
+        .max_connections(configuration.database.max_connections)
         .connect_timeout(std::time::Duration::from_secs(2))
         .connect_with(configuration.database.with_db())
         .await
@@ -54,6 +65,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
 
     let listener = TcpListener::bind(configuration.get_addr())?;
 
-    build_app(listener, connection, configuration)?.await?;
+    let email_sender = email_sender_from_settings(&configuration.email)?;
+
+    let server = build_app(listener, connection, configuration, email_sender)?;
+    actix_rt::spawn(shutdown_on_signal(server.clone(), termination_signal()));
+    server.await?;
+
+    // Dropping the guard shuts down the tracing pipeline, flushing any spans that
+    // haven't been exported yet
+    drop(uninstall);
     Ok(())
 }
@@ -0,0 +1,139 @@
+use prometheus::{Encoder, IntCounterVec, Opts, Registry, TextEncoder};
+
+/// The Prometheus metrics recorded by the application, exposed on `/metrics`
+#[derive(Clone)]
+pub struct AppMetrics {
+    registry: Registry,
+    auth_attempts: IntCounterVec,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AuthOperation {
+    Login,
+    SignUp,
+    Refresh,
+    AnonymousLogin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthOutcome {
+    Success,
+    IncorrectCredentials,
+    TwoFactorRequired,
+    AlreadyAuthenticated,
+    InvalidToken,
+    Error,
+}
+
+impl AppMetrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("bazaar".to_string()), None)?;
+        let auth_attempts = IntCounterVec::new(
+            Opts::new(
+                "auth_attempts_total",
+                "Authentication attempts by operation and outcome",
+            ),
+            &["operation", "outcome"],
+        )?;
+        registry.register(Box::new(auth_attempts.clone()))?;
+        Ok(Self {
+            registry,
+            auth_attempts,
+        })
+    }
+
+    /// Starts recording an authentication attempt, unless it's marked otherwise the
+    /// attempt is recorded as an `error` once it's dropped
+    pub fn auth_attempt(&self, operation: AuthOperation) -> AuthAttempt<'_> {
+        AuthAttempt {
+            counter: &self.auth_attempts,
+            operation,
+            outcome: AuthOutcome::Error,
+        }
+    }
+
+    /// Renders all of the metrics in the Prometheus text format
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+pub struct AuthAttempt<'a> {
+    counter: &'a IntCounterVec,
+    operation: AuthOperation,
+    outcome: AuthOutcome,
+}
+
+impl AuthAttempt<'_> {
+    pub fn succeeded(&mut self) {
+        self.outcome = AuthOutcome::Success;
+    }
+
+    pub fn failed(&mut self, outcome: AuthOutcome) {
+        self.outcome = outcome;
+    }
+}
+
+impl Drop for AuthAttempt<'_> {
+    fn drop(&mut self) {
+        self.counter
+            .with_label_values(&[self.operation.as_str(), self.outcome.as_str()])
+            .inc();
+    }
+}
+
+impl AuthOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthOperation::Login => "login",
+            AuthOperation::SignUp => "sign_up",
+            AuthOperation::Refresh => "refresh",
+            AuthOperation::AnonymousLogin => "anonymous_login",
+        }
+    }
+}
+
+impl AuthOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthOutcome::Success => "success",
+            AuthOutcome::IncorrectCredentials => "incorrect_credentials",
+            AuthOutcome::TwoFactorRequired => "two_factor_required",
+            AuthOutcome::AlreadyAuthenticated => "already_authenticated",
+            AuthOutcome::InvalidToken => "invalid_token",
+            AuthOutcome::Error => "error",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attempts_are_recorded_with_their_outcome() {
+        let metrics = AppMetrics::new().unwrap();
+        {
+            let mut attempt = metrics.auth_attempt(AuthOperation::Login);
+            attempt.failed(AuthOutcome::IncorrectCredentials);
+        }
+        {
+            let mut attempt = metrics.auth_attempt(AuthOperation::Login);
+            attempt.succeeded();
+        }
+        {
+            let _attempt = metrics.auth_attempt(AuthOperation::Refresh);
+        }
+
+        let rendered = metrics.render().unwrap();
+        assert!(rendered.contains(
+            r#"bazaar_auth_attempts_total{operation="login",outcome="incorrect_credentials"} 1"#
+        ));
+        assert!(rendered
+            .contains(r#"bazaar_auth_attempts_total{operation="login",outcome="success"} 1"#));
+        assert!(rendered
+            .contains(r#"bazaar_auth_attempts_total{operation="refresh",outcome="error"} 1"#));
+    }
+}
@@ -0,0 +1,85 @@
+use async_graphql::{Enum, Object};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{database::AccountEventRepository, Result};
+
+/// The security related things that can happen to a customer's account
+#[derive(Debug, Enum, Copy, Clone, Eq, PartialEq, sqlx::Type)]
+#[sqlx(rename = "account_event_kind", rename_all = "UPPERCASE")]
+pub enum AccountEventKind {
+    Login,
+    #[sqlx(rename = "EMAIL_CHANGE")]
+    EmailChange,
+    #[sqlx(rename = "TOKENS_INVALIDATED")]
+    TokensInvalidated,
+}
+
+/// Where the request that caused an account event came from, attached to every
+/// GraphQL request
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RequestOrigin {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct AccountEvent {
+    pub id: Uuid,
+    pub customer_id: Uuid,
+    pub kind: AccountEventKind,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AccountEvent {
+    /// Failing to record an event shouldn't fail whatever the customer was doing, so
+    /// any error is only logged
+    #[tracing::instrument(skip(origin, pool))]
+    pub async fn record<DB: AccountEventRepository>(
+        customer_id: Uuid,
+        kind: AccountEventKind,
+        origin: &RequestOrigin,
+        pool: &PgPool,
+    ) {
+        if let Err(err) = DB::create(Uuid::new_v4(), customer_id, kind, origin, pool).await {
+            error!(?err, ?kind, "failed to record account event");
+        }
+    }
+
+    #[tracing::instrument(skip(pool))]
+    pub async fn find_by_customer_id<DB: AccountEventRepository>(
+        customer_id: Uuid,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>> {
+        DB::find_by_customer_id(customer_id, pool).await
+    }
+}
+
+// `customer_id` is the **private** ID so it should never be returned by this resolver
+
+#[Object]
+impl AccountEvent {
+    async fn id(&self) -> Uuid {
+        self.id
+    }
+
+    async fn kind(&self) -> AccountEventKind {
+        self.kind
+    }
+
+    async fn ip_address(&self) -> Option<String> {
+        self.ip_address.clone()
+    }
+
+    async fn user_agent(&self) -> Option<String> {
+        self.user_agent.clone()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
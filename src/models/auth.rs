@@ -2,7 +2,9 @@ use serde::Deserialize;
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::{database::AuthRepository, Result};
+use tracing::warn;
+
+use crate::{auth, database::AuthRepository, BazaarError, Result};
 
 #[derive(Deserialize)]
 #[serde(rename_all(serialize = "snake_case", deserialize = "camelCase"))]
@@ -10,6 +12,9 @@ pub struct AuthCustomer {
     pub public_id: Uuid,
     pub(crate) id: Uuid,
     pub hashed_password: String,
+    /// Encrypted, see `auth::decrypt_two_factor_secret`
+    pub two_factor_secret: Option<Vec<u8>>,
+    pub two_factor_enabled: bool,
 }
 
 impl AuthCustomer {
@@ -23,4 +28,21 @@ impl AuthCustomer {
     pub async fn find_by_email<DB: AuthRepository>(email: &str, pool: &PgPool) -> Result<Self> {
         DB::get_auth_customer(email, pool).await
     }
+
+    /// Checks the customer's two factor code if they have it enabled, a code is only
+    /// accepted once
+    #[tracing::instrument(skip(self, code, pool), fields(id = ?self.id))]
+    pub async fn verify_second_factor<DB: AuthRepository>(
+        &self,
+        code: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<()> {
+        if let Some(step) = auth::verify_second_factor(self, code)? {
+            if !DB::use_two_factor_step(self.id, step, pool).await? {
+                warn!(step, "two factor code has already been used");
+                return Err(BazaarError::IncorrectCredentials);
+            }
+        }
+        Ok(())
+    }
 }
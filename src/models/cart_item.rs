@@ -1,12 +1,23 @@
-use async_graphql::{InputObject, SimpleObject};
+use async_graphql::{InputObject, Object};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use std::hash::{Hash, Hasher};
+use std::{
+    collections::HashSet,
+    hash::{Hash, Hasher},
+};
 use tracing::error;
 
-use crate::{database::CartItemRepository, Result};
+use crate::{database::CartItemRepository, BazaarError, Result};
 
-#[derive(Debug, SimpleObject, Deserialize, Clone)]
+/// The most of a single item that can be in a cart
+pub const MAX_LINE_QUANTITY: i32 = 999;
+
+/// How far the price a customer expected to pay can drift from the current price
+/// before it's considered a mismatch
+const PRICE_TOLERANCE: f64 = 0.005;
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct CartItem {
     pub sku: String,
     pub quantity: i32,
@@ -15,12 +26,27 @@ pub struct CartItem {
     pub description: String,
     pub img_src: String,
     pub tags: Vec<String>,
+    pub available: i32,
+    pub weight_grams: i32,
+    pub added_at: Option<DateTime<Utc>>,
+    pub gift_message: Option<String>,
 }
 
 #[derive(Debug, InputObject, Serialize, Deserialize, Clone)]
 pub struct UpdateCartItem {
     pub sku: String,
     pub quantity: u32,
+    /// The price per unit the customer was shown, if provided the item will only
+    /// be added if it still matches the current price
+    pub expected_price_per_unit: Option<f64>,
+}
+
+/// A gift message for one of the lines already in the cart
+#[derive(Debug, InputObject, Clone)]
+pub struct GiftMessage {
+    pub sku: String,
+    /// Leaving the message out (or leaving it blank) removes any existing message
+    pub message: Option<String>,
 }
 
 impl CartItem {
@@ -36,28 +62,206 @@ impl CartItem {
 
         let items = DB::find_multiple(&ids, pool).await?;
 
-        let mut internal_items = internal_items.to_vec();
-        internal_items.sort_by(|a, b| a.sku.cmp(&b.sku));
-
+        // Items can be removed from the catalogue while they're still in a cart, so
+        // match them up by sku rather than by position
         let result = items
             .into_iter()
-            .zip(internal_items.into_iter())
-            .filter_map(|(mut item, mapper)| {
-                if item.sku != mapper.sku {
-                    error!(
-                        item_sku = ?item.sku,
-                        mapper_sku = ?mapper.sku,
-                        "expected skus to match but they did not"
-                    );
-                    return None;
-                }
+            .filter_map(|mut item| {
+                let mapper = match internal_items.iter().find(|i| i.sku == item.sku) {
+                    Some(mapper) => mapper,
+                    None => {
+                        error!(item_sku = ?item.sku, "found an item that wasn't requested");
+                        return None;
+                    }
+                };
                 item.quantity = mapper.quantity;
+                item.added_at = mapper.added_at;
+                item.gift_message = mapper.gift_message.clone();
                 Some(item)
             })
             .collect();
 
         Ok(result)
     }
+
+    /// Items which share a tag with any of the given items, that aren't already
+    /// one of them
+    #[tracing::instrument(skip(items, pool))]
+    pub async fn find_recommended<DB: CartItemRepository>(
+        items: &[CartItem],
+        limit: u32,
+        pool: &PgPool,
+    ) -> Result<Vec<CartItem>> {
+        let tags = items
+            .iter()
+            .flat_map(|i| i.tags.iter().cloned())
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect::<Vec<String>>();
+        if tags.is_empty() {
+            return Ok(Vec::new());
+        }
+        let skus = items.iter().map(|i| i.sku.clone()).collect::<Vec<String>>();
+        DB::find_by_tags(&tags, &skus, i64::from(limit), pool).await
+    }
+
+    /// What this line contributes to the cart total
+    pub fn line_total(&self) -> f64 {
+        self.price_per_unit * self.quantity as f64
+    }
+
+    /// What this line contributes to the weight of the cart
+    pub fn line_weight_grams(&self) -> i64 {
+        i64::from(self.weight_grams) * i64::from(self.quantity)
+    }
+
+    /// Whether there is enough stock to fulfil the quantity in the cart
+    pub fn is_in_stock(&self) -> bool {
+        self.available > 0 && self.quantity <= self.available
+    }
+
+    /// Checks that every provided sku refers to an item that exists
+    #[tracing::instrument(skip(pool))]
+    pub async fn verify_skus_exist<DB: CartItemRepository>(
+        items: &[UpdateCartItem],
+        pool: &PgPool,
+    ) -> Result<()> {
+        let skus = items.iter().map(|i| i.sku.clone()).collect::<Vec<String>>();
+        let found = DB::find_multiple(&skus, pool).await?;
+        let missing = items
+            .iter()
+            .map(|i| i.sku.as_str())
+            .filter(|sku| !found.iter().any(|i| &i.sku == sku))
+            .collect::<Vec<&str>>();
+        if !missing.is_empty() {
+            error!(?missing, "attempted to use skus that don't exist");
+            return Err(BazaarError::ItemNotFound(missing.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// An item with an expected price that doesn't exist is reported as not found, rather
+    /// than the price going unchecked
+    #[tracing::instrument(skip(pool))]
+    pub async fn verify_expected_prices<DB: CartItemRepository>(
+        items: &[UpdateCartItem],
+        pool: &PgPool,
+    ) -> Result<()> {
+        let skus = items
+            .iter()
+            .filter(|i| i.expected_price_per_unit.is_some())
+            .map(|i| i.sku.clone())
+            .collect::<Vec<String>>();
+        if skus.is_empty() {
+            return Ok(());
+        }
+
+        let current_items = DB::find_multiple(&skus, pool).await?;
+        for item in items {
+            let expected_price = match item.expected_price_per_unit {
+                Some(price) => price,
+                None => continue,
+            };
+            let current = match current_items.iter().find(|i| i.sku == item.sku) {
+                Some(current) => current,
+                None => {
+                    error!(sku = ?item.sku, "expected a price for an item that doesn't exist");
+                    return Err(BazaarError::ItemNotFound(item.sku.clone()));
+                }
+            };
+            if (current.price_per_unit - expected_price).abs() > PRICE_TOLERANCE {
+                error!(
+                    sku = ?item.sku,
+                    expected_price,
+                    current_price = current.price_per_unit,
+                    "expected price did not match the current price"
+                );
+                return Err(BazaarError::BadRequest(format!(
+                    "Price of {} has changed, current price is {}",
+                    item.sku, current.price_per_unit
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adjusts the stock of an item by `delta`, returning the new stock level
+    #[tracing::instrument(skip(pool))]
+    pub async fn adjust_stock<DB: CartItemRepository>(
+        sku: &str,
+        delta: i32,
+        pool: &PgPool,
+    ) -> Result<i32> {
+        if let Some(stock) = DB::adjust_stock(sku, delta, pool).await? {
+            return Ok(stock);
+        }
+        if DB::find_multiple(&[sku.to_string()], pool)
+            .await?
+            .is_empty()
+        {
+            return Err(BazaarError::ItemNotFound(sku.to_string()));
+        }
+        error!(sku, delta, "attempted to reduce stock below zero");
+        Err(BazaarError::BadRequest(
+            "Stock can't be reduced below zero".to_string(),
+        ))
+    }
+}
+
+#[Object]
+impl CartItem {
+    async fn sku(&self) -> String {
+        self.sku.clone()
+    }
+
+    async fn quantity(&self) -> i32 {
+        self.quantity
+    }
+
+    async fn price_per_unit(&self) -> f64 {
+        self.price_per_unit
+    }
+
+    /// The price per unit multiplied by the quantity, as used to total the cart
+    async fn line_total(&self) -> f64 {
+        self.line_total()
+    }
+
+    async fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn description(&self) -> String {
+        self.description.clone()
+    }
+
+    async fn img_src(&self) -> String {
+        self.img_src.clone()
+    }
+
+    async fn tags(&self) -> Vec<String> {
+        self.tags.clone()
+    }
+
+    /// The amount of this item currently in stock
+    async fn available(&self) -> i32 {
+        self.available
+    }
+
+    /// Whether there is enough stock to fulfil the quantity in the cart
+    async fn in_stock(&self) -> bool {
+        self.is_in_stock()
+    }
+
+    /// When the item was first added to the cart, items added before this was
+    /// tracked won't have one
+    async fn added_at(&self) -> Option<DateTime<Utc>> {
+        self.added_at
+    }
+
+    async fn gift_message(&self) -> Option<String> {
+        self.gift_message.clone()
+    }
 }
 
 // @TODO - Add in discounts struct
@@ -71,6 +275,11 @@ impl CartItem {
 pub struct InternalCartItem {
     pub sku: String,
     pub quantity: i32,
+    /// Set when the line is first created in the cart
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gift_message: Option<String>,
 }
 
 impl Hash for InternalCartItem {
@@ -89,7 +298,12 @@ impl Eq for InternalCartItem {}
 
 impl From<(String, i32)> for InternalCartItem {
     fn from((sku, quantity): (String, i32)) -> Self {
-        Self { sku, quantity }
+        Self {
+            sku,
+            quantity,
+            added_at: None,
+            gift_message: None,
+        }
     }
 }
 
@@ -98,6 +312,8 @@ impl From<UpdateCartItem> for InternalCartItem {
         Self {
             sku: item.sku,
             quantity: item.quantity as i32,
+            added_at: None,
+            gift_message: None,
         }
     }
 }
@@ -109,6 +325,8 @@ impl std::ops::Add for InternalCartItem {
         Self {
             sku: self.sku,
             quantity: self.quantity + other.quantity,
+            added_at: self.added_at,
+            gift_message: self.gift_message,
         }
     }
 }
@@ -120,6 +338,8 @@ impl std::ops::Sub for InternalCartItem {
         Self {
             sku: self.sku,
             quantity: self.quantity - other.quantity,
+            added_at: self.added_at,
+            gift_message: self.gift_message,
         }
     }
 }
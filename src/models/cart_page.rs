@@ -0,0 +1,52 @@
+use async_graphql::{validators::IntRange, Context, ErrorExtensions, Object};
+use tracing::error;
+
+use crate::{
+    database::CartItemDatabase,
+    graphql::extract_database_pool,
+    models::{CartItem, CartSummary, ShoppingCart},
+};
+
+/// Everything needed to render the cart page in a single request
+#[derive(Debug)]
+pub struct CartPage {
+    cart: ShoppingCart,
+}
+
+impl From<ShoppingCart> for CartPage {
+    fn from(cart: ShoppingCart) -> Self {
+        Self { cart }
+    }
+}
+
+#[Object]
+impl CartPage {
+    async fn cart(&self) -> &ShoppingCart {
+        &self.cart
+    }
+
+    async fn summary(&self) -> CartSummary {
+        CartSummary::from(&self.cart)
+    }
+
+    /// In stock items which share a tag with something in the cart
+    async fn recommendations(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 4, validator(IntRange(min = "1", max = "20")))] limit: u32,
+    ) -> async_graphql::Result<Vec<CartItem>> {
+        if self.cart.items.is_empty() {
+            return Ok(Vec::new());
+        }
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        let items = CartItem::find_multiple::<CartItemDatabase>(&self.cart.items, pool)
+            .await
+            .map_err(|e| e.extend())?;
+        CartItem::find_recommended::<CartItemDatabase>(&items, limit, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, cart_id = ?self.cart.id, "failed to find recommendations for the cart");
+                err.extend()
+            })
+    }
+}
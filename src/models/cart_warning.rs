@@ -0,0 +1,86 @@
+use async_graphql::Value;
+use serde::Serialize;
+use std::sync::Mutex;
+use tracing::error;
+
+/// A soft issue with an item in the cart, these shouldn't fail the request but the
+/// customer should be told about them
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CartWarning {
+    pub sku: String,
+    pub message: String,
+}
+
+/// Collects any warnings raised while resolving a single request, they're returned
+/// to the client under the `cartWarnings` response extension
+#[derive(Debug, Default)]
+pub struct CartWarnings(Mutex<Vec<CartWarning>>);
+
+impl CartWarning {
+    pub fn removed(sku: &str) -> Self {
+        Self {
+            sku: sku.to_string(),
+            message: "Item is no longer available".to_string(),
+        }
+    }
+
+    pub fn out_of_stock(sku: &str) -> Self {
+        Self {
+            sku: sku.to_string(),
+            message: "Not enough stock to fulfil the quantity in the cart".to_string(),
+        }
+    }
+}
+
+impl CartWarnings {
+    pub fn push(&self, warning: CartWarning) {
+        match self.0.lock() {
+            Ok(mut warnings) => {
+                // The same cart can be resolved more than once in a request
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+            }
+            Err(e) => error!(err = ?e, ?warning, "failed to record cart warning"),
+        }
+    }
+
+    /// Returns the warnings as a GraphQL value, if there are any
+    pub fn to_extension(&self) -> Option<Value> {
+        let warnings = self.0.lock().ok()?;
+        if warnings.is_empty() {
+            return None;
+        }
+        serde_json::to_value(&*warnings)
+            .ok()
+            .and_then(|value| Value::from_json(value).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_extension_is_returned_without_warnings() {
+        let warnings = CartWarnings::default();
+        assert!(warnings.to_extension().is_none());
+    }
+
+    #[test]
+    fn duplicate_warnings_are_only_recorded_once() {
+        let warnings = CartWarnings::default();
+        warnings.push(CartWarning::removed("12345678"));
+        warnings.push(CartWarning::removed("12345678"));
+
+        let value = warnings.to_extension().expect("should have warnings");
+        assert_eq!(
+            value.into_json().unwrap(),
+            serde_json::json!([{
+                "sku": "12345678",
+                "message": "Item is no longer available"
+            }])
+        );
+    }
+}
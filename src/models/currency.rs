@@ -9,3 +9,30 @@ pub enum Currency {
     GBP,
     USD,
 }
+
+impl Currency {
+    /// The number of decimal places in the currency's minor unit
+    pub fn minor_units(&self) -> u32 {
+        match self {
+            Currency::GBP | Currency::USD => 2,
+        }
+    }
+
+    /// Rounds an amount to the nearest minor unit of the currency
+    pub fn round(&self, amount: f64) -> f64 {
+        let factor = 10f64.powi(self.minor_units() as i32);
+        (amount * factor).round() / factor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_are_rounded_to_minor_units() {
+        assert_eq!(Currency::GBP.round(0.1 + 0.2), 0.3);
+        assert_eq!(Currency::USD.round(10.005), 10.01);
+        assert_eq!(Currency::USD.round(4.994), 4.99);
+    }
+}
@@ -2,13 +2,18 @@ use async_graphql::{Context, ErrorExtensions, InputObject, Object};
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use sqlx::PgPool;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::{
     auth,
-    database::{CustomerRepository, ShoppingCartDatabase, ShoppingCartRepository},
+    database::{
+        AuthRepository, CustomerDatabase, CustomerRepository, ShoppingCartDatabase,
+        ShoppingCartRepository,
+    },
+    graphql::extract_database_pool,
     models::{Currency, ShoppingCart},
-    Result,
+    BazaarError, Result, POLICY_VERSION,
 };
 
 #[derive(Debug, Deserialize)]
@@ -22,6 +27,19 @@ pub struct Customer {
     pub last_modified: DateTime<Utc>,
     pub cart_id: Uuid,
     pub refresh_token_count: i32,
+    pub is_admin: bool,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub marketing_opt_in: bool,
+    pub marketing_opt_in_at: Option<DateTime<Utc>>,
+    pub avatar_url: Option<String>,
+    pub timezone: Option<String>,
+    /// A BCP 47 language tag, ie. `fr-FR`
+    pub preferred_language: Option<String>,
+    pub preferred_currency: Currency,
+    /// When the customer accepted the terms and privacy policy, customers who signed
+    /// up before consent was recorded won't have one
+    pub consented_at: Option<DateTime<Utc>>,
+    pub consent_policy_version: Option<String>,
 }
 
 #[derive(InputObject, Debug, Deserialize)]
@@ -46,6 +64,7 @@ pub struct NewCustomer {
     pub hashed_password: String,
     pub first_name: String,
     pub last_name: String,
+    pub consent_policy_version: String,
 }
 
 impl Customer {
@@ -67,6 +86,21 @@ impl Customer {
         DB::find_by_email(email, pool).await
     }
 
+    /// Finds customers whose email or name contains `query`, the customers are
+    /// returned with their public ID
+    #[tracing::instrument(skip(pool))]
+    pub async fn search<DB: CustomerRepository>(
+        query: &str,
+        limit: u32,
+        offset: u32,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>> {
+        let pattern = format!("%{}%", escape_like_pattern(query));
+        DB::search(pattern, limit.into(), offset.into(), pool).await
+    }
+
+    /// Customers are recorded as consenting to the current `POLICY_VERSION`, so they
+    /// must have accepted it before being created
     #[tracing::instrument(
         name = "new_customer",
         skip(pool, email, password, first_name, last_name)
@@ -78,6 +112,7 @@ impl Customer {
         first_name: String,
         last_name: String,
         cart_id: Option<Uuid>,
+        currency: Currency,
         pool: &PgPool,
     ) -> Result<CustomerIds> {
         let public_id = Uuid::new_v4();
@@ -96,9 +131,10 @@ impl Customer {
             hashed_password,
             first_name,
             last_name,
+            consent_policy_version: POLICY_VERSION.to_string(),
         };
 
-        DB::create_new_user(new_customer, cart_id.is_none(), Currency::GBP, pool).await?;
+        DB::create_new_user(new_customer, cart_id.is_none(), currency, pool).await?;
         Ok(CustomerIds {
             public_id,
             id,
@@ -106,16 +142,53 @@ impl Customer {
         })
     }
 
+    /// A new email address is rejected if it already belongs to a customer, or another
+    /// customer is waiting to confirm it, as only one customer can ever hold it
     #[tracing::instrument(skip(pool, update))]
     pub async fn update<DB: CustomerRepository>(
         id: Uuid,
         update: Vec<CustomerUpdate>,
         pool: &PgPool,
     ) -> Result<Self> {
+        if let Some(email) = update.iter().find(|u| u.key.eq_ignore_ascii_case("email")) {
+            if DB::is_email_in_use(email.value.clone(), id, pool).await? {
+                return Err(BazaarError::BadRequest(
+                    "Email address is already in use".to_string(),
+                ));
+            }
+        }
         DB::update(id, update, pool).await?;
         DB::find_by_id(id, pool).await
     }
 
+    /// Email changes made through `update` are held as pending, they're only applied
+    /// once the customer confirms them with the token sent to the new address.
+    ///
+    /// The customer is returned with their public ID, alongside their private ID
+    #[tracing::instrument(skip(token, pool))]
+    pub async fn confirm_email_change<A: AuthRepository, C: CustomerRepository>(
+        token: &str,
+        leeway: u64,
+        pool: &PgPool,
+    ) -> Result<(Uuid, Self)> {
+        let claims = auth::decode_email_change_token(token, leeway)?;
+        let id = A::map_id(Some(claims.sub), pool)
+            .await?
+            .ok_or(BazaarError::NotFound)?;
+        if !C::confirm_pending_email(id, claims.email, pool).await? {
+            error!(
+                ?id,
+                "attempted to confirm an email change that isn't pending"
+            );
+            return Err(BazaarError::BadRequest(
+                "No matching email change is pending".to_string(),
+            ));
+        }
+        let mut customer = C::find_by_id(id, pool).await?;
+        customer.id = claims.sub;
+        Ok((id, customer))
+    }
+
     #[tracing::instrument(skip(pool))]
     pub async fn add_new_cart<C: CustomerRepository, SC: ShoppingCartRepository>(
         id: Uuid,
@@ -129,6 +202,39 @@ impl Customer {
         C::add_new_cart(id, cart_id, currency, pool).await
     }
 
+    /// Points the customer at a brand new known cart, for when the cart they
+    /// reference has been removed
+    #[tracing::instrument(skip(pool))]
+    pub async fn replace_missing_cart<C: CustomerRepository>(
+        id: Uuid,
+        currency: Currency,
+        pool: &PgPool,
+    ) -> Result<ShoppingCart> {
+        let cart_id = Uuid::new_v4();
+        C::add_new_cart(id, cart_id, currency, pool).await
+    }
+
+    /// `customers.cart_id` can drift from the cart that references the customer (ie. if
+    /// the cart was recreated). The cart is treated as the source of truth, so the
+    /// customer is pointed back at it
+    #[tracing::instrument(skip(pool))]
+    pub async fn reconcile_cart_id<DB: CustomerRepository>(
+        id: Uuid,
+        cart_id: Uuid,
+        pool: &PgPool,
+    ) -> Result<()> {
+        let current_cart_id = Customer::check_cart::<DB>(id, pool).await?;
+        if current_cart_id != cart_id {
+            warn!(
+                ?current_cart_id,
+                ?cart_id,
+                "customer's cart pointer has diverged from their cart, updating it"
+            );
+            DB::update_cart_id(id, cart_id, pool).await?;
+        }
+        Ok(())
+    }
+
     #[tracing::instrument(skip(pool))]
     pub async fn increment_refresh_token_counter<DB: CustomerRepository>(
         id: Uuid,
@@ -137,6 +243,48 @@ impl Customer {
         DB::increment_refresh_token_counter(id, pool).await
     }
 
+    #[tracing::instrument(skip(pool))]
+    pub async fn rotate_refresh_token_counter<DB: CustomerRepository>(
+        id: Uuid,
+        current_count: i32,
+        grace_until: DateTime<Utc>,
+        pool: &PgPool,
+    ) -> Result<Option<i32>> {
+        DB::rotate_refresh_token_counter(id, current_count, grace_until, pool).await
+    }
+
+    #[tracing::instrument(skip(pool))]
+    pub async fn is_refresh_token_count_valid<DB: CustomerRepository>(
+        id: Uuid,
+        count: i32,
+        pool: &PgPool,
+    ) -> Result<bool> {
+        DB::is_refresh_token_count_valid(id, count, pool).await
+    }
+
+    #[tracing::instrument(skip(pool))]
+    pub async fn touch_last_login<DB: CustomerRepository>(id: Uuid, pool: &PgPool) -> Result<()> {
+        DB::touch_last_login(id, pool).await
+    }
+
+    /// `marketing_opt_in_at` is only updated when the customer's consent changes
+    #[tracing::instrument(skip(pool))]
+    pub async fn set_marketing_consent<DB: CustomerRepository>(
+        id: Uuid,
+        opt_in: bool,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        if DB::set_marketing_consent(id, opt_in, pool).await? {
+            info!(opt_in, "customer changed their marketing consent");
+        }
+        DB::find_by_id(id, pool).await
+    }
+
+    #[tracing::instrument(skip(pool))]
+    pub async fn is_admin<DB: CustomerRepository>(id: Uuid, pool: &PgPool) -> Result<bool> {
+        DB::is_admin(id, pool).await
+    }
+
     #[tracing::instrument(skip(pool))]
     pub async fn fetch_refresh_token_counter<DB: CustomerRepository>(
         id: Uuid,
@@ -180,11 +328,67 @@ impl Customer {
         self.last_modified
     }
 
+    async fn avatar_url(&self) -> Option<String> {
+        self.avatar_url.clone()
+    }
+
+    /// The customer's IANA timezone, ie. `Europe/London`
+    async fn timezone(&self) -> Option<String> {
+        self.timezone.clone()
+    }
+
+    /// The customer's BCP 47 language tag, ie. `fr-FR`
+    async fn preferred_language(&self) -> Option<String> {
+        self.preferred_language.clone()
+    }
+
+    /// The currency new known carts are created in for this customer
+    async fn preferred_currency(&self) -> Currency {
+        self.preferred_currency
+    }
+
+    /// When the customer last logged in with their credentials
+    async fn last_login_at(&self) -> Option<DateTime<Utc>> {
+        self.last_login_at
+    }
+
+    async fn marketing_opt_in(&self) -> bool {
+        self.marketing_opt_in
+    }
+
+    /// When the customer last opted in to or out of marketing
+    async fn marketing_opt_in_at(&self) -> Option<DateTime<Utc>> {
+        self.marketing_opt_in_at
+    }
+
+    /// If the customer's cart no longer exists, a new empty cart is created for them
     async fn cart(&self, ctx: &Context<'_>) -> async_graphql::Result<ShoppingCart> {
-        let pool = ctx.data::<PgPool>()?;
-        ShoppingCart::find_by_id::<ShoppingCartDatabase>(self.cart_id, pool)
-            .await
-            .map_err(|e| e.extend())
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        match ShoppingCart::find_by_id::<ShoppingCartDatabase>(self.cart_id, pool).await {
+            Err(BazaarError::NotFound) => {
+                warn!(cart_id = ?self.cart_id, "customer's cart is missing, creating a new one");
+                // `self.id` may have already been swapped for the public id, so look the
+                // customer back up to get the private id the cart needs to reference
+                let customer =
+                    Customer::find_by_email::<CustomerDatabase>(self.email.clone(), pool)
+                        .await
+                        .map_err(|err| {
+                            error!(?err, "failed to look up customer to replace their cart");
+                            err.extend()
+                        })?;
+                Customer::replace_missing_cart::<CustomerDatabase>(
+                    customer.id,
+                    customer.preferred_currency,
+                    pool,
+                )
+                .await
+            }
+            result => result,
+        }
+        .map_err(|err| {
+            error!(?err, "failed to fetch customer's cart");
+            err.extend()
+        })
     }
 }
 
@@ -198,3 +402,26 @@ impl CustomerIds {
         self.id
     }
 }
+
+/// Escapes the characters `LIKE` treats as wildcards, so they're matched literally
+fn escape_like_pattern(query: &str) -> String {
+    let mut escaped = String::with_capacity(query.len());
+    for c in query.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like_pattern("bruce"), "bruce");
+        assert_eq!(escape_like_pattern("100%_\\"), "100\\%\\_\\\\");
+    }
+}
@@ -0,0 +1,241 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{Datelike, Duration, NaiveDate, Utc, Weekday};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+use crate::{
+    configuration::{LeadTime, ShippingSettings},
+    models::Currency,
+};
+
+#[derive(Debug, Enum, Copy, Clone, Eq, PartialEq)]
+pub enum ShippingMethod {
+    Standard,
+    Express,
+}
+
+impl ShippingMethod {
+    pub const ALL: [Self; 2] = [Self::Standard, Self::Express];
+}
+
+/// A shipping method the cart can be sent with, what it costs and when it would arrive
+#[derive(Debug, SimpleObject, PartialEq)]
+pub struct ShippingOption {
+    pub method: ShippingMethod,
+    pub price: f64,
+    pub currency: Currency,
+    pub estimated_delivery: DeliveryEstimate,
+}
+
+/// Works out which shipping methods a cart can be sent with
+pub struct ShippingCalculator<'a> {
+    settings: &'a ShippingSettings,
+}
+
+impl<'a> ShippingCalculator<'a> {
+    pub fn new(settings: &'a ShippingSettings) -> Self {
+        Self { settings }
+    }
+
+    /// Methods with a weight limit below `weight_grams` are left out
+    pub fn options(
+        &self,
+        weight_grams: u32,
+        currency: Currency,
+        today: NaiveDate,
+    ) -> Vec<ShippingOption> {
+        ShippingMethod::ALL
+            .iter()
+            .filter_map(|&method| {
+                let settings = self.settings.method(method);
+                if settings
+                    .max_weight_grams
+                    .map_or(false, |max| weight_grams > max)
+                {
+                    return None;
+                }
+                Some(ShippingOption {
+                    method,
+                    price: settings.price.in_currency(currency),
+                    currency,
+                    estimated_delivery: DeliveryEstimate::from_date(today, settings.lead_time()),
+                })
+            })
+            .collect()
+    }
+}
+
+/// The window of dates a delivery is expected to arrive within, in the
+/// customer's timezone
+#[derive(Debug, SimpleObject, PartialEq)]
+pub struct DeliveryEstimate {
+    pub earliest: NaiveDate,
+    pub latest: NaiveDate,
+}
+
+impl DeliveryEstimate {
+    /// Estimates from today's date in the given IANA timezone, falling back to UTC
+    /// when there isn't one (or it's not recognised)
+    pub fn from_now(timezone: Option<&str>, lead_time: LeadTime) -> Self {
+        Self::from_date(today_in(timezone), lead_time)
+    }
+
+    pub fn from_date(today: NaiveDate, lead_time: LeadTime) -> Self {
+        Self {
+            earliest: add_business_days(today, lead_time.min_business_days),
+            latest: add_business_days(today, lead_time.max_business_days),
+        }
+    }
+}
+
+/// Today's date in the given IANA timezone, falling back to UTC when there isn't one
+/// (or it's not recognised)
+pub fn today_in(timezone: Option<&str>) -> NaiveDate {
+    let tz = timezone
+        .and_then(|tz| Tz::from_str(tz).ok())
+        .unwrap_or(Tz::UTC);
+    Utc::now().with_timezone(&tz).date().naive_local()
+}
+
+/// Weekends aren't counted, public holidays aren't taken into account
+fn add_business_days(mut date: NaiveDate, days: u32) -> NaiveDate {
+    let mut remaining = days;
+    while remaining > 0 {
+        date = date + Duration::days(1);
+        if !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) {
+            remaining -= 1;
+        }
+    }
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{ShippingMethodSettings, ShippingPrices};
+
+    fn shipping() -> ShippingSettings {
+        ShippingSettings {
+            standard: ShippingMethodSettings {
+                min_business_days: 3,
+                max_business_days: 5,
+                price: ShippingPrices {
+                    gbp: 3.99,
+                    usd: 4.99,
+                },
+                max_weight_grams: None,
+            },
+            express: ShippingMethodSettings {
+                min_business_days: 1,
+                max_business_days: 2,
+                price: ShippingPrices {
+                    gbp: 9.99,
+                    usd: 12.99,
+                },
+                max_weight_grams: Some(5000),
+            },
+        }
+    }
+
+    #[test]
+    fn standard_delivery_uses_the_standard_lead_time() {
+        // A Monday
+        let today = NaiveDate::from_ymd(2021, 1, 4);
+        let lead_time = shipping().lead_time(ShippingMethod::Standard);
+
+        assert_eq!(
+            DeliveryEstimate::from_date(today, lead_time),
+            DeliveryEstimate {
+                earliest: NaiveDate::from_ymd(2021, 1, 7),
+                latest: NaiveDate::from_ymd(2021, 1, 11),
+            }
+        );
+    }
+
+    #[test]
+    fn express_delivery_uses_the_express_lead_time() {
+        let today = NaiveDate::from_ymd(2021, 1, 4);
+        let lead_time = shipping().lead_time(ShippingMethod::Express);
+
+        assert_eq!(
+            DeliveryEstimate::from_date(today, lead_time),
+            DeliveryEstimate {
+                earliest: NaiveDate::from_ymd(2021, 1, 5),
+                latest: NaiveDate::from_ymd(2021, 1, 6),
+            }
+        );
+    }
+
+    #[test]
+    fn every_method_is_available_for_a_light_cart() {
+        let today = NaiveDate::from_ymd(2021, 1, 4);
+        let settings = shipping();
+        let options = ShippingCalculator::new(&settings).options(5000, Currency::GBP, today);
+
+        assert_eq!(
+            options,
+            vec![
+                ShippingOption {
+                    method: ShippingMethod::Standard,
+                    price: 3.99,
+                    currency: Currency::GBP,
+                    estimated_delivery: DeliveryEstimate {
+                        earliest: NaiveDate::from_ymd(2021, 1, 7),
+                        latest: NaiveDate::from_ymd(2021, 1, 11),
+                    },
+                },
+                ShippingOption {
+                    method: ShippingMethod::Express,
+                    price: 9.99,
+                    currency: Currency::GBP,
+                    estimated_delivery: DeliveryEstimate {
+                        earliest: NaiveDate::from_ymd(2021, 1, 5),
+                        latest: NaiveDate::from_ymd(2021, 1, 6),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prices_are_in_the_carts_currency() {
+        let today = NaiveDate::from_ymd(2021, 1, 4);
+        let settings = shipping();
+        let prices = ShippingCalculator::new(&settings)
+            .options(0, Currency::USD, today)
+            .into_iter()
+            .map(|option| (option.price, option.currency))
+            .collect::<Vec<_>>();
+
+        assert_eq!(prices, vec![(4.99, Currency::USD), (12.99, Currency::USD)]);
+    }
+
+    #[test]
+    fn methods_with_a_lower_weight_limit_are_excluded() {
+        let today = NaiveDate::from_ymd(2021, 1, 4);
+        let settings = shipping();
+        let methods = ShippingCalculator::new(&settings)
+            .options(5001, Currency::GBP, today)
+            .into_iter()
+            .map(|option| option.method)
+            .collect::<Vec<_>>();
+
+        assert_eq!(methods, vec![ShippingMethod::Standard]);
+    }
+
+    #[test]
+    fn weekends_are_skipped() {
+        // A Friday
+        let today = NaiveDate::from_ymd(2021, 1, 8);
+        assert_eq!(
+            add_business_days(today, 1),
+            NaiveDate::from_ymd(2021, 1, 11)
+        );
+        // A Saturday
+        let today = NaiveDate::from_ymd(2021, 1, 9);
+        assert_eq!(
+            add_business_days(today, 1),
+            NaiveDate::from_ymd(2021, 1, 11)
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use async_graphql::{resolver_utils::EnumType, Object, SimpleObject};
+
+use crate::models::{shopping_cart::CartType, Currency};
+
+/// A single variant of one of the API's enums, along with how it should be displayed
+#[derive(Debug, SimpleObject)]
+pub struct EnumVariant {
+    /// The value as it appears in the schema, ie. `GBP`
+    pub value: String,
+    /// A human readable name for the value
+    pub label: String,
+}
+
+/// Enums which can describe their variants to clients
+trait Described: EnumType {
+    fn label(&self) -> &'static str;
+
+    fn variants() -> Vec<EnumVariant> {
+        Self::items()
+            .iter()
+            .map(|item| EnumVariant {
+                value: item.name.to_string(),
+                label: item.value.label().to_string(),
+            })
+            .collect()
+    }
+}
+
+impl Described for CartType {
+    fn label(&self) -> &'static str {
+        match self {
+            CartType::Anonymous => "Anonymous",
+            CartType::Known => "Known",
+        }
+    }
+}
+
+impl Described for Currency {
+    fn label(&self) -> &'static str {
+        match self {
+            Currency::GBP => "British Pound",
+            Currency::USD => "US Dollar",
+        }
+    }
+}
+
+/// The variants of the enums used throughout the API, so clients don't need to
+/// hardcode them
+pub struct Metadata;
+
+#[Object]
+impl Metadata {
+    async fn cart_types(&self) -> Vec<EnumVariant> {
+        CartType::variants()
+    }
+
+    async fn currencies(&self) -> Vec<EnumVariant> {
+        Currency::variants()
+    }
+}
@@ -1,18 +1,38 @@
+pub mod account_event;
 pub(crate) mod auth;
 pub mod cart_item;
+mod cart_page;
+mod cart_warning;
 mod cookies;
 mod currency;
 pub mod customer;
 mod customer_type;
+mod delivery;
+mod metadata;
+pub mod saved_cart;
+mod shared_cart;
 pub mod shopping_cart;
 pub(crate) mod token;
+mod token_status;
 pub mod tokens;
+mod two_factor;
 
+pub use account_event::{AccountEvent, RequestOrigin};
 pub use cart_item::CartItem;
+pub use cart_page::CartPage;
+pub use cart_warning::{CartWarning, CartWarnings};
 pub use cookies::BazaarCookies;
 pub use currency::Currency;
 pub use customer::{Customer, CustomerUpdate};
 pub use customer_type::CustomerType;
-pub use shopping_cart::ShoppingCart;
-pub use token::{BazaarToken, Claims, TokenType};
+pub use delivery::{DeliveryEstimate, ShippingCalculator, ShippingMethod, ShippingOption};
+pub use metadata::{EnumVariant, Metadata};
+pub use saved_cart::SavedCart;
+pub use shared_cart::SharedCart;
+pub use shopping_cart::{CartSummary, ShoppingCart};
+pub use token::{
+    BazaarToken, Claims, EmailChangeClaims, TokenType, CLAIMS_VERSION, EMAIL_CHANGE_AUDIENCE,
+};
+pub use token_status::TokenStatus;
 pub use tokens::BazaarTokens;
+pub use two_factor::TwoFactorSetup;
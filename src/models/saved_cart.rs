@@ -0,0 +1,112 @@
+use async_graphql::{Context, ErrorExtensions, Object};
+use chrono::{DateTime, Utc};
+use sqlx::{types::Json, PgPool};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    database::{CartItemDatabase, SavedCartRepository},
+    graphql::extract_database_pool,
+    models::{cart_item::InternalCartItem, CartItem},
+    BazaarError, Result,
+};
+
+/// A snapshot of the items in a customer's cart, saved so the same basket
+/// can be ordered again
+#[derive(Debug)]
+pub struct SavedCart {
+    pub id: Uuid,
+    pub customer_id: Uuid,
+    pub name: String,
+    pub items: Vec<InternalCartItem>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub(crate) struct SqlxSavedCart {
+    pub id: Uuid,
+    pub customer_id: Uuid,
+    pub name: String,
+    pub items: Json<Vec<InternalCartItem>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SavedCart {
+    #[tracing::instrument(skip(pool, items))]
+    pub async fn new<DB: SavedCartRepository>(
+        customer_id: Uuid,
+        name: String,
+        items: &[InternalCartItem],
+        pool: &PgPool,
+    ) -> Result<Self> {
+        if items.is_empty() {
+            return Err(BazaarError::BadRequest(
+                "Unable to save an empty cart".to_string(),
+            ));
+        }
+        // Only what's in the cart is saved, the items are new to the cart again when loaded
+        let items = items
+            .iter()
+            .map(|item| InternalCartItem::from((item.sku.clone(), item.quantity)))
+            .collect::<Vec<InternalCartItem>>();
+        let items_array = serde_json::to_value(&items)?;
+        DB::create(Uuid::new_v4(), customer_id, name, items_array, pool).await
+    }
+
+    #[tracing::instrument(skip(pool))]
+    pub async fn find_by_customer_id<DB: SavedCartRepository>(
+        customer_id: Uuid,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>> {
+        DB::find_by_customer_id(customer_id, pool).await
+    }
+
+    /// Returns `NotFound` if the saved cart belongs to a different customer
+    #[tracing::instrument(skip(pool))]
+    pub async fn find_by_id<DB: SavedCartRepository>(
+        id: Uuid,
+        customer_id: Uuid,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        DB::find_by_id(id, customer_id, pool).await
+    }
+}
+
+impl From<SqlxSavedCart> for SavedCart {
+    fn from(saved_cart: SqlxSavedCart) -> Self {
+        Self {
+            id: saved_cart.id,
+            customer_id: saved_cart.customer_id,
+            name: saved_cart.name,
+            items: saved_cart.items.to_vec(),
+            created_at: saved_cart.created_at,
+        }
+    }
+}
+
+// As with the shopping cart, `customer_id` is the **private** ID so it
+// should never be returned by this resolver
+
+#[Object]
+impl SavedCart {
+    async fn id(&self) -> Uuid {
+        self.id
+    }
+
+    async fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    async fn items(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<CartItem>> {
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        CartItem::find_multiple::<CartItemDatabase>(&self.items, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, saved_cart_id = ?self.id, "failed to find the items in the saved cart");
+                err.extend()
+            })
+    }
+}
@@ -0,0 +1,73 @@
+use async_graphql::{Context, ErrorExtensions, Object};
+use sqlx::PgPool;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    auth,
+    database::{CartItemDatabase, ShoppingCartRepository},
+    graphql::extract_database_pool,
+    models::{cart_item::InternalCartItem, CartItem, Currency, ShoppingCart},
+    Result,
+};
+
+/// A read only view of a cart that has been shared via a link
+#[derive(Debug)]
+pub struct SharedCart {
+    cart_id: Uuid,
+    pub currency: Currency,
+    pub items: Vec<InternalCartItem>,
+}
+
+impl SharedCart {
+    /// Creates the token for a link to the cart, it expires after
+    /// `CART_SHARE_TOKEN_DURATION`
+    #[tracing::instrument]
+    pub fn create_link(cart_id: Uuid) -> Result<String> {
+        auth::encode_cart_share_token(cart_id)
+    }
+
+    #[tracing::instrument(skip(token, pool))]
+    pub async fn from_link<DB: ShoppingCartRepository>(
+        token: &str,
+        leeway: u64,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        let cart_id = auth::decode_cart_share_token(token, leeway)?;
+        let cart = ShoppingCart::find_by_id::<DB>(cart_id, pool).await?;
+        Ok(Self::from(cart))
+    }
+}
+
+impl From<ShoppingCart> for SharedCart {
+    fn from(cart: ShoppingCart) -> Self {
+        Self {
+            cart_id: cart.id,
+            currency: cart.currency,
+            items: cart.items,
+        }
+    }
+}
+
+// The cart's ID is enough to take over an anonymous cart (see `recoverCart`), so it
+// should never be returned by this resolver
+
+#[Object]
+impl SharedCart {
+    async fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    async fn items(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<CartItem>> {
+        if self.items.is_empty() {
+            return Ok(Vec::new());
+        }
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        CartItem::find_multiple::<CartItemDatabase>(&self.items, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, cart_id = ?self.cart_id, "failed to find the items in the shared cart");
+                err.extend()
+            })
+    }
+}
@@ -1,18 +1,29 @@
-use async_graphql::{Context, ErrorExtensions, Object};
+use async_graphql::{Context, ErrorExtensions, Object, SimpleObject};
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use sqlx::{types::Json, PgPool};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::iter::FromIterator;
-use tracing::debug;
+use std::sync::Arc;
+use tracing::{debug, error};
 use uuid::Uuid;
 
 use crate::{
-    database::{CartItemDatabase, CartItemRepository, ShoppingCartRepository},
-    models::{cart_item::InternalCartItem, CartItem, Currency},
-    Result,
+    database::{CartItemDatabase, CartItemRepository, CustomerDatabase, ShoppingCartRepository},
+    graphql::{extract_configuration, extract_database_pool},
+    models::{
+        cart_item::{GiftMessage, InternalCartItem, MAX_LINE_QUANTITY},
+        delivery::today_in,
+        CartItem, CartWarning, CartWarnings, Currency, Customer, DeliveryEstimate,
+        ShippingCalculator, ShippingMethod, ShippingOption,
+    },
+    BazaarError, Result,
 };
 
+/// The longest gift message that can be set on a line, in characters
+const MAX_GIFT_MESSAGE_LENGTH: usize = 200;
+
 #[derive(Debug, async_graphql::Enum, Copy, Clone, Eq, PartialEq, Deserialize, sqlx::Type)]
 #[sqlx(rename = "user_cart_type", rename_all = "UPPERCASE")]
 #[serde(rename_all(deserialize = "SCREAMING_SNAKE_CASE"))]
@@ -28,23 +39,41 @@ pub struct ShoppingCart {
     pub customer_id: Option<Uuid>,
     pub cart_type: CartType,
     pub items: Vec<InternalCartItem>,
+    /// The total quantity and number of distinct items, stored alongside the items so
+    /// they can be read without needing to load the items
+    pub item_count: i32,
+    pub distinct_item_count: i32,
     pub discounts: Option<Vec<Uuid>>,
     pub price_before_discounts: f64,
     pub price_after_discounts: f64,
     pub currency: Currency,
+    pub gift_wrap: bool,
     pub created_at: DateTime<Utc>,
     pub last_modified: DateTime<Utc>,
 }
 
+/// A lightweight view of a cart, read from the totals stored on the cart
+/// without needing to look up each of the items within it
+#[derive(Debug, SimpleObject)]
+pub struct CartSummary {
+    pub item_count: i32,
+    pub distinct_item_count: i32,
+    pub total: f64,
+    pub currency: Currency,
+}
+
 pub(crate) struct SqlxShoppingCart {
     pub id: Uuid,
     pub customer_id: Option<Uuid>,
     pub cart_type: CartType,
     pub items: Json<Vec<InternalCartItem>>,
+    pub item_count: i32,
+    pub distinct_item_count: i32,
     pub discounts: Option<Vec<Uuid>>,
     pub price_before_discounts: f64,
     pub price_after_discounts: f64,
     pub currency: Currency,
+    pub gift_wrap: bool,
     pub created_at: DateTime<Utc>,
     pub last_modified: DateTime<Utc>,
 }
@@ -55,6 +84,15 @@ impl ShoppingCart {
         DB::find_by_id(id, pool).await
     }
 
+    /// Reads the summary straight from the cart's stored totals, without loading the items
+    #[tracing::instrument(skip(pool))]
+    pub async fn find_summary<DB: ShoppingCartRepository>(
+        id: Uuid,
+        pool: &PgPool,
+    ) -> Result<CartSummary> {
+        DB::find_summary(id, pool).await
+    }
+
     #[tracing::instrument(skip(pool))]
     pub async fn find_by_customer_id<DB: ShoppingCartRepository>(
         customer_id: Uuid,
@@ -89,14 +127,76 @@ impl ShoppingCart {
         ShoppingCart::new::<DB>(id, Some(customer_id), CartType::Known, currency, pool).await
     }
 
+    /// Finds a cart that can be handed to a new anonymous session, only carts which are
+    /// still anonymous and don't belong to a customer can be recovered
+    #[tracing::instrument(skip(pool))]
+    pub async fn find_recoverable<DB: ShoppingCartRepository>(
+        id: Uuid,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        let cart = Self::find_by_id::<DB>(id, pool).await?;
+        if cart.cart_type != CartType::Anonymous || cart.customer_id.is_some() {
+            error!(cart_id = ?id, "attempted to recover a cart that belongs to a customer");
+            return Err(BazaarError::Forbidden);
+        }
+        Ok(cart)
+    }
+
     #[tracing::instrument(skip(pool))]
     pub async fn edit_cart_items<DB: ShoppingCartRepository, CI: CartItemRepository>(
         cart_id: Uuid,
         items: Vec<InternalCartItem>,
+        max_distinct_items: usize,
         pool: &PgPool,
     ) -> Result<Self> {
         let mut cart = Self::find_by_id::<DB>(cart_id, pool).await?;
-        cart.update_items_in_cart(items);
+        cart.update_items_in_cart(items, max_distinct_items)?;
+        cart.update_cart::<DB, CI>(pool).await
+    }
+
+    /// Sets the quantity of each provided item to the absolute quantity given, a
+    /// quantity of `0` removes the item from the cart
+    #[tracing::instrument(skip(pool))]
+    pub async fn set_cart_items<DB: ShoppingCartRepository, CI: CartItemRepository>(
+        cart_id: Uuid,
+        items: Vec<InternalCartItem>,
+        max_distinct_items: usize,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        let mut cart = Self::find_by_id::<DB>(cart_id, pool).await?;
+        let deltas = cart.quantity_deltas(items);
+        cart.update_items_in_cart(deltas, max_distinct_items)?;
+        cart.update_cart::<DB, CI>(pool).await
+    }
+
+    /// Replaces everything in the cart with the provided items
+    #[tracing::instrument(skip(pool))]
+    pub async fn replace_cart_items<DB: ShoppingCartRepository, CI: CartItemRepository>(
+        cart_id: Uuid,
+        items: Vec<InternalCartItem>,
+        max_distinct_items: usize,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        let mut cart = Self::find_by_id::<DB>(cart_id, pool).await?;
+        cart.items.clear();
+        cart.update_items_in_cart(items, max_distinct_items)?;
+        cart.update_cart::<DB, CI>(pool).await
+    }
+
+    /// Sets whether the cart should be gift wrapped and the gift messages on the given
+    /// lines, anything not provided is left as it is
+    #[tracing::instrument(skip(pool))]
+    pub async fn set_gift_options<DB: ShoppingCartRepository, CI: CartItemRepository>(
+        cart_id: Uuid,
+        gift_wrap: Option<bool>,
+        gift_messages: Vec<GiftMessage>,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        let mut cart = Self::find_by_id::<DB>(cart_id, pool).await?;
+        if let Some(gift_wrap) = gift_wrap {
+            cart.gift_wrap = gift_wrap;
+        }
+        cart.set_gift_messages(gift_messages)?;
         cart.update_cart::<DB, CI>(pool).await
     }
 
@@ -108,6 +208,7 @@ impl ShoppingCart {
     ) -> Result<Uuid> {
         let mut cart = Self::find_by_id::<DB>(customers_cart_id, pool).await?;
         let anon_cart = Self::find_by_id::<DB>(anonymous_cart_id, pool).await?;
+        cart.ensure_same_currency(&anon_cart)?;
         cart.merge_items_from_other_cart(anon_cart);
         cart.update_cart::<DB, CI>(pool).await?;
         Ok(customers_cart_id)
@@ -121,6 +222,12 @@ impl ShoppingCart {
     ) -> Result<Uuid> {
         DB::update_cart_type(cart_id, cart_type, pool).await
     }
+
+    /// A known cart is replaced with a new, empty cart so the customer always has one
+    #[tracing::instrument(skip(pool))]
+    pub async fn delete<DB: ShoppingCartRepository>(cart_id: Uuid, pool: &PgPool) -> Result<()> {
+        DB::delete(cart_id, Uuid::new_v4(), pool).await
+    }
 }
 
 /// Private API
@@ -136,22 +243,141 @@ impl ShoppingCart {
         DB::create_new_cart(id, customer_id, cart_type, currency, pool).await
     }
 
-    // @TODO - Write unit tests for this
+    /// Converts the absolute quantities requested into the change needed from the
+    /// quantities currently in the cart. If a sku is provided more than once, the
+    /// last quantity wins
     #[tracing::instrument]
-    fn update_items_in_cart(&mut self, items: Vec<InternalCartItem>) {
-        let mut current_cart_items = Vec::new();
-        std::mem::swap(&mut self.items, &mut current_cart_items);
-        let mut item_set: HashSet<InternalCartItem> = HashSet::from_iter(current_cart_items);
+    fn quantity_deltas(&self, items: Vec<InternalCartItem>) -> Vec<InternalCartItem> {
+        let mut requested: HashMap<String, i32> = HashMap::new();
+        for item in items {
+            requested.insert(item.sku, item.quantity);
+        }
+        requested
+            .into_iter()
+            .map(|(sku, quantity)| {
+                let current = self
+                    .items
+                    .iter()
+                    .find(|i| i.sku == sku)
+                    .map_or(0, |i| i.quantity);
+                InternalCartItem {
+                    sku,
+                    quantity: quantity - current,
+                    added_at: None,
+                    gift_message: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Applies the change in quantity for each item. New items can't be added once the cart
+    /// holds `max_distinct_items`, however the quantities of existing items can still be changed.
+    /// No line can end up with more than `MAX_LINE_QUANTITY` of an item
+    #[tracing::instrument]
+    fn update_items_in_cart(
+        &mut self,
+        items: Vec<InternalCartItem>,
+        max_distinct_items: usize,
+    ) -> Result<()> {
+        let mut item_set: HashSet<InternalCartItem> = HashSet::from_iter(self.items.clone());
         for item in items {
+            let is_increase = item.quantity > 0;
             let updated_item = match item_set.take(&item) {
                 Some(old_item) => old_item + item,
-                None => item,
+                None => InternalCartItem {
+                    added_at: Some(Utc::now()),
+                    ..item
+                },
             };
+            if is_increase && updated_item.quantity > MAX_LINE_QUANTITY {
+                error!(
+                    cart_id = ?self.id,
+                    sku = %updated_item.sku,
+                    quantity = updated_item.quantity,
+                    "attempted to add more of an item than a cart can hold"
+                );
+                return Err(BazaarError::BadRequest(format!(
+                    "quantity of {} can't be more than {}",
+                    updated_item.sku, MAX_LINE_QUANTITY
+                )));
+            }
             if updated_item.quantity > 0 {
                 item_set.insert(updated_item);
             }
         }
+
+        let adds_new_item = item_set.iter().any(|item| !self.items.contains(item));
+        if adds_new_item && item_set.len() > max_distinct_items {
+            error!(
+                cart_id = ?self.id,
+                max_distinct_items,
+                "attempted to add an item to a cart that is already full"
+            );
+            return Err(BazaarError::BadRequest(
+                "cart line limit reached".to_string(),
+            ));
+        }
         self.items = item_set.into_iter().collect::<Vec<InternalCartItem>>();
+        Ok(())
+    }
+
+    /// Messages can only be set on lines which are already in the cart
+    #[tracing::instrument]
+    fn set_gift_messages(&mut self, gift_messages: Vec<GiftMessage>) -> Result<()> {
+        let cart_id = self.id;
+        for GiftMessage { sku, message } in gift_messages {
+            let message = message
+                .map(|m| m.trim().to_string())
+                .filter(|m| !m.is_empty());
+            if message
+                .as_ref()
+                .map_or(false, |m| m.chars().count() > MAX_GIFT_MESSAGE_LENGTH)
+            {
+                return Err(BazaarError::BadRequest(format!(
+                    "Gift messages can't be longer than {} characters",
+                    MAX_GIFT_MESSAGE_LENGTH
+                )));
+            }
+            let item = self
+                .items
+                .iter_mut()
+                .find(|i| i.sku == sku)
+                .ok_or_else(|| {
+                    error!(
+                        ?cart_id,
+                        ?sku,
+                        "attempted to set a gift message on an item not in the cart"
+                    );
+                    BazaarError::BadRequest(format!("{} isn't in the cart", sku))
+                })?;
+            item.gift_message = message;
+        }
+        Ok(())
+    }
+
+    /// Carts can only be merged if they share a currency, otherwise the prices of the
+    /// merged items would be calculated in the wrong currency
+    #[tracing::instrument(skip(self, other), fields(cart_id = ?self.id, other_cart_id = ?other.id))]
+    fn ensure_same_currency(&self, other: &Self) -> Result<()> {
+        if self.currency != other.currency {
+            return Err(BazaarError::CurrencyMismatch(format!(
+                "Unable to merge a {:?} cart into a {:?} cart",
+                other.currency, self.currency
+            )));
+        }
+        Ok(())
+    }
+
+    /// An anonymous cart is adopted in the preferred currency of the customer signing up.
+    /// Items can't be converted between currencies, so only an empty cart can change currency
+    pub fn ensure_adoptable_in(&self, currency: Currency) -> Result<()> {
+        if self.currency != currency && !self.items.is_empty() {
+            return Err(BazaarError::CurrencyMismatch(format!(
+                "Unable to use a {:?} cart with items in it as a {:?} cart",
+                self.currency, currency
+            )));
+        }
+        Ok(())
     }
 
     // @TODO - Write unit tests for this
@@ -170,6 +396,18 @@ impl ShoppingCart {
                 }
                 None => item,
             };
+            if is_increase && updated_item.quantity > MAX_LINE_QUANTITY {
+                error!(
+                    cart_id = ?self.id,
+                    sku = %updated_item.sku,
+                    quantity = updated_item.quantity,
+                    "attempted to add more of an item than a cart can hold"
+                );
+                return Err(BazaarError::BadRequest(format!(
+                    "quantity of {} can't be more than {}",
+                    updated_item.sku, MAX_LINE_QUANTITY
+                )));
+            }
             if updated_item.quantity > 0 {
                 item_set.insert(updated_item);
             }
@@ -177,18 +415,47 @@ impl ShoppingCart {
         self.items = item_set.into_iter().collect::<Vec<InternalCartItem>>();
     }
 
+    /// Totals are rounded to the minor units of the cart's currency so floating point
+    /// noise from the sum isn't persisted
+    fn calculate_prices(&mut self, cart_items: &[CartItem]) {
+        let subtotal = cart_items.iter().fold(0f64, |mut acc, item| {
+            acc += item.line_total();
+            acc
+        });
+        self.price_before_discounts = self.currency.round(subtotal);
+        // @TODO - Add in discounts stuff
+        self.price_after_discounts = self.currency.round(self.price_before_discounts);
+    }
+
+    /// Only carts belonging to a customer can have a timezone
+    async fn customer_timezone(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<String>> {
+        let customer_id = match self.customer_id {
+            Some(customer_id) => customer_id,
+            None => return Ok(None),
+        };
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        let customer = Customer::find_by_id::<CustomerDatabase>(customer_id, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, cart_id = ?self.id, "failed to find the cart's customer");
+                err.extend()
+            })?;
+        Ok(customer.timezone)
+    }
+
+    fn calculate_item_counts(&mut self) {
+        self.item_count = self.items.iter().map(|i| i.quantity).sum();
+        self.distinct_item_count = self.items.len() as i32;
+    }
+
     #[tracing::instrument(skip(pool))]
     async fn update_cart<SC: ShoppingCartRepository, CI: CartItemRepository>(
         &mut self,
         pool: &PgPool,
     ) -> Result<Self> {
         let cart_items = CartItem::find_multiple::<CI>(&self.items, pool).await?;
-        self.price_before_discounts = cart_items.iter().fold(0f64, |mut acc, item| {
-            acc += item.price_per_unit * item.quantity as f64;
-            acc
-        });
-        // @TODO - Add in discounts stuff
-        self.price_after_discounts = self.price_before_discounts;
+        self.calculate_prices(&cart_items);
+        self.calculate_item_counts();
 
         // Work around until SQLx supports an Array of Custom Types (their goal
         // is for 0.5 release)
@@ -198,17 +465,31 @@ impl ShoppingCart {
     }
 }
 
+impl From<&ShoppingCart> for CartSummary {
+    fn from(cart: &ShoppingCart) -> Self {
+        Self {
+            item_count: cart.item_count,
+            distinct_item_count: cart.distinct_item_count,
+            total: cart.price_after_discounts,
+            currency: cart.currency,
+        }
+    }
+}
+
 impl From<SqlxShoppingCart> for ShoppingCart {
     fn from(cart: SqlxShoppingCart) -> Self {
         Self {
             id: cart.id,
             customer_id: cart.customer_id,
             items: cart.items.to_vec(),
+            item_count: cart.item_count,
+            distinct_item_count: cart.distinct_item_count,
             cart_type: cart.cart_type,
             price_before_discounts: cart.price_before_discounts,
             discounts: cart.discounts,
             price_after_discounts: cart.price_after_discounts,
             currency: cart.currency,
+            gift_wrap: cart.gift_wrap,
             created_at: cart.created_at,
             last_modified: cart.last_modified,
         }
@@ -245,6 +526,10 @@ impl ShoppingCart {
         self.currency
     }
 
+    async fn gift_wrap(&self) -> bool {
+        self.gift_wrap
+    }
+
     async fn created_at(&self) -> DateTime<Utc> {
         self.created_at
     }
@@ -253,15 +538,303 @@ impl ShoppingCart {
         self.last_modified
     }
 
-    // @TODO - Implement proper error handling for this - theres quite a few layers that could
-    // potentially go wrong
+    /// When the cart would be delivered if ordered now, in the customer's timezone
+    /// (or UTC if they haven't set one)
+    async fn estimated_delivery(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default_with = "ShippingMethod::Standard")] method: ShippingMethod,
+    ) -> async_graphql::Result<DeliveryEstimate> {
+        let lead_time = extract_configuration(ctx)
+            .map_err(|e| e.extend())?
+            .shipping
+            .lead_time(method);
+        let timezone = self.customer_timezone(ctx).await?;
+        Ok(DeliveryEstimate::from_now(timezone.as_deref(), lead_time))
+    }
+
+    /// The shipping methods the cart can be sent with, methods with a weight limit
+    /// the cart is over are left out
+    async fn shipping_options(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<Vec<ShippingOption>> {
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        let items = CartItem::find_multiple::<CartItemDatabase>(&self.items, pool)
+            .await
+            .map_err(|err| {
+                error!(?err, cart_id = ?self.id, "failed to find the cart's items");
+                err.extend()
+            })?;
+
+        let settings = &extract_configuration(ctx).map_err(|e| e.extend())?.shipping;
+        let timezone = self.customer_timezone(ctx).await?;
+        Ok(ShippingCalculator::new(settings).options(
+            total_weight_grams(&items),
+            self.currency,
+            today_in(timezone.as_deref()),
+        ))
+    }
+
     async fn items(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<CartItem>> {
         if self.items.is_empty() {
             return Ok(Vec::new());
         }
-        let pool = ctx.data::<PgPool>()?;
-        CartItem::find_multiple::<CartItemDatabase>(&self.items, pool)
+        let pool = extract_database_pool(ctx).map_err(|e| e.extend())?;
+        let items = CartItem::find_multiple::<CartItemDatabase>(&self.items, pool)
             .await
-            .map_err(|e| e.extend())
+            .map_err(|err| {
+                error!(?err, cart_id = ?self.id, "failed to find the items in the cart");
+                err.extend()
+            })?;
+        if let Some(warnings) = ctx.data_opt::<Arc<CartWarnings>>() {
+            for item in &self.items {
+                if !items.iter().any(|i| i.sku == item.sku) {
+                    warnings.push(CartWarning::removed(&item.sku));
+                }
+            }
+            for item in items.iter().filter(|i| !i.is_in_stock()) {
+                warnings.push(CartWarning::out_of_stock(&item.sku));
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Saturates rather than overflowing, so a cart with a huge quantity is still too heavy
+/// for any method with a weight limit
+fn total_weight_grams(items: &[CartItem]) -> u32 {
+    let weight_grams = items
+        .iter()
+        .map(CartItem::line_weight_grams)
+        .fold(0i64, i64::saturating_add);
+    u32::try_from(weight_grams.max(0)).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    fn build_cart(currency: Currency) -> ShoppingCart {
+        ShoppingCart {
+            id: Uuid::new_v4(),
+            customer_id: None,
+            cart_type: CartType::Anonymous,
+            items: Vec::new(),
+            item_count: 0,
+            distinct_item_count: 0,
+            discounts: None,
+            price_before_discounts: 0.0,
+            price_after_discounts: 0.0,
+            currency,
+            gift_wrap: false,
+            created_at: Utc::now(),
+            last_modified: Utc::now(),
+        }
+    }
+
+    fn build_cart_item(price_per_unit: f64, quantity: i32) -> CartItem {
+        CartItem {
+            sku: Uuid::new_v4().to_string(),
+            quantity,
+            price_per_unit,
+            name: "item".to_string(),
+            description: "item".to_string(),
+            img_src: "item.png".to_string(),
+            tags: Vec::new(),
+            available: 100,
+            weight_grams: 0,
+            added_at: None,
+            gift_message: None,
+        }
+    }
+
+    #[test]
+    fn cart_prices_are_rounded_to_the_currency_minor_units() {
+        let mut cart = build_cart(Currency::GBP);
+        let items = vec![build_cart_item(1.1, 3), build_cart_item(0.2, 1)];
+        // Without rounding this sums to 3.5000000000000004
+        cart.calculate_prices(&items);
+
+        assert_eq!(cart.price_before_discounts, 3.5);
+        assert_eq!(cart.price_after_discounts, 3.5);
+    }
+
+    #[test]
+    fn huge_quantities_do_not_overflow_the_cart_weight() {
+        let mut item = build_cart_item(1.0, 3);
+        item.weight_grams = 100;
+        assert_eq!(total_weight_grams(&[item.clone()]), 300);
+
+        item.quantity = i32::MAX;
+        assert_eq!(total_weight_grams(&[item.clone(), item]), u32::MAX);
+    }
+
+    #[test]
+    fn quantity_deltas_are_relative_to_the_current_cart() {
+        let mut cart = build_cart(Currency::GBP);
+        cart.items = vec![("a".to_string(), 3).into(), ("b".to_string(), 1).into()];
+
+        let mut deltas = cart.quantity_deltas(vec![
+            ("a".to_string(), 1).into(),
+            ("b".to_string(), 0).into(),
+            ("c".to_string(), 2).into(),
+            ("c".to_string(), 4).into(),
+        ]);
+        deltas.sort_by(|a, b| a.sku.cmp(&b.sku));
+
+        let deltas: Vec<(String, i32)> = deltas.into_iter().map(|i| (i.sku, i.quantity)).collect();
+        assert_eq!(
+            deltas,
+            vec![
+                ("a".to_string(), -2),
+                ("b".to_string(), -1),
+                ("c".to_string(), 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn new_items_can_not_be_added_past_the_line_limit() {
+        let mut cart = build_cart(Currency::GBP);
+        cart.items = vec![("a".to_string(), 1).into(), ("b".to_string(), 1).into()];
+
+        let err = assert_err!(cart.update_items_in_cart(vec![("c".to_string(), 1).into()], 2));
+        assert_eq!(
+            err,
+            BazaarError::BadRequest("cart line limit reached".to_string())
+        );
+        assert_eq!(cart.items.len(), 2);
+
+        assert_ok!(cart.update_items_in_cart(
+            vec![("a".to_string(), 2).into(), ("b".to_string(), -1).into()],
+            2
+        ));
+        assert_eq!(cart.items.len(), 1);
+        assert_eq!(cart.items[0].sku, "a");
+        assert_eq!(cart.items[0].quantity, 3);
+
+        // Removing an item frees up a line for a new one
+        assert_ok!(cart.update_items_in_cart(vec![("c".to_string(), 1).into()], 2));
+        assert_eq!(cart.items.len(), 2);
+    }
+
+    #[test]
+    fn lines_can_not_be_built_up_past_the_quantity_limit() {
+        let mut cart = build_cart(Currency::GBP);
+        cart.items = vec![("a".to_string(), MAX_LINE_QUANTITY - 1).into()];
+
+        assert_ok!(cart.update_items_in_cart(vec![("a".to_string(), 1).into()], 1));
+        let err = assert_err!(cart.update_items_in_cart(vec![("a".to_string(), 1).into()], 1));
+        assert_eq!(
+            err,
+            BazaarError::BadRequest("quantity of a can't be more than 999".to_string())
+        );
+        assert_eq!(cart.items[0].quantity, MAX_LINE_QUANTITY);
+    }
+
+    #[test]
+    fn only_new_lines_are_stamped_with_when_they_were_added() {
+        let mut cart = build_cart(Currency::GBP);
+        let added_at = Utc::now() - chrono::Duration::days(1);
+        cart.items = vec![InternalCartItem {
+            sku: "a".to_string(),
+            quantity: 1,
+            added_at: Some(added_at),
+            gift_message: None,
+        }];
+
+        assert_ok!(cart.update_items_in_cart(
+            vec![("a".to_string(), 1).into(), ("b".to_string(), 1).into()],
+            2
+        ));
+        let a = cart.items.iter().find(|i| i.sku == "a").unwrap();
+        let b = cart.items.iter().find(|i| i.sku == "b").unwrap();
+        assert_eq!(a.added_at, Some(added_at));
+        assert!(b.added_at.unwrap() > added_at);
+    }
+
+    #[test]
+    fn gift_messages_can_only_be_set_on_lines_in_the_cart() {
+        let mut cart = build_cart(Currency::GBP);
+        cart.items = vec![("a".to_string(), 1).into()];
+
+        assert_ok!(cart.set_gift_messages(vec![GiftMessage {
+            sku: "a".to_string(),
+            message: Some(" Happy birthday! ".to_string()),
+        }]));
+        assert_eq!(
+            cart.items[0].gift_message.as_deref(),
+            Some("Happy birthday!")
+        );
+
+        assert_err!(cart.set_gift_messages(vec![GiftMessage {
+            sku: "b".to_string(),
+            message: Some("Happy birthday!".to_string()),
+        }]));
+
+        assert_ok!(cart.set_gift_messages(vec![GiftMessage {
+            sku: "a".to_string(),
+            message: Some(" ".to_string()),
+        }]));
+        assert!(cart.items[0].gift_message.is_none());
+    }
+
+    #[test]
+    fn gift_messages_can_not_be_too_long() {
+        let mut cart = build_cart(Currency::GBP);
+        cart.items = vec![("a".to_string(), 1).into()];
+
+        assert_err!(cart.set_gift_messages(vec![GiftMessage {
+            sku: "a".to_string(),
+            message: Some("a".repeat(MAX_GIFT_MESSAGE_LENGTH + 1)),
+        }]));
+        assert_ok!(cart.set_gift_messages(vec![GiftMessage {
+            sku: "a".to_string(),
+            message: Some("a".repeat(MAX_GIFT_MESSAGE_LENGTH)),
+        }]));
+    }
+
+    #[test]
+    fn cart_items_stored_without_an_added_at_still_deserialize() {
+        let item: InternalCartItem =
+            serde_json::from_str(r#"{ "sku": "a", "quantity": 2 }"#).unwrap();
+        assert_eq!(item.quantity, 2);
+        assert!(item.added_at.is_none());
+    }
+
+    #[test]
+    fn carts_with_the_same_currency_can_be_merged() {
+        let cart = build_cart(Currency::GBP);
+        let other = build_cart(Currency::GBP);
+        assert_ok!(cart.ensure_same_currency(&other));
+    }
+
+    #[test]
+    fn carts_with_different_currencies_can_not_be_merged() {
+        let cart = build_cart(Currency::GBP);
+        let other = build_cart(Currency::USD);
+        let err = assert_err!(cart.ensure_same_currency(&other));
+        assert_eq!(
+            err,
+            BazaarError::CurrencyMismatch("Unable to merge a USD cart into a GBP cart".to_string())
+        );
+    }
+
+    #[test]
+    fn only_empty_carts_can_be_adopted_in_another_currency() {
+        let mut cart = build_cart(Currency::GBP);
+        assert_ok!(cart.ensure_adoptable_in(Currency::USD));
+
+        cart.items = vec![("a".to_string(), 1).into()];
+        assert_ok!(cart.ensure_adoptable_in(Currency::GBP));
+        let err = assert_err!(cart.ensure_adoptable_in(Currency::USD));
+        assert_eq!(
+            err,
+            BazaarError::CurrencyMismatch(
+                "Unable to use a GBP cart with items in it as a USD cart".to_string()
+            )
+        );
     }
 }
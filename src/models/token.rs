@@ -86,6 +86,8 @@ pub(crate) fn utc_from_timestamp(timestamp: usize) -> DateTime<Utc> {
 pub enum TokenType {
     Access,
     Refresh(i32),
+    /// Lets anyone holding it view the items in a cart, without being able to edit it
+    CartShare,
 }
 
 impl TokenType {
@@ -93,12 +95,25 @@ impl TokenType {
         match self {
             Self::Access => "ACCESS",
             Self::Refresh(_) => "REFRESH",
+            Self::CartShare => "CART_SHARE",
         }
     }
 }
 
+/// The version of `Claims` currently being issued. Bump this whenever a field is
+/// added to or removed from the claims, new fields must have a serde default so
+/// tokens issued before the change can still be decoded
+pub const CLAIMS_VERSION: u8 = 2;
+
+/// Tokens issued before `ver` was added to the claims
+fn legacy_claims_version() -> u8 {
+    1
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct Claims {
+    #[serde(default = "legacy_claims_version")]
+    pub ver: u8,
     pub sub: Option<Uuid>,
     pub customer_type: CustomerType,
     pub cart_id: Uuid,
@@ -110,3 +125,19 @@ pub struct Claims {
     #[serde(skip)]
     pub id: Option<Uuid>,
 }
+
+/// The audience of the tokens sent to verify a new email address, so they can't be
+/// mistaken for any other token signed with the same key
+pub const EMAIL_CHANGE_AUDIENCE: &str = "email_change";
+
+/// Sent to a customer's new email address, proving they control it before their
+/// email is actually changed
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct EmailChangeClaims {
+    /// The customer's public ID
+    pub sub: Uuid,
+    pub email: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+}
@@ -0,0 +1,88 @@
+use async_graphql::SimpleObject;
+use chrono::Duration;
+
+use crate::{auth::TIME_TO_REFRESH, models::BazaarToken};
+
+/// How long the tokens sent with the request have left, so clients can refresh them
+/// without needing to decode them
+#[derive(Debug, SimpleObject, PartialEq)]
+pub struct TokenStatus {
+    /// Not set if there isn't a valid access token
+    pub access_expires_in_seconds: Option<i64>,
+    pub refresh_expires_in_seconds: i64,
+    /// Whether the client should call `refresh` now, either because the access token
+    /// has expired or because refreshing would issue a new refresh token
+    pub should_refresh: bool,
+}
+
+impl TokenStatus {
+    pub fn new(access_token: Option<&BazaarToken>, refresh_token: &BazaarToken) -> Self {
+        let access_expires_in = access_token.map(|t| t.time_till_expiry().max(Duration::zero()));
+        let refresh_expires_in = refresh_token.time_till_expiry();
+        Self {
+            access_expires_in_seconds: access_expires_in.map(|d| d.num_seconds()),
+            refresh_expires_in_seconds: refresh_expires_in.num_seconds(),
+            should_refresh: access_expires_in.map_or(true, |d| d <= Duration::zero())
+                || refresh_expires_in <= *TIME_TO_REFRESH,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use jsonwebtoken::{Algorithm, Header, TokenData};
+    use uuid::Uuid;
+
+    use crate::models::{Claims, CustomerType, TokenType, CLAIMS_VERSION};
+
+    fn build_token(token_type: TokenType, expires_in: Duration) -> BazaarToken {
+        let iat = Utc::now();
+        let claims = Claims {
+            ver: CLAIMS_VERSION,
+            sub: None,
+            customer_type: CustomerType::Anonymous,
+            cart_id: Uuid::new_v4(),
+            exp: (iat + expires_in).timestamp() as usize,
+            iat: iat.timestamp() as usize,
+            count: None,
+            id: None,
+            token_type,
+        };
+        BazaarToken::from(TokenData {
+            header: Header::new(Algorithm::PS256),
+            claims,
+        })
+    }
+
+    #[test]
+    fn fresh_tokens_do_not_need_refreshing() {
+        let access = build_token(TokenType::Access, Duration::minutes(15));
+        let refresh = build_token(TokenType::Refresh(0), Duration::days(28));
+        let status = TokenStatus::new(Some(&access), &refresh);
+
+        assert!(!status.should_refresh);
+        assert!(status.access_expires_in_seconds.unwrap() > 800);
+        assert!(status.refresh_expires_in_seconds > Duration::days(27).num_seconds());
+    }
+
+    #[test]
+    fn a_refresh_token_near_expiry_needs_refreshing() {
+        let access = build_token(TokenType::Access, Duration::minutes(15));
+        let refresh = build_token(TokenType::Refresh(0), Duration::days(1));
+
+        assert!(TokenStatus::new(Some(&access), &refresh).should_refresh);
+    }
+
+    #[test]
+    fn a_missing_or_expired_access_token_needs_refreshing() {
+        let expired = build_token(TokenType::Access, Duration::seconds(-5));
+        let refresh = build_token(TokenType::Refresh(0), Duration::days(28));
+
+        let status = TokenStatus::new(Some(&expired), &refresh);
+        assert!(status.should_refresh);
+        assert_eq!(status.access_expires_in_seconds, Some(0));
+        assert!(TokenStatus::new(None, &refresh).should_refresh);
+    }
+}
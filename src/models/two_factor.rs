@@ -0,0 +1,62 @@
+use async_graphql::SimpleObject;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth,
+    database::{AuthRepository, CustomerRepository},
+    models::Customer,
+    BazaarError, Result,
+};
+
+/// What a customer needs to add Bazaar to their authenticator app, two factor
+/// authentication isn't required on login until a code has been confirmed
+#[derive(Debug, SimpleObject)]
+pub struct TwoFactorSetup {
+    /// The base32 encoded secret, for entering into the app by hand
+    pub secret: String,
+    /// An `otpauth://` URI, for generating a QR code
+    pub uri: String,
+}
+
+impl TwoFactorSetup {
+    /// Generates a new secret, replacing any that hasn't been confirmed yet
+    #[tracing::instrument(skip(pool))]
+    pub async fn enable<A: AuthRepository, C: CustomerRepository>(
+        id: Uuid,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        let customer = Customer::find_by_id::<C>(id, pool).await?;
+        let secret = auth::generate_two_factor_secret()?;
+        let encrypted = auth::encrypt_two_factor_secret(&secret)?;
+        if !A::set_two_factor_secret(id, encrypted, pool).await? {
+            return Err(BazaarError::BadRequest(
+                "Two factor authentication is already enabled".to_string(),
+            ));
+        }
+        Ok(Self {
+            secret: auth::encode_two_factor_secret(&secret),
+            uri: auth::two_factor_provisioning_uri(&secret, &customer.email),
+        })
+    }
+
+    /// Turns on two factor authentication once the customer has shown their app is
+    /// generating the right codes
+    #[tracing::instrument(skip(code, pool))]
+    pub async fn confirm<A: AuthRepository>(id: Uuid, code: &str, pool: &PgPool) -> Result<()> {
+        let encrypted = A::find_two_factor_secret(id, pool).await?.ok_or_else(|| {
+            BazaarError::BadRequest("Two factor authentication hasn't been set up".to_string())
+        })?;
+        let secret = auth::decrypt_two_factor_secret(&encrypted)?;
+        let step = auth::matching_two_factor_step(&secret, code, Utc::now())
+            .ok_or_else(|| BazaarError::BadRequest("Invalid two factor code".to_string()))?;
+        // Recorded so the code used to confirm can't then be used to log in
+        if !A::use_two_factor_step(id, step, pool).await? {
+            return Err(BazaarError::BadRequest(
+                "Two factor code has already been used".to_string(),
+            ));
+        }
+        A::enable_two_factor(id, pool).await
+    }
+}
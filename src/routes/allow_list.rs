@@ -0,0 +1,104 @@
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use std::collections::HashSet;
+use std::fs;
+
+use crate::{configuration::GraphqlSettings, BazaarError, Result};
+
+/// The set of GraphQL operations that may be executed
+///
+/// When the allow list is disabled every operation is allowed, otherwise only
+/// operations whose SHA-256 hash (hex encoded, of the query exactly as it is sent)
+/// is present in the allow list file can be executed
+#[derive(Debug, Clone, Default)]
+pub struct OperationAllowList {
+    hashes: Option<HashSet<String>>,
+}
+
+impl OperationAllowList {
+    /// Loads the allow list file, which should contain one hash per line. Blank lines
+    /// and lines starting with `#` are ignored
+    pub fn from_settings(settings: &GraphqlSettings) -> Result<Self> {
+        if !settings.allow_list_enabled {
+            return Ok(Self::default());
+        }
+        let path = settings.allow_list_path.as_ref().ok_or_else(|| {
+            BazaarError::ConfigError(
+                "graphql.allow_list_path must be set when the allow list is enabled".to_string(),
+            )
+        })?;
+        let contents = fs::read_to_string(path).map_err(|err| {
+            error!(?err, %path, "failed to read the graphql operation allow list");
+            BazaarError::ConfigError(format!("Unable to read allow list at {}", path))
+        })?;
+        let hashes: HashSet<String> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_lowercase)
+            .collect();
+        info!(
+            operations = hashes.len(),
+            "loaded the graphql operation allow list"
+        );
+        Ok(Self {
+            hashes: Some(hashes),
+        })
+    }
+
+    pub fn is_allowed(&self, query: &str) -> bool {
+        match &self.hashes {
+            Some(hashes) => hashes.contains(&Self::hash_operation(query)),
+            None => true,
+        }
+    }
+
+    pub fn hash_operation(query: &str) -> String {
+        format!("{:x}", Sha256::digest(query.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_operation_is_allowed_when_disabled() {
+        let allow_list = OperationAllowList::from_settings(&GraphqlSettings {
+            allow_list_enabled: false,
+            allow_list_path: None,
+        })
+        .unwrap();
+        assert!(allow_list.is_allowed("{ healthCheck }"));
+    }
+
+    #[test]
+    fn enabling_the_allow_list_requires_a_path() {
+        let result = OperationAllowList::from_settings(&GraphqlSettings {
+            allow_list_enabled: true,
+            allow_list_path: None,
+        });
+        assert!(matches!(result, Err(BazaarError::ConfigError(_))));
+    }
+
+    #[test]
+    fn only_listed_operations_are_allowed_when_enabled() {
+        let path = std::env::temp_dir().join(format!("{}.txt", uuid::Uuid::new_v4()));
+        let contents = format!(
+            "# allowed operations\n\n{}\n",
+            OperationAllowList::hash_operation("{ healthCheck }").to_uppercase()
+        );
+        fs::write(&path, contents).unwrap();
+
+        let allow_list = OperationAllowList::from_settings(&GraphqlSettings {
+            allow_list_enabled: true,
+            allow_list_path: Some(path.to_string_lossy().to_string()),
+        })
+        .unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(allow_list.is_allowed("{ healthCheck }"));
+        assert!(!allow_list.is_allowed("{ customers { id } }"));
+    }
+}
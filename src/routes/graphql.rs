@@ -1,24 +1,55 @@
-use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Result};
-use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use actix_web::{
+    http::{
+        header::{HeaderValue, ACCEPT, CONTENT_TYPE, USER_AGENT},
+        StatusCode,
+    },
+    web, HttpMessage, HttpRequest, HttpResponse, Responder, Result,
+};
+use async_graphql::{
+    http::{playground_source, GraphQLPlaygroundConfig},
+    ErrorExtensions, ObjectType, Schema, ServerError, SubscriptionType,
+};
 use async_graphql_actix_web::{Request, Response};
 use async_graphql_telemetry_extension::OpenTelemetryConfig;
-use opentelemetry::Context;
-use tracing::Span;
+use futures::FutureExt;
+use opentelemetry::{trace::TraceContextExt, Context};
+use tracing::{debug, error, field::Empty, warn, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
 
 use crate::{
     graphql::BazaarSchema,
-    models::{BazaarCookies, TokenType},
+    models::{BazaarCookies, CartWarnings, RequestOrigin, TokenType},
+    routes::OperationAllowList,
+    BazaarError,
 };
 
-#[tracing::instrument(name = "graphql", skip(schema, http_request, graphql_request))]
+/// The media type defined by the GraphQL over HTTP spec
+const GRAPHQL_RESPONSE_CONTENT_TYPE: &str = "application/graphql-response+json";
+
+/// How async-graphql prefixes the message when an argument fails one of its validators
+const INVALID_ARGUMENT_PREFIX: &str = "Invalid value for argument \"";
+
+/// The response extension any cart warnings raised during the request are returned under
+const CART_WARNINGS_EXTENSION: &str = "cartWarnings";
+
+/// How async-graphql prefixes the message when a UUID argument fails to parse
+const INVALID_UUID_PREFIX: &str = "Failed to parse \"UUID\": ";
+
+#[tracing::instrument(
+    name = "graphql",
+    skip(schema, allow_list, http_request, graphql_request),
+    fields(trace_id = Empty, span_id = Empty)
+)]
 pub async fn graphql_index(
     schema: web::Data<BazaarSchema>,
+    allow_list: web::Data<OperationAllowList>,
     http_request: HttpRequest,
     graphql_request: Request,
-) -> Result<Response> {
+) -> Result<HttpResponse> {
     // Get the Open Telemetry Context
     let cx = Context::current();
 
@@ -30,14 +61,57 @@ pub async fn graphql_index(
     let span = Span::current();
     // Attach the Otel context to the tracing span
     span.set_parent(cx);
+    record_trace_ids(&span);
 
     let otel_context = OpenTelemetryConfig::default().parent_span(span);
 
     let mut request = graphql_request.into_inner();
-    request = request.data(Arc::clone(&cookies)).data(otel_context);
+    if !allow_list.is_allowed(&request.query) {
+        warn!(
+            operation_hash = %OperationAllowList::hash_operation(&request.query),
+            "rejected an operation that is not on the allow list"
+        );
+        return Err(BazaarError::Forbidden.into());
+    }
+    let warnings = Arc::new(CartWarnings::default());
+    request = request
+        .data(Arc::clone(&cookies))
+        .data(Arc::clone(&warnings))
+        .data(extract_origin(&http_request))
+        .data(otel_context);
+    let query = request.query.clone();
 
-    let resp: Response = schema.execute(request).await.into();
-    Ok(resp)
+    let (mut resp, panicked) = execute_catching_panics(schema.get_ref(), request).await;
+    extend_argument_errors(&mut resp.errors, &query);
+    if let Some(warnings) = warnings.to_extension() {
+        resp.extensions
+            .insert(CART_WARNINGS_EXTENSION.to_string(), warnings);
+    }
+
+    if !accepts_graphql_response(&http_request) {
+        // Legacy `application/json` behaviour, every response is a 200
+        let resp: Response = resp.into();
+        return resp.respond_to(&http_request).await;
+    }
+
+    // Errors raised while resolving a field always carry a path, so if none of the errors
+    // do the request couldn't be executed at all (ie. it failed to parse or validate).
+    // Field errors are still considered a successful response
+    let status = if panicked {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else if resp.is_err() && resp.errors.iter().all(|err| err.path.is_empty()) {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::OK
+    };
+    let resp: Response = resp.into();
+    let mut http_response = resp.respond_to(&http_request).await?;
+    *http_response.status_mut() = status;
+    http_response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(GRAPHQL_RESPONSE_CONTENT_TYPE),
+    );
+    Ok(http_response)
 }
 
 pub async fn graphql_playground() -> HttpResponse {
@@ -48,7 +122,57 @@ pub async fn graphql_playground() -> HttpResponse {
         ))
 }
 
-/// Pulls the Access Token & Refresh Token from the cookies sent on the request
+/// Records the OTEL trace and span IDs on the span, every log record emitted within it
+/// carries its fields so they can be used to jump from a log line to the trace.
+/// The span must declare empty `trace_id` and `span_id` fields
+fn record_trace_ids(span: &Span) {
+    let context = span.context();
+    let span_context = context.span().span_context();
+    span.record("trace_id", &span_context.trace_id().to_hex().as_str());
+    span.record("span_id", &span_context.span_id().to_hex().as_str());
+}
+
+/// A panicking resolver would otherwise take down the worker's response and the client
+/// would just see the connection reset, so it's turned into an unexpected error instead.
+/// Also returns whether the execution panicked
+async fn execute_catching_panics<Query, Mutation, Subscription>(
+    schema: &Schema<Query, Mutation, Subscription>,
+    request: async_graphql::Request,
+) -> (async_graphql::Response, bool)
+where
+    Query: ObjectType + Send + Sync + 'static,
+    Mutation: ObjectType + Send + Sync + 'static,
+    Subscription: SubscriptionType + Send + Sync + 'static,
+{
+    match AssertUnwindSafe(schema.execute(request))
+        .catch_unwind()
+        .await
+    {
+        Ok(resp) => (resp, false),
+        Err(panic) => {
+            error!(
+                panic = %panic_message(&*panic),
+                "a resolver panicked while executing the request"
+            );
+            let err = BazaarError::UnexpectedError.extend().into_server_error();
+            (async_graphql::Response::from_errors(vec![err]), true)
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Pulls the Access Token & Refresh Token from the cookies sent on the request.
+/// Only whether each cookie was sent is logged, never their values
+#[tracing::instrument(skip(req))]
 fn extract_cookies(req: &HttpRequest) -> Result<BazaarCookies> {
     let access_cookie = req
         .cookie(TokenType::Access.as_str())
@@ -56,8 +180,229 @@ fn extract_cookies(req: &HttpRequest) -> Result<BazaarCookies> {
     let refresh_cookie = req
         .cookie(TokenType::Refresh(0).as_str())
         .map(|c| c.value().to_string());
+    debug!(
+        has_access_cookie = access_cookie.is_some(),
+        has_refresh_cookie = refresh_cookie.is_some(),
+        "extracted cookies from the request"
+    );
 
     // @TODO - Come back and work out how to handle these errors appropriately
     let cookies = BazaarCookies::new(access_cookie, refresh_cookie)?;
     Ok(cookies)
 }
+
+/// The client's address respects `Forwarded`/`X-Forwarded-For`, so it's only as
+/// trustworthy as the proxy in front of the app
+fn extract_origin(req: &HttpRequest) -> RequestOrigin {
+    RequestOrigin {
+        ip_address: req
+            .connection_info()
+            .realip_remote_addr()
+            .map(ToString::to_string),
+        user_agent: req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string),
+    }
+}
+
+/// Whether the client has opted in to the GraphQL over HTTP response media type
+fn accepts_graphql_response(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|media_type| media_type.split(';').next())
+        .any(|media_type| {
+            media_type
+                .trim()
+                .eq_ignore_ascii_case(GRAPHQL_RESPONSE_CONTENT_TYPE)
+        })
+}
+
+/// Validator and scalar parsing failures are raised by async-graphql before any resolver
+/// runs, so they never pass through `BazaarError`. This gives them the same extensions as
+/// the rest of the API
+fn extend_argument_errors(errors: &mut [ServerError], query: &str) {
+    for err in errors.iter_mut() {
+        let invalid_argument = if err.path.is_empty() {
+            parse_invalid_argument(&err.message)
+                .map(|(field, details)| (Some(field.to_string()), details.to_string()))
+        } else {
+            None
+        };
+        let invalid_argument = invalid_argument.or_else(|| {
+            let details = err.message.strip_prefix(INVALID_UUID_PREFIX)?;
+            // Parse failures don't name the argument, but they are positioned at its value
+            let field = err
+                .locations
+                .first()
+                .and_then(|pos| argument_at(query, pos.line, pos.column));
+            Some((
+                field.map(str::to_string),
+                format!("invalid uuid: {}", details),
+            ))
+        });
+
+        if let Some((field, details)) = invalid_argument {
+            let extensions = err.extensions.get_or_insert_with(Default::default);
+            extensions.set("status", 400);
+            extensions.set("statusText", "BAD_REQUEST");
+            if let Some(field) = field {
+                extensions.set("field", field);
+            }
+            extensions.set("details", details);
+        }
+    }
+}
+
+/// Finds the name of the argument whose value starts at the given (1-indexed) position
+fn argument_at(query: &str, line: usize, column: usize) -> Option<&str> {
+    let line = query.lines().nth(line.checked_sub(1)?)?;
+    let before_value = line
+        .get(..column.checked_sub(1)?)?
+        .trim_end()
+        .strip_suffix(':')?
+        .trim_end();
+    let start = before_value
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    Some(&before_value[start..]).filter(|name| !name.is_empty())
+}
+
+/// Splits an invalid argument message into the argument name and the validator's reason
+fn parse_invalid_argument(message: &str) -> Option<(&str, &str)> {
+    let rest = message.strip_prefix(INVALID_ARGUMENT_PREFIX)?;
+    let (field, details) = rest.split_at(rest.find('"')?);
+    Some((field, details[1..].trim_start_matches(',').trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::RecordedFields;
+    use async_graphql::{EmptyMutation, EmptySubscription, Object};
+
+    struct PanickingQuery;
+
+    #[Object]
+    impl PanickingQuery {
+        async fn boom(&self) -> i32 {
+            panic!("boom")
+        }
+    }
+
+    #[tokio::test]
+    async fn a_panicking_resolver_returns_an_unexpected_error() {
+        let schema = Schema::new(PanickingQuery, EmptyMutation, EmptySubscription);
+
+        let (resp, panicked) =
+            execute_catching_panics(&schema, async_graphql::Request::new("{ boom }")).await;
+
+        assert!(panicked);
+        assert_eq!(resp.errors.len(), 1);
+        assert_eq!(resp.errors[0].message, "Unexpected error occurred");
+        assert_eq!(
+            serde_json::to_value(&resp.errors[0].extensions).unwrap(),
+            serde_json::json!({ "status": 500, "statusText": "SERVER_ERROR" })
+        );
+    }
+
+    #[test]
+    fn trace_ids_are_recorded_on_the_span() {
+        use opentelemetry::{sdk::trace::TracerProvider, trace::TracerProvider as _};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let tracer = TracerProvider::builder().build().get_tracer("test", None);
+        let recorded = RecordedFields::default();
+        let subscriber = tracing_subscriber::Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .with(recorded.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", trace_id = Empty, span_id = Empty);
+            record_trace_ids(&span);
+
+            let trace_id = span.context().span().span_context().trace_id().to_hex();
+            assert_ne!(trace_id.trim_start_matches('0'), "");
+            let recorded = recorded.recorded();
+            assert!(recorded.contains(&format!("trace_id={:?}", trace_id)));
+            assert!(recorded.iter().any(|field| field.starts_with("span_id=")));
+        });
+    }
+
+    #[test]
+    fn cookie_presence_is_logged_without_the_token_values() {
+        use actix_web::{cookie::Cookie, test::TestRequest};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let recorded = RecordedFields::default();
+        let subscriber = tracing_subscriber::Registry::default().with(recorded.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let req = TestRequest::default()
+                .cookie(Cookie::new(
+                    TokenType::Access.as_str(),
+                    "secret-access-token",
+                ))
+                .to_http_request();
+            let cookies = extract_cookies(&req).unwrap();
+            assert_eq!(
+                cookies.get_access_cookie().unwrap().as_deref(),
+                Some("secret-access-token")
+            );
+        });
+
+        let recorded = recorded.recorded();
+        assert!(recorded.contains(&"has_access_cookie=true".to_string()));
+        assert!(recorded.contains(&"has_refresh_cookie=false".to_string()));
+        assert!(recorded.iter().all(|field| !field.contains("secret")));
+    }
+
+    #[test]
+    fn panic_messages_are_extracted_from_the_payload() {
+        let panic = std::panic::catch_unwind(|| panic!("static message")).unwrap_err();
+        assert_eq!(panic_message(&*panic), "static message");
+        let panic = std::panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&*panic), "formatted 1");
+    }
+
+    #[test]
+    fn invalid_argument_messages_are_parsed() {
+        let message = "Invalid value for argument \"password\", the value length is 5, must be greater than or equal to 8";
+        assert_eq!(
+            parse_invalid_argument(message),
+            Some((
+                "password",
+                "the value length is 5, must be greater than or equal to 8"
+            ))
+        );
+    }
+
+    #[test]
+    fn arguments_are_found_from_the_position_of_their_value() {
+        let query = "mutation {\n  recoverCart(cartId: \"abc\") { issuedAt }\n}";
+        assert_eq!(argument_at(query, 2, 23), Some("cartId"));
+        let query = "mutation recover($id: UUID!) { recoverCart(cartId:$id) { issuedAt } }";
+        assert_eq!(argument_at(query, 1, 51), Some("cartId"));
+    }
+
+    #[test]
+    fn positions_that_are_not_argument_values_are_ignored() {
+        let query = "mutation {\n  recoverCart(cartId: \"abc\") { issuedAt }\n}";
+        assert_eq!(argument_at(query, 2, 3), None);
+        assert_eq!(argument_at(query, 5, 1), None);
+        assert_eq!(argument_at(query, 0, 0), None);
+    }
+
+    #[test]
+    fn other_messages_are_ignored() {
+        assert_eq!(parse_invalid_argument("Unknown field \"foo\""), None);
+        assert_eq!(
+            parse_invalid_argument("Invalid value for argument \"password"),
+            None
+        );
+    }
+}
@@ -0,0 +1,16 @@
+use actix_web::{web, HttpResponse};
+use tracing::error;
+
+use crate::AppMetrics;
+
+pub async fn prometheus_metrics(metrics: web::Data<AppMetrics>) -> HttpResponse {
+    match metrics.render() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body),
+        Err(err) => {
+            error!(?err, "failed to render metrics");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
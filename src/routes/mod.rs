@@ -1,3 +1,9 @@
+mod allow_list;
 mod graphql;
+mod metrics;
+mod readiness;
 
+pub use allow_list::OperationAllowList;
 pub use graphql::*;
+pub use metrics::prometheus_metrics;
+pub use readiness::{readiness, Readiness};
@@ -0,0 +1,58 @@
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::error;
+
+use crate::AppConfig;
+
+/// Whether the instance can take traffic, load balancers should stop routing to it
+/// while it responds with a `503`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    /// Not checked while the pool is exhausted
+    pub database_reachable: bool,
+    pub pool_size: u32,
+    pub idle_connections: usize,
+    /// Every connection the pool is allowed to open is in use
+    pub pool_exhausted: bool,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.database_reachable && !self.pool_exhausted
+    }
+}
+
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    configuration: web::Data<AppConfig>,
+) -> HttpResponse {
+    let pool_size = pool.size();
+    let idle_connections = pool.num_idle();
+    let pool_exhausted =
+        idle_connections == 0 && pool_size >= configuration.database.max_connections;
+    // An exhausted pool would block here until a connection is freed up
+    let database_reachable = !pool_exhausted && ping(&pool).await;
+    let readiness = Readiness {
+        database_reachable,
+        pool_size,
+        idle_connections,
+        pool_exhausted,
+    };
+    if readiness.is_ready() {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+async fn ping(pool: &PgPool) -> bool {
+    match sqlx::query("SELECT 1").execute(pool).await {
+        Ok(_) => true,
+        Err(err) => {
+            error!(?err, "readiness check failed to reach the database");
+            false
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use actix_web::dev::Server;
+use std::future::Future;
+use tracing::info;
+
+/// How long in-flight requests are given to complete once a shutdown has been triggered
+pub const SHUTDOWN_TIMEOUT_SECONDS: u64 = 30;
+
+/// Waits for the provided signal to resolve and then gracefully stops the server,
+/// allowing any in-flight requests to be drained before it exits
+pub async fn shutdown_on_signal(server: Server, signal: impl Future<Output = ()>) {
+    signal.await;
+    info!("shutdown signal received, draining in-flight requests");
+    server.stop(true).await;
+    info!("server has been shut down");
+}
+
+/// Resolves once the process receives either `SIGTERM` or `SIGINT`
+#[cfg(unix)]
+pub async fn termination_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate =
+        signal(SignalKind::terminate()).expect("failed to install the SIGTERM handler");
+    tokio::select! {
+        _ = terminate.recv() => {},
+        _ = tokio::signal::ctrl_c() => {},
+    }
+}
+
+/// Resolves once the process receives `Ctrl-C`
+#[cfg(not(unix))]
+pub async fn termination_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use claim::assert_ok;
+    use futures::channel::oneshot;
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use tokio::time::delay_for;
+
+    #[actix_rt::test]
+    async fn in_flight_requests_complete_before_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind random port");
+        let address = format!("http://{}/", listener.local_addr().unwrap());
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/",
+                web::get().to(|| async {
+                    delay_for(Duration::from_millis(500)).await;
+                    HttpResponse::Ok().body("done")
+                }),
+            )
+        })
+        .disable_signals()
+        .listen(listener)
+        .expect("failed to listen")
+        .run();
+
+        let (tx, rx) = oneshot::channel::<()>();
+        actix_rt::spawn(shutdown_on_signal(server.clone(), async {
+            let _ = rx.await;
+        }));
+
+        let request = async move {
+            reqwest::get(&address)
+                .await
+                .expect("request should complete")
+                .text()
+                .await
+                .expect("response should have a body")
+        };
+        let trigger_shutdown = async move {
+            // Ensure the request is in-flight before shutting down
+            delay_for(Duration::from_millis(100)).await;
+            tx.send(()).expect("failed to send shutdown signal");
+        };
+
+        let (body, _, result) = futures::join!(request, trigger_shutdown, server);
+        assert_eq!(body, "done");
+        assert_ok!(result);
+    }
+}
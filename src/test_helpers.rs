@@ -1,9 +1,14 @@
 use chrono::{Duration, Utc};
+use tracing::{field::Field, span::Record, Event, Id, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
 use uuid::Uuid;
 
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
 use crate::{
     auth::authorize::encode_jwt,
-    models::{Claims, CustomerType, TokenType},
+    models::{Claims, CustomerType, TokenType, CLAIMS_VERSION},
 };
 
 /// Creates a valid JWT from the provided IDs
@@ -20,6 +25,7 @@ pub fn create_valid_jwt_token(
         Some(0)
     };
     let claims = Claims {
+        ver: CLAIMS_VERSION,
         sub: Some(public_id),
         customer_type: CustomerType::Known,
         cart_id,
@@ -33,6 +39,34 @@ pub fn create_valid_jwt_token(
     (token, claims)
 }
 
+/// Captures every field recorded on an event, or on a span after it was created,
+/// formatted as `name=value`
+#[derive(Clone, Default)]
+pub struct RecordedFields(Arc<Mutex<Vec<String>>>);
+
+impl RecordedFields {
+    pub fn recorded(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, field: &Field, value: &dyn Debug) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("{}={:?}", field.name(), value));
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RecordedFields {
+    fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+        values.record(&mut |field: &Field, value: &dyn Debug| self.push(field, value));
+    }
+
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        event.record(&mut |field: &Field, value: &dyn Debug| self.push(field, value));
+    }
+}
+
 // These keys are for local unit tests only, and aren't the ones used in the app
 pub fn set_token_env_vars_for_tests() {
     use std::env::set_var;
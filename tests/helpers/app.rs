@@ -1,13 +1,15 @@
+use bazaar::configuration::Configuration;
 use sqlx::PgPool;
 use std::net::TcpListener;
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::helpers::{configure_database, set_env_vars_for_tests, TRACING};
+use crate::helpers::{configure_database, set_env_vars_for_tests, RecordingEmailSender, TRACING};
 
 pub struct TestApp {
     pub address: String,
     pub db_pool: PgPool,
+    pub emails: Arc<RecordingEmailSender>,
 }
 
 pub struct IdHolder {
@@ -16,12 +18,18 @@ pub struct IdHolder {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with_configuration(|_| {}).await
+}
+
+/// Spawns the app after applying `configure` to the configuration loaded for tests
+pub async fn spawn_app_with_configuration(configure: impl FnOnce(&mut Configuration)) -> TestApp {
     lazy_static::initialize(&TRACING);
 
     let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind random port");
     let port = listener.local_addr().unwrap().port();
 
     let mut configuration = bazaar::get_configuration().expect("failed to read configuration");
+    configure(&mut configuration);
     set_env_vars_for_tests();
 
     let database_name = Uuid::new_v4().to_string();
@@ -29,12 +37,19 @@ pub async fn spawn_app() -> TestApp {
 
     let pool = configure_database(&configuration.database).await;
 
-    let server = bazaar::build_app(listener, pool.clone(), Arc::new(configuration))
-        .expect("failed to bind address");
+    let emails = Arc::new(RecordingEmailSender::default());
+    let server = bazaar::build_app(
+        listener,
+        pool.clone(),
+        Arc::new(configuration),
+        emails.clone(),
+    )
+    .expect("failed to bind address");
 
     let _ = tokio::spawn(server);
     TestApp {
         address: format!("http://127.0.0.1:{}", port),
         db_pool: pool,
+        emails,
     }
 }
@@ -1,3 +1,6 @@
+/// Mirrors `cart.max_distinct_items` in the base configuration
+pub const MAX_DISTINCT_CART_ITEMS: usize = 50;
+
 pub const CUSTOMER_GRAPHQL_FIELDS: &str = "#
 id,
 firstName,
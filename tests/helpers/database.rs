@@ -1,10 +1,14 @@
 use anyhow::Result;
-use sqlx::{Connection, Executor, PgConnection, PgPool};
+use sqlx::{postgres::PgPoolOptions, Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 
 use crate::helpers::CustomerData;
 
-use bazaar::{configuration::DatabaseSettings, database::CustomerDatabase, models::Customer};
+use bazaar::{
+    configuration::DatabaseSettings,
+    database::CustomerDatabase,
+    models::{Currency, Customer},
+};
 
 pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
     let mut connection = PgConnection::connect_with(&config.without_db())
@@ -15,7 +19,9 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .await
         .expect("failed to create database");
 
-    let pool = PgPool::connect_with(config.with_db())
+    let pool = PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .connect_with(config.with_db())
         .await
         .expect("failed to connect to database");
     sqlx::migrate!("./migrations")
@@ -37,15 +43,25 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
 /// 2. Customers Table
 /// 3. Shopping Carts Table
 pub async fn insert_default_customer(pool: &PgPool) -> Result<CustomerData> {
-    let email = "imbatman@test.com";
-    let password = "Passw0rd";
+    insert_customer("imbatman@test.com", "Passw0rd", "Bruce", "Wayne", pool).await
+}
+
+/// Inserts a new customer with the provided details
+pub async fn insert_customer(
+    email: &str,
+    password: &str,
+    first_name: &str,
+    last_name: &str,
+    pool: &PgPool,
+) -> Result<CustomerData> {
     let ids = Customer::new::<CustomerDatabase>(
         Uuid::new_v4(),
         email.to_string(),
         password.to_string(),
-        "Bruce".to_string(),
-        "Wayne".to_string(),
+        first_name.to_string(),
+        last_name.to_string(),
         None,
+        Currency::GBP,
         pool,
     )
     .await?;
@@ -55,10 +71,19 @@ pub async fn insert_default_customer(pool: &PgPool) -> Result<CustomerData> {
         cart_id: Some(ids.cart_id),
         email: Some(email.to_owned()),
         password: Some(password.to_owned()),
-        first_name: Some(String::from("Bruce")),
-        last_name: Some(String::from("Wayne")),
+        first_name: Some(first_name.to_owned()),
+        last_name: Some(last_name.to_owned()),
         raw_access_token: None,
         raw_refresh_token: None,
     };
     Ok(customer)
 }
+
+/// Grants the customer with the provided private ID admin permissions
+pub async fn make_customer_admin(id: Uuid, pool: &PgPool) -> Result<()> {
+    sqlx::query("UPDATE customers SET is_admin = TRUE WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
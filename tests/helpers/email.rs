@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+use bazaar::{
+    integrations::email::{Email, EmailKind, EmailSender},
+    Result,
+};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Keeps every email instead of sending it, so tests can check what would have been sent
+#[derive(Debug, Default)]
+pub struct RecordingEmailSender {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl RecordingEmailSender {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Emails are sent in the background, so wait (briefly) for one of the given kind
+    pub async fn wait_for(&self, kind: EmailKind) -> Option<Email> {
+        for _ in 0..50 {
+            if let Some(email) = self.sent().into_iter().find(|e| e.kind == kind) {
+                return Some(email);
+            }
+            tokio::time::delay_for(Duration::from_millis(20)).await;
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl EmailSender for RecordingEmailSender {
+    async fn send(&self, email: Email) -> Result<()> {
+        self.sent.lock().unwrap().push(email);
+        Ok(())
+    }
+}
@@ -2,19 +2,23 @@
 mod app;
 mod constants;
 mod database;
+mod email;
 mod env_vars;
 mod graphql;
 mod math;
 mod reqwest;
+mod two_factor;
 mod types;
 
 pub use self::reqwest::*;
-pub use app::{spawn_app, IdHolder, TestApp};
+pub use app::{spawn_app, spawn_app_with_configuration, IdHolder, TestApp};
 pub use constants::*;
 pub use database::*;
+pub use email::RecordingEmailSender;
 pub use env_vars::set_env_vars_for_tests;
 pub use graphql::parse_graphql_response;
 pub use math::assert_on_decimal;
+pub use two_factor::two_factor_code;
 pub use types::*;
 
 use lazy_static::lazy_static;
@@ -1,12 +1,17 @@
 use anyhow::Result;
-use jsonwebtoken::{dangerous_insecure_decode, TokenData};
+use chrono::Utc;
+use jsonwebtoken::{dangerous_insecure_decode, encode, Algorithm, EncodingKey, Header, TokenData};
 use lazy_static::lazy_static;
 use regex::Regex;
-use reqwest::{header::HeaderMap, Client, ClientBuilder};
+use reqwest::{
+    header::{HeaderMap, COOKIE},
+    Client, ClientBuilder,
+};
 use serde_json::{json, Value};
 use std::time::Duration;
+use uuid::Uuid;
 
-use bazaar::models::Claims;
+use bazaar::models::{Claims, CustomerType, TokenType, CLAIMS_VERSION};
 
 use crate::helpers::{CustomerData, TOKEN_GRAPHQL_FIELDS};
 
@@ -50,6 +55,79 @@ pub async fn send_request(client: &Client, address: &str, body: &Value) -> Resul
     Ok(Response { data, cookies })
 }
 
+/// Sends the request with the provided refresh token as the only cookie
+pub async fn send_request_with_refresh_token(
+    address: &str,
+    body: &Value,
+    refresh_token: &str,
+) -> Result<Response> {
+    let response = build_http_client()?
+        .post(address)
+        .header(COOKIE, format!("REFRESH={}", refresh_token))
+        .json(body)
+        .send()
+        .await?;
+
+    let headers = response.headers();
+    let cookies = parse_cookies(&headers);
+    let data = response.json::<serde_json::Value>().await?;
+
+    eprintln!("{:#?}", &data);
+    Ok(Response { data, cookies })
+}
+
+/// Signs a refresh token for a known customer directly, so tests can control the
+/// refresh count and when it expires
+pub fn create_refresh_token(
+    customer: &CustomerData,
+    count: i32,
+    expires_in: chrono::Duration,
+) -> Result<String> {
+    let iat = Utc::now();
+    let claims = Claims {
+        ver: CLAIMS_VERSION,
+        sub: customer.public_id,
+        customer_type: CustomerType::Known,
+        cart_id: customer.cart_id.expect("customer should have a cart"),
+        exp: (iat + expires_in).timestamp() as usize,
+        iat: iat.timestamp() as usize,
+        token_type: TokenType::Refresh(count),
+        count: Some(count),
+        id: None,
+    };
+    let key = std::env::var("REFRESH_TOKEN_PRIVATE_KEY")?;
+    let token = encode(
+        &Header::new(Algorithm::PS256),
+        &claims,
+        &EncodingKey::from_rsa_pem(key.as_bytes())?,
+    )?;
+    Ok(token)
+}
+
+/// Signs a cart share token directly, so tests can control when it expires
+pub fn create_cart_share_token(cart_id: Uuid, expires_in: chrono::Duration) -> Result<String> {
+    let iat = Utc::now();
+    let claims = Claims {
+        ver: CLAIMS_VERSION,
+        sub: None,
+        customer_type: CustomerType::Anonymous,
+        cart_id,
+        exp: (iat + expires_in).timestamp() as usize,
+        iat: iat.timestamp() as usize,
+        token_type: TokenType::CartShare,
+        count: None,
+        id: None,
+    };
+    // Every token other than the access token is signed with the refresh key
+    let key = std::env::var("REFRESH_TOKEN_PRIVATE_KEY")?;
+    let token = encode(
+        &Header::new(Algorithm::PS256),
+        &claims,
+        &EncodingKey::from_rsa_pem(key.as_bytes())?,
+    )?;
+    Ok(token)
+}
+
 pub async fn get_anonymous_token(client: &Client, address: &str) -> Result<CustomerData> {
     let graphql_mutatation = format!(
         r#"
@@ -99,7 +177,7 @@ pub async fn sign_user_up_and_get_known_token(
     let graphql_mutatation = format!(
         r#"
         mutation signUp($email: String!, $password: String!, $firstName: String!, $lastName: String!) {{
-            signUp(email: $email, password: $password, firstName: $firstName, lastName: $lastName) {{
+            signUp(email: $email, password: $password, firstName: $firstName, lastName: $lastName, acceptedTerms: true, acceptedPrivacyPolicy: true) {{
                 {}
             }}
         }}
@@ -150,6 +228,45 @@ pub async fn sign_user_up_and_get_known_token(
     Ok(customer)
 }
 
+/// Logs in the provided customer, storing the returned tokens within the client
+pub async fn login_customer(
+    client: &Client,
+    address: &str,
+    customer: &CustomerData,
+) -> Result<CustomerData> {
+    let graphql_mutatation = format!(
+        r#"
+        mutation login($email: String!, $password: String!) {{
+            login(email: $email, password: $password) {{
+                {}
+            }}
+        }}
+    "#,
+        TOKEN_GRAPHQL_FIELDS
+    );
+
+    let body = json!({
+        "query": graphql_mutatation,
+        "variables": {
+            "email": customer.email,
+            "password": customer.password
+        }
+    });
+
+    let response = client.post(address).json(&body).send().await?;
+
+    let headers = response.headers();
+    let cookies = parse_cookies(&headers);
+
+    let data = response.json::<serde_json::Value>().await?;
+    eprintln!("{:#?}", &data);
+
+    let mut customer = customer.clone();
+    customer.raw_access_token = cookies.raw_access;
+    customer.raw_refresh_token = cookies.raw_refresh;
+    Ok(customer)
+}
+
 fn parse_cookies(headers: &HeaderMap) -> Cookies {
     let cookies = headers.get_all("set-cookie");
     let mut access_token = String::default();
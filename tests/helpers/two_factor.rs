@@ -0,0 +1,53 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use ring::hmac;
+
+const BASE32_ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const STEP_SECONDS: i64 = 30;
+
+/// Acts as the customer's authenticator app, generating the code for the time step
+/// `steps_from_now` away from the current one, from the base32 secret returned by
+/// `enableTwoFactor`
+///
+/// This is deliberately independent of the application's implementation, so it can
+/// be checked against the RFC 6238 test vectors on its own
+pub fn two_factor_code(encoded_secret: &str, steps_from_now: i64) -> Result<String> {
+    let mut secret = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in encoded_secret.chars() {
+        let value = BASE32_ALPHABET
+            .find(c)
+            .ok_or_else(|| anyhow!("invalid base32 character: {}", c))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            secret.push((buffer >> bits) as u8);
+        }
+    }
+    let step = Utc::now().timestamp() / STEP_SECONDS + steps_from_now;
+    Ok(totp(&secret, step as u64))
+}
+
+fn totp(secret: &[u8], step: u64) -> String {
+    let tag = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret),
+        &step.to_be_bytes(),
+    );
+    let hash = tag.as_ref();
+    let offset = (hash[19] & 0x0f) as usize;
+    let code = (u32::from(hash[offset] & 0x7f) << 24)
+        | (u32::from(hash[offset + 1]) << 16)
+        | (u32::from(hash[offset + 2]) << 8)
+        | u32::from(hash[offset + 3]);
+    format!("{:06}", code % 1_000_000)
+}
+
+#[test]
+fn codes_match_the_rfc_6238_test_vectors() {
+    // The SHA1 secret and times from the RFC, whose 8 digit codes end in these 6
+    let secret = b"12345678901234567890";
+    assert_eq!(totp(secret, 59 / 30), "287082");
+    assert_eq!(totp(secret, 1_111_111_109 / 30), "081804");
+    assert_eq!(totp(secret, 2_000_000_000 / 30), "279037");
+}
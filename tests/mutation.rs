@@ -6,8 +6,10 @@ use serde_json::json;
 use uuid::Uuid;
 
 use bazaar::{
+    auth::encode_email_change_token,
     database::{CartItemDatabase, CustomerDatabase, ShoppingCartDatabase},
-    models::{cart_item::InternalCartItem, Customer, ShoppingCart},
+    integrations::email::EmailKind,
+    models::{cart_item::InternalCartItem, Currency, Customer, ShoppingCart},
 };
 
 mod helpers;
@@ -23,7 +25,7 @@ async fn mutation_sign_up_without_token_works() -> Result<()> {
     let graphql_mutatation = format!(
         r#"
         mutation signUp($email: String!, $password: String!, $firstName: String!, $lastName: String!) {{
-            signUp(email: $email, password: $password, firstName: $firstName, lastName: $lastName) {{
+            signUp(email: $email, password: $password, firstName: $firstName, lastName: $lastName, acceptedTerms: true, acceptedPrivacyPolicy: true) {{
                {} 
             }}
         }}
@@ -69,6 +71,63 @@ async fn mutation_sign_up_without_token_works() -> Result<()> {
     Ok(())
 }
 
+#[actix_rt::test]
+async fn mutation_sign_up_requires_consent() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let sign_up = |accepted_privacy_policy: bool| {
+        json!({
+            "query": r#"
+                mutation signUp($acceptedPrivacyPolicy: Boolean!) {
+                    signUp(
+                        email: "007@test.com",
+                        password: "Passw0rd!",
+                        firstName: "James",
+                        lastName: "Bond",
+                        acceptedTerms: true,
+                        acceptedPrivacyPolicy: $acceptedPrivacyPolicy
+                    ) { issuedAt }
+                }
+            "#,
+            "variables": { "acceptedPrivacyPolicy": accepted_privacy_policy }
+        })
+    };
+
+    let response = send_request(&client, &app.address, &sign_up(false)).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST",
+                "details": "The terms and privacy policy must be accepted"
+            }
+        }])
+    );
+    assert!(
+        Customer::find_by_email::<CustomerDatabase>("007@test.com".to_string(), &app.db_pool)
+            .await
+            .is_err()
+    );
+
+    let before = chrono::Utc::now();
+    let response = send_request(&client, &app.address, &sign_up(true)).await?;
+    assert!(response.data["errors"].is_null());
+
+    let customer =
+        Customer::find_by_email::<CustomerDatabase>("007@test.com".to_string(), &app.db_pool)
+            .await?;
+    assert_eq!(
+        customer.consent_policy_version.as_deref(),
+        Some(bazaar::POLICY_VERSION)
+    );
+    let consented_at = customer.consented_at.expect("consent should be recorded");
+    assert!(consented_at >= before - chrono::Duration::seconds(1));
+
+    Ok(())
+}
+
 #[actix_rt::test]
 async fn mutation_sign_up_with_anonymous_token_works() -> Result<()> {
     let app = spawn_app().await;
@@ -78,7 +137,7 @@ async fn mutation_sign_up_with_anonymous_token_works() -> Result<()> {
     let graphql_mutatation = format!(
         r#"
         mutation signUp($email: String!, $password: String!, $firstName: String!, $lastName: String!) {{
-            signUp(email: $email, password: $password, firstName: $firstName, lastName: $lastName) {{
+            signUp(email: $email, password: $password, firstName: $firstName, lastName: $lastName, acceptedTerms: true, acceptedPrivacyPolicy: true) {{
                {} 
             }}
         }}
@@ -120,6 +179,207 @@ async fn mutation_sign_up_with_anonymous_token_works() -> Result<()> {
     assert_ne!(access_claims.sub, Some(new_customer.id));
     // The cart should have been promoted, so they both should be the same
     assert_eq!(access_claims.cart_id, anon_customer.cart_id.unwrap());
+    // And it should now belong to the customer
+    let cart =
+        ShoppingCart::find_by_customer_id::<ShoppingCartDatabase>(new_customer.id, &app.db_pool)
+            .await?;
+    assert_eq!(cart.id, anon_customer.cart_id.unwrap());
+    assert_eq!(cart.customer_id, Some(new_customer.id));
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_sign_up_creates_cart_in_preferred_currency() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let graphql_mutatation = format!(
+        r#"
+        mutation {{
+            signUp(email: "usd@test.com", password: "Passw0rd!", firstName: "Tony", lastName: "Stark", acceptedTerms: true, acceptedPrivacyPolicy: true, preferredCurrency: USD) {{
+               {}
+            }}
+        }}
+    "#,
+        TOKEN_GRAPHQL_FIELDS
+    );
+    let body = json!({ "query": graphql_mutatation });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+
+    let body = json!({
+        "query": "query customer { customer { preferredCurrency cart { currency } } }"
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["data"]["customer"].clone(),
+        expected: json!({
+            "preferredCurrency": "USD",
+            "cart": {
+                "currency": "USD"
+            }
+        })
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_sign_up_with_anonymous_token_creates_cart_in_preferred_currency() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let anon_customer = get_anonymous_token(&client, &app.address).await?;
+
+    let graphql_mutatation = format!(
+        r#"
+        mutation {{
+            signUp(email: "usd@test.com", password: "Passw0rd!", firstName: "Tony", lastName: "Stark", acceptedTerms: true, acceptedPrivacyPolicy: true, preferredCurrency: USD) {{
+               {}
+            }}
+        }}
+    "#,
+        TOKEN_GRAPHQL_FIELDS
+    );
+    let body = json!({ "query": graphql_mutatation });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+
+    // The empty anonymous cart is adopted and moved into the preferred currency
+    let cart = ShoppingCart::find_by_id::<ShoppingCartDatabase>(
+        anon_customer.cart_id.unwrap(),
+        &app.db_pool,
+    )
+    .await?;
+    assert_eq!(cart.currency, Currency::USD);
+    let body = json!({
+        "query": "query customer { customer { preferredCurrency cart { id currency } } }"
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["data"]["customer"].clone(),
+        expected: json!({
+            "preferredCurrency": "USD",
+            "cart": {
+                "id": anon_customer.cart_id.unwrap(),
+                "currency": "USD"
+            }
+        })
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_sign_up_rejects_a_preferred_currency_the_anonymous_items_are_not_in() -> Result<()>
+{
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _anon_customer = get_anonymous_token(&client, &app.address).await?;
+    let body = json!({
+        "query": "mutation addItemsToCart($newItems: [UpdateCartItem!]!) { addItemsToCart(newItems: $newItems) { currency } }",
+        "variables": { "newItems": [{ "sku": "12345678", "quantity": 1 }] }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(response.data["data"]["addItemsToCart"]["currency"], "GBP");
+
+    let graphql_mutatation = format!(
+        r#"
+        mutation {{
+            signUp(email: "usd@test.com", password: "Passw0rd!", firstName: "Tony", lastName: "Stark", acceptedTerms: true, acceptedPrivacyPolicy: true, preferredCurrency: USD) {{
+               {}
+            }}
+        }}
+    "#,
+        TOKEN_GRAPHQL_FIELDS
+    );
+    let body = json!({ "query": graphql_mutatation });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "details": "Unable to use a GBP cart with items in it as a USD cart"
+            }
+        }])
+    );
+    assert!(
+        Customer::find_by_email::<CustomerDatabase>("usd@test.com".to_string(), &app.db_pool)
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn deleting_a_customer_leaves_no_orphaned_rows() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+    let id = customer.private_id.unwrap();
+
+    let body = json!({
+        "query": "mutation saveCart($name: String!) { saveCart(name: $name) { id } }",
+        "variables": { "name": "For later" }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+
+    sqlx::query("DELETE FROM customers WHERE id = $1")
+        .bind(id)
+        .execute(&app.db_pool)
+        .await?;
+
+    for table in &["auth", "shopping_carts", "saved_carts", "account_events"] {
+        let column = if *table == "auth" {
+            "id"
+        } else {
+            "customer_id"
+        };
+        let (remaining,): (i64,) = sqlx::query_as(&format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = $1",
+            table, column
+        ))
+        .bind(id)
+        .fetch_one(&app.db_pool)
+        .await?;
+        assert_eq!(remaining, 0, "{} still has rows for the customer", table);
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_sign_up_with_short_password_returns_bad_request() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let graphql_mutatation = format!(
+        r#"
+        mutation {{
+            signUp(email: "short@test.com", password: "short", firstName: "James", lastName: "Bond", acceptedTerms: true, acceptedPrivacyPolicy: true) {{
+               {}
+            }}
+        }}
+    "#,
+        TOKEN_GRAPHQL_FIELDS
+    );
+
+    let body = json!({ "query": graphql_mutatation });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST",
+                "field": "password"
+            }
+        }])
+    );
 
     Ok(())
 }
@@ -133,7 +393,7 @@ async fn mutation_sign_up_with_known_tokens_should_error() -> Result<()> {
     let graphql_mutatation = format!(
         r#"
         mutation signUp($email: String!, $password: String!, $firstName: String!, $lastName: String!) {{
-            signUp(email: $email, password: $password, firstName: $firstName, lastName: $lastName) {{
+            signUp(email: $email, password: $password, firstName: $firstName, lastName: $lastName, acceptedTerms: true, acceptedPrivacyPolicy: true) {{
                {} 
             }}
         }}
@@ -245,6 +505,59 @@ async fn mutation_login_with_valid_credentials_and_anonymous_tokens_works() -> R
     Ok(())
 }
 
+#[actix_rt::test]
+async fn mutation_login_with_anonymous_cart_in_another_currency_still_works() -> Result<()> {
+    let app = spawn_app().await;
+    let known_client = build_http_client()?;
+    let sign_up = format!(
+        r#"
+        mutation {{
+            signUp(email: "usd@test.com", password: "Passw0rd!", firstName: "Tony", lastName: "Stark", acceptedTerms: true, acceptedPrivacyPolicy: true, preferredCurrency: USD) {{
+               {}
+            }}
+        }}
+    "#,
+        TOKEN_GRAPHQL_FIELDS
+    );
+    let response = send_request(&known_client, &app.address, &json!({ "query": sign_up })).await?;
+    assert!(response.data["errors"].is_null());
+
+    // Anonymous carts are always GBP
+    let anon_client = build_http_client()?;
+    let _anon_customer = get_anonymous_token(&anon_client, &app.address).await?;
+    let body = json!({
+        "query": "mutation addItemsToCart($newItems: [UpdateCartItem!]!) { addItemsToCart(newItems: $newItems) { currency } }",
+        "variables": { "newItems": [{ "sku": "12345678", "quantity": 1 }] }
+    });
+    let response = send_request(&anon_client, &app.address, &body).await?;
+    assert_eq!(response.data["data"]["addItemsToCart"]["currency"], "GBP");
+
+    let login = format!(
+        r#"
+        mutation {{
+            login(email: "usd@test.com", password: "Passw0rd!") {{
+               {}
+            }}
+        }}
+    "#,
+        TOKEN_GRAPHQL_FIELDS
+    );
+    let response = send_request(&anon_client, &app.address, &json!({ "query": login })).await?;
+    assert!(response.data["errors"].is_null());
+    assert_some!(response.cookies.access);
+    assert_some!(response.cookies.refresh);
+
+    // The anonymous items can't be priced in USD, so they aren't merged in
+    let body = json!({ "query": "query { customer { cart { currency items { sku } } } }" });
+    let response = send_request(&anon_client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["data"]["customer"]["cart"].clone(),
+        expected: json!({ "currency": "USD", "items": [] })
+    );
+
+    Ok(())
+}
+
 #[actix_rt::test]
 async fn mutation_login_with_already_logged_in_customer_errors() -> Result<()> {
     let app = spawn_app().await;
@@ -417,6 +730,78 @@ async fn mutation_anonymous_login_works() -> Result<()> {
     Ok(())
 }
 
+#[actix_rt::test]
+async fn mutation_anonymous_login_works_with_null_or_missing_variables() -> Result<()> {
+    let app = spawn_app().await;
+    let query = format!(
+        "mutation anonymousLogin {{ anonymousLogin {{ {} }} }}",
+        TOKEN_GRAPHQL_FIELDS
+    );
+
+    let bodies = vec![
+        json!({ "query": query, "variables": null }),
+        json!({ "query": query }),
+    ];
+    for body in bodies {
+        let client = build_http_client()?;
+        let response = send_request(&client, &app.address, &body).await?;
+        assert!(response.data["errors"].is_null(), "{}", response.data);
+        assert_some!(response.cookies.access);
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_set_marketing_consent_only_records_changes() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+
+    let set_consent = |opt_in: bool| {
+        json!({
+            "query": "mutation setMarketingConsent($optIn: Boolean!) { setMarketingConsent(optIn: $optIn) { marketingOptIn marketingOptInAt } }",
+            "variables": { "optIn": opt_in }
+        })
+    };
+    let opt_in_at = |response: &serde_json::Value| {
+        let value = &response["data"]["setMarketingConsent"]["marketingOptInAt"];
+        DateTime::parse_from_rfc3339(value.as_str().unwrap()).unwrap()
+    };
+
+    let response = send_request(&client, &app.address, &set_consent(true)).await?;
+    assert_eq!(
+        response.data["data"]["setMarketingConsent"]["marketingOptIn"],
+        true
+    );
+    let opted_in_at = opt_in_at(&response.data);
+
+    // Opting in again isn't a change, so the original time is kept
+    let response = send_request(&client, &app.address, &set_consent(true)).await?;
+    assert_eq!(opt_in_at(&response.data), opted_in_at);
+
+    let response = send_request(&client, &app.address, &set_consent(false)).await?;
+    assert_eq!(
+        response.data["data"]["setMarketingConsent"]["marketingOptIn"],
+        false
+    );
+    assert!(opt_in_at(&response.data) > opted_in_at);
+
+    // Anonymous customers have no consent to record
+    let anon_client = build_http_client()?;
+    get_anonymous_token(&anon_client, &app.address).await?;
+    let response = send_request(&anon_client, &app.address, &set_consent(true)).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Anonymous users do not have access to this resource",
+            "extensions": { "status": 401 }
+        }])
+    );
+
+    Ok(())
+}
+
 #[actix_rt::test]
 async fn mutation_update_customer_works() -> Result<()> {
     let app = spawn_app().await;
@@ -451,26 +836,27 @@ async fn mutation_update_customer_works() -> Result<()> {
             generate_json("lastName", "Pool")
         ]),
     ];
+    // Email changes are only applied once they've been verified
     let expected = vec![
         json!({
             "firstName": "Clark",
             "lastName": "Kent",
-            "email": "updated@test.com"
+            "email": "superman@test.com"
         }),
         json!({
             "firstName": "Updated",
             "lastName": "Kent",
-            "email": "updated@test.com"
+            "email": "superman@test.com"
         }),
         json!({
             "firstName": "Updated",
             "lastName": "Updated",
-            "email": "updated@test.com"
+            "email": "superman@test.com"
         }),
         json!({
             "firstName": "Mr",
             "lastName": "Pool",
-            "email": "deadpool@troll.com"
+            "email": "superman@test.com"
         }),
     ];
 
@@ -498,46 +884,356 @@ async fn mutation_update_customer_works() -> Result<()> {
 }
 
 #[actix_rt::test]
-async fn mutation_update_customer_without_known_token_errors() -> Result<()> {
+async fn mutation_update_customer_email_is_pending_until_confirmed() -> Result<()> {
     let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+    let original_email = customer.email.clone().unwrap();
+    let private_id =
+        Customer::find_by_email::<CustomerDatabase>(original_email.clone(), &app.db_pool)
+            .await?
+            .id;
 
-    let graphql_mutatation = format!(
-        r#"
-        mutation updateCustomer($update: [CustomerUpdate!]!) {{
-            updateCustomer(update: $update) {{
-                {}
-            }}
-        }}
-    "#,
-        CUSTOMER_GRAPHQL_FIELDS
+    let body = json!({
+        "query": "mutation updateCustomer($update: [CustomerUpdate!]!) { updateCustomer(update: $update) { email } }",
+        "variables": {
+            "update": [{ "key": "email", "value": "new@test.com" }]
+        }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(
+        response.data["data"]["updateCustomer"]["email"],
+        json!(original_email)
     );
 
-    let update = json!([{
-        "key": "firstName",
-        "value": "Clark"
-    }]);
+    let (email, pending_email): (String, Option<String>) =
+        sqlx::query_as("SELECT email, pending_email FROM customers WHERE id = $1")
+            .bind(private_id)
+            .fetch_one(&app.db_pool)
+            .await?;
+    assert_eq!(email, original_email);
+    assert_eq!(pending_email, Some("new@test.com".to_string()));
+
+    // A token for a different address doesn't confirm the pending change
+    let confirm_email_change = "mutation confirmEmailChange($token: String!) { confirmEmailChange(token: $token) { id email } }";
+    let wrong_token = encode_email_change_token(customer.public_id.unwrap(), "other@test.com")?;
+    let body = json!({
+        "query": confirm_email_change,
+        "variables": { "token": wrong_token }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(response.data["errors"][0]["extensions"]["status"], 400);
+    let customer_after = Customer::find_by_id::<CustomerDatabase>(private_id, &app.db_pool).await?;
+    assert_eq!(customer_after.email, original_email);
 
+    let token = encode_email_change_token(customer.public_id.unwrap(), "new@test.com")?;
     let body = json!({
-        "query": graphql_mutatation,
-        "variables": {
-            "update": update
-            }
+        "query": confirm_email_change,
+        "variables": { "token": token }
     });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(
+        response.data["data"]["confirmEmailChange"],
+        json!({
+            "id": customer.public_id.unwrap(),
+            "email": "new@test.com"
+        })
+    );
+    let customer_after = Customer::find_by_id::<CustomerDatabase>(private_id, &app.db_pool).await?;
+    assert_eq!(customer_after.email, "new@test.com");
+
+    Ok(())
+}
 
+#[actix_rt::test]
+async fn mutation_update_customer_email_rejects_addresses_in_use() -> Result<()> {
+    let app = spawn_app().await;
     let client = build_http_client()?;
-    get_anonymous_token(&client, &app.address).await?;
+    let customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+    let other_customer = insert_default_customer(&app.db_pool).await?;
 
-    // [ No tokens | Anonymous Tokens ]
-    // One client holds no tokens in cookies, the other holds anonymous tokens
-    let test_cases = vec![build_http_client()?, client];
-    let expected = vec![
-        json!([{
-            "message": "Invalid token provided",
-            "extensions": {
-                "status": 401,
-                "statusText": "INVALID_TOKEN",
-                "details": "No token was found"
-            }
+    let update_email = |email: &str| {
+        json!({
+            "query": "mutation updateCustomer($update: [CustomerUpdate!]!) { updateCustomer(update: $update) { email } }",
+            "variables": {
+                "update": [{ "key": "email", "value": email }]
+            }
+        })
+    };
+    let response = send_request(
+        &client,
+        &app.address,
+        &update_email(other_customer.email.as_deref().unwrap()),
+    )
+    .await?;
+    assert_eq!(response.data["errors"][0]["extensions"]["status"], 400);
+
+    // An address another customer is waiting to confirm is also rejected, though the
+    // customer waiting on it can request it again
+    let response = send_request(&client, &app.address, &update_email("pending@test.com")).await?;
+    assert!(response.data["errors"].is_null());
+    let response = send_request(&client, &app.address, &update_email("pending@test.com")).await?;
+    assert!(response.data["errors"].is_null());
+    let other_client = build_http_client()?;
+    login_customer(&other_client, &app.address, &other_customer).await?;
+    let response = send_request(
+        &other_client,
+        &app.address,
+        &update_email("pending@test.com"),
+    )
+    .await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "details": "Email address is already in use"
+            }
+        }])
+    );
+
+    // The address is free when the change is requested, but taken before it's confirmed
+    let response = send_request(&client, &app.address, &update_email("new@test.com")).await?;
+    assert!(response.data["errors"].is_null());
+    insert_customer("new@test.com", "Passw0rd", "Diana", "Prince", &app.db_pool).await?;
+
+    let token = encode_email_change_token(customer.public_id.unwrap(), "new@test.com")?;
+    let body = json!({
+        "query": "mutation confirmEmailChange($token: String!) { confirmEmailChange(token: $token) { email } }",
+        "variables": { "token": token }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(response.data["errors"][0]["extensions"]["status"], 400);
+    let customer_after =
+        Customer::find_by_email::<CustomerDatabase>(customer.email.unwrap(), &app.db_pool).await;
+    assert!(customer_after.is_ok());
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_sign_up_and_email_changes_send_emails() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+
+    let welcome = app
+        .emails
+        .wait_for(EmailKind::Welcome)
+        .await
+        .expect("a welcome email should be sent on sign up");
+    assert_eq!(welcome.to, customer.email.unwrap());
+    assert!(welcome.body.contains("Clark"));
+
+    let body = json!({
+        "query": "mutation updateCustomer($update: [CustomerUpdate!]!) { updateCustomer(update: $update) { email } }",
+        "variables": {
+            "update": [{ "key": "email", "value": "new@test.com" }]
+        }
+    });
+    send_request(&client, &app.address, &body).await?;
+
+    let verification = app
+        .emails
+        .wait_for(EmailKind::EmailChangeVerification)
+        .await
+        .expect("a verification email should be sent to the new address");
+    assert_eq!(verification.to, "new@test.com");
+    assert_eq!(app.emails.sent().len(), 2);
+
+    // The token in the email confirms the change
+    let token = verification.body.lines().last().unwrap();
+    let body = json!({
+        "query": "mutation confirmEmailChange($token: String!) { confirmEmailChange(token: $token) { email } }",
+        "variables": { "token": token }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(
+        response.data["data"]["confirmEmailChange"]["email"],
+        json!("new@test.com")
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_update_customer_avatar_url_only_accepts_http_urls() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+
+    let graphql_mutatation = r#"
+        mutation updateCustomer($update: [CustomerUpdate!]!) {
+            updateCustomer(update: $update) {
+                id
+                avatarUrl
+            }
+        }
+    "#;
+
+    let build_body = |value: &str| -> serde_json::Value {
+        json!({
+            "query": graphql_mutatation,
+            "variables": {
+                "update": [{ "key": "avatarUrl", "value": value }]
+            }
+        })
+    };
+
+    let avatar = "https://cdn.bazaar.com/avatars/clark.png";
+    let response = send_request(&client, &app.address, &build_body(avatar)).await?;
+    assert!(response.data["errors"].is_null());
+    assert_eq!(
+        response.data["data"]["updateCustomer"]["avatarUrl"],
+        json!(avatar)
+    );
+
+    for invalid in &["javascript:alert(1)", "not a url"] {
+        let response = send_request(&client, &app.address, &build_body(invalid)).await?;
+        let message = response.data["errors"][0]["message"]
+            .as_str()
+            .expect("an error should be returned");
+        assert!(message.contains(&format!("invalid url: {}", invalid)));
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_update_customer_timezone_only_accepts_iana_timezones() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+
+    let graphql_mutatation = r#"
+        mutation updateCustomer($update: [CustomerUpdate!]!) {
+            updateCustomer(update: $update) {
+                id
+                timezone
+            }
+        }
+    "#;
+
+    let build_body = |value: &str| -> serde_json::Value {
+        json!({
+            "query": graphql_mutatation,
+            "variables": {
+                "update": [{ "key": "timezone", "value": value }]
+            }
+        })
+    };
+
+    let response = send_request(&client, &app.address, &build_body("Europe/London")).await?;
+    assert!(response.data["errors"].is_null());
+    assert_eq!(
+        response.data["data"]["updateCustomer"]["timezone"],
+        json!("Europe/London")
+    );
+
+    let response = send_request(&client, &app.address, &build_body("Not/AZone")).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST",
+                "field": "update",
+                "details": "invalid timezone: Not/AZone"
+            }
+        }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_update_customer_preferred_language_only_accepts_language_tags() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+
+    let graphql_mutatation = r#"
+        mutation updateCustomer($update: [CustomerUpdate!]!) {
+            updateCustomer(update: $update) {
+                id
+                preferredLanguage
+            }
+        }
+    "#;
+
+    let build_body = |value: &str| -> serde_json::Value {
+        json!({
+            "query": graphql_mutatation,
+            "variables": {
+                "update": [{ "key": "preferredLanguage", "value": value }]
+            }
+        })
+    };
+
+    let response = send_request(&client, &app.address, &build_body("fr-FR")).await?;
+    assert!(response.data["errors"].is_null());
+    assert_eq!(
+        response.data["data"]["updateCustomer"]["preferredLanguage"],
+        json!("fr-FR")
+    );
+
+    let response = send_request(&client, &app.address, &build_body("fr_FR")).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST",
+                "field": "update",
+                "details": "invalid language tag: fr_FR"
+            }
+        }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_update_customer_without_known_token_errors() -> Result<()> {
+    let app = spawn_app().await;
+
+    let graphql_mutatation = format!(
+        r#"
+        mutation updateCustomer($update: [CustomerUpdate!]!) {{
+            updateCustomer(update: $update) {{
+                {}
+            }}
+        }}
+    "#,
+        CUSTOMER_GRAPHQL_FIELDS
+    );
+
+    let update = json!([{
+        "key": "firstName",
+        "value": "Clark"
+    }]);
+
+    let body = json!({
+        "query": graphql_mutatation,
+        "variables": {
+            "update": update
+            }
+    });
+
+    let client = build_http_client()?;
+    get_anonymous_token(&client, &app.address).await?;
+
+    // [ No tokens | Anonymous Tokens ]
+    // One client holds no tokens in cookies, the other holds anonymous tokens
+    let test_cases = vec![build_http_client()?, client];
+    let expected = vec![
+        json!([{
+            "message": "Invalid token provided",
+            "extensions": {
+                "status": 401,
+                "statusText": "INVALID_TOKEN",
+                "details": "No token was found"
+            }
         }]),
         json!([{
             "message": "Anonymous users do not have access to this resource",
@@ -636,6 +1332,106 @@ async fn mutation_add_item_to_cart_works() -> Result<()> {
     Ok(())
 }
 
+#[actix_rt::test]
+async fn mutation_editing_cart_advances_last_modified() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+
+    let graphql_mutatation = format!(
+        r#"
+        mutation addItemsToCart($newItems: [UpdateCartItem!]!) {{
+            addItemsToCart(newItems: $newItems) {{
+                {}
+            }}
+        }}
+    "#,
+        SHOPPING_CART_GRAPHQL_FIELDS
+    );
+
+    let body = json!({
+        "query": graphql_mutatation,
+        "variables": {
+            "newItems": [{
+                "sku": "12345678",
+                "quantity": 1
+            }]
+        }
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    let cart = response.data["data"]["addItemsToCart"].clone();
+
+    let last_modified = DateTime::parse_from_rfc3339(&cart["lastModified"].as_str().unwrap())
+        .expect("date should parse correctly with rfc3339");
+    let created_at = DateTime::parse_from_rfc3339(&cart["createdAt"].as_str().unwrap())
+        .expect("date should parse correctly with rfc3339");
+
+    assert!(last_modified > created_at);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_set_cart_items_sets_absolute_quantities() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+    let cart_id = customer.cart_id.unwrap();
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        cart_id,
+        vec![
+            InternalCartItem {
+                sku: "12345678".to_string(),
+                quantity: 5,
+                added_at: None,
+                gift_message: None,
+            },
+            InternalCartItem {
+                sku: "22345678".to_string(),
+                quantity: 2,
+                added_at: None,
+                gift_message: None,
+            },
+        ],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let body = json!({
+        "query": "mutation setCartItems($items: [UpdateCartItem!]!) { setCartItems(items: $items) { id items { sku quantity } } }",
+        "variables": {
+            "items": [
+                { "sku": "12345678", "quantity": 1 },
+                { "sku": "22345678", "quantity": 0 },
+                { "sku": "32345678", "quantity": 4 }
+            ]
+        }
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    let cart = response.data["data"]["setCartItems"].clone();
+
+    assert_json_include!(
+        actual: &cart,
+        expected: json!({
+            "id": cart_id,
+            "items": [
+                { "sku": "12345678", "quantity": 1 },
+                { "sku": "32345678", "quantity": 4 }
+            ]
+        })
+    );
+    assert_eq!(cart["items"].as_array().unwrap().len(), 2);
+
+    let cart = ShoppingCart::find_by_id::<ShoppingCartDatabase>(cart_id, &app.db_pool).await?;
+    assert_eq!(cart.items.len(), 2);
+
+    Ok(())
+}
+
 #[actix_rt::test]
 async fn mutation_remove_item_from_cart_completely_removes_negative_quantities() -> Result<()> {
     let app = spawn_app().await;
@@ -654,7 +1450,10 @@ async fn mutation_remove_item_from_cart_completely_removes_negative_quantities()
         vec![InternalCartItem {
             sku: "12345678".to_string(),
             quantity: 1,
+            added_at: None,
+            gift_message: None,
         }],
+        MAX_DISTINCT_CART_ITEMS,
         &app.db_pool,
     )
     .await?;
@@ -667,7 +1466,10 @@ async fn mutation_remove_item_from_cart_completely_removes_negative_quantities()
         vec![InternalCartItem {
             sku: "12345678".to_string(),
             quantity: 1,
+            added_at: None,
+            gift_message: None,
         }],
+        MAX_DISTINCT_CART_ITEMS,
         &app.db_pool,
     )
     .await?;
@@ -739,49 +1541,167 @@ async fn mutation_remove_item_from_cart_completely_removes_negative_quantities()
 }
 
 #[actix_rt::test]
-async fn mutation_remove_items_from_cart_correctly_handles_leftover_items() -> Result<()> {
+async fn mutation_saved_carts_can_be_listed_and_loaded_back() -> Result<()> {
     let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+    let cart_id = customer.cart_id.unwrap();
 
-    let anon_client = build_http_client()?;
-    let anon_customer = get_anonymous_token(&anon_client, &app.address).await?;
-    let anon_cart_id = anon_customer.cart_id.clone().unwrap();
-
-    let known_client = build_http_client()?;
-    let known_customer = sign_user_up_and_get_known_token(&known_client, &app.address).await?;
-    let known_cart_id = known_customer.cart_id.clone().unwrap();
-    assert_ne!(anon_cart_id, known_cart_id);
-
-    let cart = ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
-        anon_cart_id,
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        cart_id,
         vec![
-            InternalCartItem {
-                sku: "12345678".to_string(),
-                quantity: 5,
-            },
-            InternalCartItem {
-                sku: "22345678".to_string(),
-                quantity: 2,
-            },
+            ("12345678".to_string(), 2).into(),
+            ("22345678".to_string(), 1).into(),
         ],
+        MAX_DISTINCT_CART_ITEMS,
         &app.db_pool,
     )
     .await?;
 
-    assert!(!cart.items.is_empty());
-    assert!(cart.price_before_discounts > 0f64);
-
+    let body = json!({
+        "query": "mutation saveCart($name: String!) { saveCart(name: $name) { id name items { sku quantity } } }",
+        "variables": { "name": "Weekly shop" }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    let saved_cart = response.data["data"]["saveCart"].clone();
+    assert_eq!(saved_cart["name"], "Weekly shop");
+    assert_eq!(
+        saved_cart["items"],
+        json!([
+            { "sku": "12345678", "quantity": 2 },
+            { "sku": "22345678", "quantity": 1 }
+        ])
+    );
+
+    let body = json!({ "query": "query savedCarts { savedCarts { id name } }" });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(
+        response.data["data"]["savedCarts"],
+        json!([{ "id": saved_cart["id"], "name": "Weekly shop" }])
+    );
+
+    // Empty the live cart, then load the saved one back into it
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        cart_id,
+        vec![
+            ("12345678".to_string(), -2).into(),
+            ("22345678".to_string(), -1).into(),
+        ],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+    let cart = ShoppingCart::find_by_id::<ShoppingCartDatabase>(cart_id, &app.db_pool).await?;
+    assert!(cart.items.is_empty());
+
+    let body = json!({
+        "query": "mutation loadSavedCart($id: UUID!) { loadSavedCart(id: $id) { id items { sku quantity } } }",
+        "variables": { "id": saved_cart["id"] }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(
+        response.data["data"]["loadSavedCart"],
+        json!({
+            "id": cart_id,
+            "items": [
+                { "sku": "12345678", "quantity": 2 },
+                { "sku": "22345678", "quantity": 1 }
+            ]
+        })
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_saved_carts_require_a_known_customer() -> Result<()> {
+    let app = spawn_app().await;
+    let known_client = build_http_client()?;
+    let customer = sign_user_up_and_get_known_token(&known_client, &app.address).await?;
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![("12345678".to_string(), 1).into()],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let save_cart = json!({
+        "query": "mutation saveCart($name: String!) { saveCart(name: $name) { id } }",
+        "variables": { "name": "Weekly shop" }
+    });
+    let response = send_request(&known_client, &app.address, &save_cart).await?;
+    let saved_cart_id = response.data["data"]["saveCart"]["id"].clone();
+
+    let anon_client = build_http_client()?;
+    let _anon_customer = get_anonymous_token(&anon_client, &app.address).await?;
+    let response = send_request(&anon_client, &app.address, &save_cart).await?;
+    assert_eq!(response.data["errors"][0]["extensions"]["status"], 401);
+
+    let body = json!({
+        "query": "mutation loadSavedCart($id: UUID!) { loadSavedCart(id: $id) { id } }",
+        "variables": { "id": saved_cart_id }
+    });
+    let response = send_request(&anon_client, &app.address, &body).await?;
+    assert_eq!(response.data["errors"][0]["extensions"]["status"], 401);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_remove_items_from_cart_correctly_handles_leftover_items() -> Result<()> {
+    let app = spawn_app().await;
+
+    let anon_client = build_http_client()?;
+    let anon_customer = get_anonymous_token(&anon_client, &app.address).await?;
+    let anon_cart_id = anon_customer.cart_id.clone().unwrap();
+
+    let known_client = build_http_client()?;
+    let known_customer = sign_user_up_and_get_known_token(&known_client, &app.address).await?;
+    let known_cart_id = known_customer.cart_id.clone().unwrap();
+    assert_ne!(anon_cart_id, known_cart_id);
+
+    let cart = ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        anon_cart_id,
+        vec![
+            InternalCartItem {
+                sku: "12345678".to_string(),
+                quantity: 5,
+                added_at: None,
+                gift_message: None,
+            },
+            InternalCartItem {
+                sku: "22345678".to_string(),
+                quantity: 2,
+                added_at: None,
+                gift_message: None,
+            },
+        ],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    assert!(!cart.items.is_empty());
+    assert!(cart.price_before_discounts > 0f64);
+
     let cart = ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
         known_cart_id,
         vec![
             InternalCartItem {
                 sku: "12345678".to_string(),
                 quantity: 5,
+                added_at: None,
+                gift_message: None,
             },
             InternalCartItem {
                 sku: "22345678".to_string(),
                 quantity: 2,
+                added_at: None,
+                gift_message: None,
             },
         ],
+        MAX_DISTINCT_CART_ITEMS,
         &app.db_pool,
     )
     .await?;
@@ -929,3 +1849,987 @@ async fn mutation_refresh_works() -> Result<()> {
 
     Ok(())
 }
+
+#[actix_rt::test]
+async fn mutation_invalidate_all_tokens_only_invalidates_target_customer() -> Result<()> {
+    let app = spawn_app().await;
+
+    let admin_client = build_http_client()?;
+    let admin = insert_default_customer(&app.db_pool).await?;
+    make_customer_admin(admin.private_id.unwrap(), &app.db_pool).await?;
+    login_customer(&admin_client, &app.address, &admin).await?;
+
+    let target_client = build_http_client()?;
+    let target = sign_user_up_and_get_known_token(&target_client, &app.address).await?;
+
+    let other_client = build_http_client()?;
+    let other = insert_customer(
+        "wonderwoman@test.com",
+        "Themyscira1",
+        "Diana",
+        "Prince",
+        &app.db_pool,
+    )
+    .await?;
+    login_customer(&other_client, &app.address, &other).await?;
+
+    let invalidate_body = json!({
+        "query": r#"
+            mutation invalidateAllTokens($customerId: UUID!) {
+                invalidateAllTokens(customerId: $customerId)
+            }
+        "#,
+        "variables": {
+            "customerId": target.public_id.unwrap()
+        }
+    });
+
+    // Non admins shouldn't be able to invalidate other customer's tokens
+    let response = send_request(&other_client, &app.address, &invalidate_body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Not authorized to request the specified resource",
+            "extensions": {
+                "status": 403,
+                "statusText": "FORBIDDEN"
+            }
+        }])
+    );
+
+    let response = send_request(&admin_client, &app.address, &invalidate_body).await?;
+    assert_json_include!(
+        actual: response.data["data"].clone(),
+        expected: json!({ "invalidateAllTokens": true })
+    );
+
+    let refresh_body = json!({
+        "query": format!(
+            r#"
+            mutation refresh {{
+                refresh {{
+                    {}
+                }}
+            }}
+        "#,
+            TOKEN_GRAPHQL_FIELDS
+        ),
+    });
+
+    let response = send_request(&target_client, &app.address, &refresh_body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Invalid token provided",
+        }])
+    );
+
+    let response = send_request(&other_client, &app.address, &refresh_body).await?;
+    assert!(response.data["errors"].is_null());
+    assert_some!(response.cookies.access);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_delete_cart_gives_the_customer_a_new_cart() -> Result<()> {
+    let app = spawn_app().await;
+
+    let admin_client = build_http_client()?;
+    let admin = insert_default_customer(&app.db_pool).await?;
+    make_customer_admin(admin.private_id.unwrap(), &app.db_pool).await?;
+    login_customer(&admin_client, &app.address, &admin).await?;
+
+    let client = build_http_client()?;
+    let customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+    let cart_id = customer.cart_id.unwrap();
+
+    let delete_body = json!({
+        "query": r#"
+            mutation deleteCart($id: UUID!) {
+                deleteCart(id: $id)
+            }
+        "#,
+        "variables": {
+            "id": cart_id
+        }
+    });
+
+    // Customers shouldn't be able to delete carts
+    let response = send_request(&client, &app.address, &delete_body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 403,
+                "statusText": "FORBIDDEN"
+            }
+        }])
+    );
+
+    let response = send_request(&admin_client, &app.address, &delete_body).await?;
+    assert_json_include!(
+        actual: response.data["data"].clone(),
+        expected: json!({ "deleteCart": true })
+    );
+    assert!(
+        ShoppingCart::find_by_id::<ShoppingCartDatabase>(cart_id, &app.db_pool)
+            .await
+            .is_err()
+    );
+
+    // The customer is pointed at a new, empty cart as part of the delete
+    let stored =
+        Customer::find_by_email::<CustomerDatabase>(customer.email.clone().unwrap(), &app.db_pool)
+            .await?;
+    assert_ne!(stored.cart_id, cart_id);
+    let new_cart =
+        ShoppingCart::find_by_id::<ShoppingCartDatabase>(stored.cart_id, &app.db_pool).await?;
+    assert_eq!(new_cart.customer_id, Some(stored.id));
+    assert!(new_cart.items.is_empty());
+
+    // Deleting it a second time should report that it's missing
+    let response = send_request(&admin_client, &app.address, &delete_body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 404,
+            }
+        }])
+    );
+
+    // Logging in again works straight away and uses the new cart
+    let new_client = build_http_client()?;
+    login_customer(&new_client, &app.address, &customer).await?;
+    let customer_body = json!({
+        "query": r#"
+            query customer {
+                customer {
+                    cart {
+                        id
+                    }
+                }
+            }
+        "#
+    });
+    let response = send_request(&new_client, &app.address, &customer_body).await?;
+    assert!(response.data["errors"].is_null());
+    let new_cart_id: Uuid =
+        serde_json::from_value(response.data["data"]["customer"]["cart"]["id"].clone())?;
+    assert_eq!(new_cart_id, stored.cart_id);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_concurrent_refreshes_do_not_invalidate_each_other() -> Result<()> {
+    let app = spawn_app().await;
+    let customer = insert_default_customer(&app.db_pool).await?;
+    let id = customer.private_id.unwrap();
+
+    // Close enough to expiring that refreshing will rotate the refresh token
+    let refresh_token = create_refresh_token(&customer, 0, chrono::Duration::days(1))?;
+    let body = json!({
+        "query": format!(
+            r#"
+            mutation refresh {{
+                refresh {{
+                    {}
+                }}
+            }}
+        "#,
+            TOKEN_GRAPHQL_FIELDS
+        ),
+    });
+
+    let (first, second) = futures::join!(
+        send_request_with_refresh_token(&app.address, &body, &refresh_token),
+        send_request_with_refresh_token(&app.address, &body, &refresh_token)
+    );
+
+    let counter =
+        Customer::fetch_refresh_token_counter::<CustomerDatabase>(id, &app.db_pool).await?;
+    assert_eq!(counter, 1);
+    for response in vec![first?, second?] {
+        assert!(response.data["errors"].is_null());
+        let refresh = response
+            .cookies
+            .refresh
+            .expect("a new refresh token should be issued");
+        assert_eq!(refresh.claims.count, Some(counter));
+    }
+
+    // The rotated token should still work once the race is over
+    let response = send_request_with_refresh_token(&app.address, &body, &refresh_token).await?;
+    assert!(response.data["errors"].is_null());
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_invalidated_refresh_tokens_get_no_grace_period() -> Result<()> {
+    let app = spawn_app().await;
+    let customer = insert_default_customer(&app.db_pool).await?;
+    let id = customer.private_id.unwrap();
+    let refresh_token = create_refresh_token(&customer, 0, chrono::Duration::days(1))?;
+
+    Customer::increment_refresh_token_counter::<CustomerDatabase>(id, &app.db_pool).await?;
+
+    let body = json!({
+        "query": format!(
+            r#"
+            mutation refresh {{
+                refresh {{
+                    {}
+                }}
+            }}
+        "#,
+            TOKEN_GRAPHQL_FIELDS
+        ),
+    });
+    let response = send_request_with_refresh_token(&app.address, &body, &refresh_token).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{ "message": "Invalid token provided" }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_add_item_to_cart_with_expected_price_detects_mismatches() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    let graphql_mutatation = format!(
+        r#"
+        mutation addItemsToCart($newItems: [UpdateCartItem!]!) {{
+            addItemsToCart(newItems: $newItems) {{
+                {}
+            }}
+        }}
+    "#,
+        SHOPPING_CART_GRAPHQL_FIELDS
+    );
+
+    let body = json!({
+        "query": graphql_mutatation,
+        "variables": {
+            "newItems": [{
+                "sku": "12345678",
+                "quantity": 1,
+                "expectedPricePerUnit": 0.99
+            }]
+        }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    let cart = response.data["data"]["addItemsToCart"].clone();
+    assert_json_include!(
+        actual: &cart,
+        expected: json!({
+            "id": customer.cart_id.unwrap(),
+            "items": [{
+                "sku": "12345678",
+                "quantity": 1,
+            }],
+        })
+    );
+
+    let body = json!({
+        "query": graphql_mutatation,
+        "variables": {
+            "newItems": [{
+                "sku": "12345678",
+                "quantity": 1,
+                "expectedPricePerUnit": 0.50
+            }]
+        }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Bad Request: Price of 12345678 has changed, current price is 0.99",
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST"
+            }
+        }])
+    );
+
+    // The rejected item should not have been added
+    let cart =
+        ShoppingCart::find_by_id::<ShoppingCartDatabase>(customer.cart_id.unwrap(), &app.db_pool)
+            .await?;
+    assert_eq!(cart.items[0].quantity, 1);
+
+    // A price can't be confirmed for an item that doesn't exist
+    let body = json!({
+        "query": graphql_mutatation,
+        "variables": {
+            "newItems": [{
+                "sku": "not-a-real-sku",
+                "quantity": 1,
+                "expectedPricePerUnit": 0.99
+            }]
+        }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 404,
+                "statusText": "NOT_FOUND",
+                "details": "not-a-real-sku"
+            }
+        }])
+    );
+    let cart =
+        ShoppingCart::find_by_id::<ShoppingCartDatabase>(customer.cart_id.unwrap(), &app.db_pool)
+            .await?;
+    assert_eq!(cart.items.len(), 1);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_login_records_the_last_login() -> Result<()> {
+    let app = spawn_app().await;
+    let customer = insert_default_customer(&app.db_pool).await?;
+    let id = customer.private_id.unwrap();
+
+    let before = Customer::find_by_id::<CustomerDatabase>(id, &app.db_pool).await?;
+    assert!(before.last_login_at.is_none());
+
+    let client = build_http_client()?;
+    login_customer(&client, &app.address, &customer).await?;
+    let first_login = Customer::find_by_id::<CustomerDatabase>(id, &app.db_pool)
+        .await?
+        .last_login_at
+        .expect("login should record the last login");
+
+    let body = json!({ "query": "query customer { customer { lastLoginAt } }" });
+    let response = send_request(&client, &app.address, &body).await?;
+    let last_login_at = response.data["data"]["customer"]["lastLoginAt"]
+        .as_str()
+        .expect("last login should be returned");
+    assert_eq!(DateTime::parse_from_rfc3339(last_login_at)?, first_login);
+
+    let other_client = build_http_client()?;
+    login_customer(&other_client, &app.address, &customer).await?;
+    let second_login = Customer::find_by_id::<CustomerDatabase>(id, &app.db_pool)
+        .await?
+        .last_login_at
+        .expect("login should record the last login");
+    assert!(second_login > first_login);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_login_repairs_a_stale_cart_pointer() -> Result<()> {
+    let app = spawn_app().await;
+    let customer = insert_default_customer(&app.db_pool).await?;
+    let id = customer.private_id.unwrap();
+    let cart_id = customer.cart_id.unwrap();
+
+    sqlx::query("UPDATE customers SET cart_id = $1 WHERE id = $2")
+        .bind(Uuid::new_v4())
+        .bind(id)
+        .execute(&app.db_pool)
+        .await?;
+
+    let client = build_http_client()?;
+    login_customer(&client, &app.address, &customer).await?;
+
+    let repaired = Customer::find_by_id::<CustomerDatabase>(id, &app.db_pool).await?;
+    assert_eq!(repaired.cart_id, cart_id);
+
+    let body = json!({ "query": "query customer { customer { cart { id } } }" });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(
+        response.data["data"]["customer"]["cart"]["id"],
+        json!(cart_id)
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_add_items_to_cart_enforces_the_line_limit() -> Result<()> {
+    let app = spawn_app_with_configuration(|configuration| {
+        configuration.cart.max_distinct_items = 2;
+    })
+    .await;
+    let client = build_http_client()?;
+    let _customer = get_anonymous_token(&client, &app.address).await?;
+
+    let add_items = |skus: Vec<&str>| {
+        json!({
+            "query": "mutation addItemsToCart($newItems: [UpdateCartItem!]!) { addItemsToCart(newItems: $newItems) { items { sku quantity } } }",
+            "variables": {
+                "newItems": skus
+                    .into_iter()
+                    .map(|sku| json!({ "sku": sku, "quantity": 1 }))
+                    .collect::<Vec<_>>()
+            }
+        })
+    };
+
+    let response = send_request(
+        &client,
+        &app.address,
+        &add_items(vec!["12345678", "22345678"]),
+    )
+    .await?;
+    assert!(response.data["errors"].is_null());
+
+    let response = send_request(&client, &app.address, &add_items(vec!["32345678"])).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Bad Request: cart line limit reached",
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST"
+            }
+        }])
+    );
+
+    // Items already in the cart can still be adjusted
+    let response = send_request(&client, &app.address, &add_items(vec!["12345678"])).await?;
+    assert_json_include!(
+        actual: response.data["data"]["addItemsToCart"].clone(),
+        expected: json!({
+            "items": [
+                { "sku": "12345678", "quantity": 2 },
+                { "sku": "22345678", "quantity": 1 }
+            ]
+        })
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_set_cart_items_reports_every_invalid_item() -> Result<()> {
+    let app = spawn_app_with_configuration(|configuration| {
+        configuration.cart.max_distinct_items = 2;
+    })
+    .await;
+    let client = build_http_client()?;
+    let _customer = get_anonymous_token(&client, &app.address).await?;
+
+    let body = json!({
+        "query": "mutation setCartItems($items: [UpdateCartItem!]!) { setCartItems(items: $items) { id } }",
+        "variables": {
+            "items": [
+                { "sku": "bad sku", "quantity": 1 },
+                { "sku": "12345678", "quantity": 1000 },
+                { "sku": "22345678", "quantity": 1 }
+            ]
+        }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST",
+                "details": "invalid sku: bad sku; quantity of 12345678 can't be more than 999; a cart can't hold more than 2 different items"
+            }
+        }])
+    );
+
+    // Nothing should have been added to the cart
+    let query = json!({ "query": "query { cart { items { sku } } }" });
+    let response = send_request(&client, &app.address, &query).await?;
+    assert_eq!(response.data["data"]["cart"]["items"], json!([]));
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_add_items_to_cart_keeps_unknown_skus_by_default() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = get_anonymous_token(&client, &app.address).await?;
+
+    let body = json!({
+        "query": "mutation addItemsToCart($newItems: [UpdateCartItem!]!) { addItemsToCart(newItems: $newItems) { items { sku quantity } priceAfterDiscounts } }",
+        "variables": {
+            "newItems": [
+                { "sku": "12345678", "quantity": 1 },
+                { "sku": "not-a-sku", "quantity": 1 }
+            ]
+        }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+    let cart = response.data["data"]["addItemsToCart"].clone();
+    assert_json_include!(
+        actual: &cart,
+        expected: json!({
+            "items": [
+                { "sku": "12345678", "quantity": 1 },
+                { "sku": "not-a-sku", "quantity": 1 }
+            ]
+        })
+    );
+    assert_on_decimal(cart["priceAfterDiscounts"].as_f64().unwrap(), 0.99);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_add_items_to_cart_rejects_unknown_skus_when_strict() -> Result<()> {
+    let app = spawn_app_with_configuration(|configuration| {
+        configuration.cart.strict_skus = true;
+    })
+    .await;
+    let client = build_http_client()?;
+    let _customer = get_anonymous_token(&client, &app.address).await?;
+
+    let body = json!({
+        "query": "mutation addItemsToCart($newItems: [UpdateCartItem!]!) { addItemsToCart(newItems: $newItems) { items { sku quantity } } }",
+        "variables": {
+            "newItems": [
+                { "sku": "12345678", "quantity": 1 },
+                { "sku": "not-a-sku", "quantity": 1 }
+            ]
+        }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Could not find item",
+            "extensions": {
+                "status": 404,
+                "statusText": "NOT_FOUND",
+                "details": "not-a-sku"
+            }
+        }])
+    );
+
+    // Nothing should have been added to the cart
+    let query = json!({ "query": "query { cart { items { sku } } }" });
+    let response = send_request(&client, &app.address, &query).await?;
+    assert_eq!(response.data["data"]["cart"]["items"], json!([]));
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_recover_cart_restores_an_anonymous_cart() -> Result<()> {
+    let app = spawn_app().await;
+    let original_client = build_http_client()?;
+    let customer = get_anonymous_token(&original_client, &app.address).await?;
+    let cart_id = customer.cart_id.unwrap();
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        cart_id,
+        vec![InternalCartItem {
+            sku: "12345678".to_string(),
+            quantity: 2,
+            added_at: None,
+            gift_message: None,
+        }],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    // A client which has lost its cookies
+    let client = build_http_client()?;
+    let body = json!({
+        "query": "mutation recoverCart($cartId: UUID!) { recoverCart(cartId: $cartId) { tokenType } }",
+        "variables": { "cartId": cart_id }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+    assert_some!(response.cookies.raw_access);
+
+    let body = json!({ "query": "query cart { cart { id cartType items { sku quantity } } }" });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["data"]["cart"].clone(),
+        expected: json!({
+            "id": cart_id,
+            "cartType": "ANONYMOUS",
+            "items": [{ "sku": "12345678", "quantity": 2 }]
+        })
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_recover_cart_rejects_a_known_cart() -> Result<()> {
+    let app = spawn_app().await;
+    let known_client = build_http_client()?;
+    let known_customer = sign_user_up_and_get_known_token(&known_client, &app.address).await?;
+
+    let client = build_http_client()?;
+    let body = json!({
+        "query": "mutation recoverCart($cartId: UUID!) { recoverCart(cartId: $cartId) { tokenType } }",
+        "variables": { "cartId": known_customer.cart_id.unwrap() }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Not authorized to request the specified resource",
+            "extensions": {
+                "status": 403,
+                "statusText": "FORBIDDEN"
+            }
+        }])
+    );
+    assert!(response.cookies.raw_access.is_none());
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_recover_cart_with_a_malformed_uuid_returns_bad_request() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let body = json!({
+        "query": r#"mutation { recoverCart(cartId: "not-a-uuid") { tokenType } }"#,
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST",
+                "field": "cartId"
+            }
+        }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_login_outcomes_are_recorded_in_metrics() -> Result<()> {
+    let app = spawn_app().await;
+    let customer = insert_default_customer(&app.db_pool).await?;
+
+    let body = json!({
+        "query": "mutation login($email: String!, $password: String!) { login(email: $email, password: $password) { tokenType } }",
+        "variables": {
+            "email": customer.email.clone().unwrap(),
+            "password": "not the right password"
+        }
+    });
+    let client = build_http_client()?;
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_array());
+
+    login_customer(&client, &app.address, &customer).await?;
+
+    let metrics = client
+        .get(&format!("{}/metrics", app.address))
+        .send()
+        .await?
+        .text()
+        .await?;
+    assert!(metrics.contains(
+        r#"bazaar_auth_attempts_total{operation="login",outcome="incorrect_credentials"} 1"#
+    ));
+    assert!(
+        metrics.contains(r#"bazaar_auth_attempts_total{operation="login",outcome="success"} 1"#)
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_enabling_two_factor_requires_a_code_on_login() -> Result<()> {
+    let app = spawn_app().await;
+    let customer = insert_default_customer(&app.db_pool).await?;
+    let client = build_http_client()?;
+    login_customer(&client, &app.address, &customer).await?;
+
+    let body = json!({ "query": "mutation { enableTwoFactor { secret uri } }" });
+    let response = send_request(&client, &app.address, &body).await?;
+    let setup = response.data["data"]["enableTwoFactor"].clone();
+    let secret = setup["secret"].as_str().expect("should return the secret");
+    assert!(setup["uri"]
+        .as_str()
+        .expect("should return the uri")
+        .starts_with("otpauth://totp/Bazaar:imbatman%40test.com?secret="));
+
+    let body = json!({
+        "query": "mutation confirmTwoFactor($code: String!) { confirmTwoFactor(code: $code) }",
+        "variables": { "code": "000000" }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "statusText": "BAD_REQUEST",
+                "details": "Invalid two factor code"
+            }
+        }])
+    );
+
+    let body = json!({
+        "query": "mutation confirmTwoFactor($code: String!) { confirmTwoFactor(code: $code) }",
+        "variables": { "code": two_factor_code(secret, 0)? }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_eq!(response.data["data"]["confirmTwoFactor"], true);
+
+    let login = "mutation login($email: String!, $password: String!, $totpCode: String) { login(email: $email, password: $password, totpCode: $totpCode) { tokenType } }";
+    let body = json!({
+        "query": login,
+        "variables": {
+            "email": customer.email.clone().unwrap(),
+            "password": customer.password.clone().unwrap()
+        }
+    });
+    let response = send_request(&build_http_client()?, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Two factor authentication code required",
+            "extensions": {
+                "status": 401,
+                "statusText": "TWO_FACTOR_REQUIRED"
+            }
+        }])
+    );
+    assert!(response.cookies.raw_access.is_none());
+
+    // The code used to confirm has been used up, but the next step's is still accepted
+    // to allow for clock drift
+    let body = json!({
+        "query": login,
+        "variables": {
+            "email": customer.email.clone().unwrap(),
+            "password": customer.password.clone().unwrap(),
+            "totpCode": two_factor_code(secret, 1)?
+        }
+    });
+    let response = send_request(&build_http_client()?, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+    assert_some!(response.cookies.raw_access);
+
+    // Codes can't be replayed
+    let response = send_request(&build_http_client()?, &app.address, &body).await?;
+    assert_eq!(response.data["errors"][0]["extensions"]["status"], 401);
+    assert!(response.cookies.raw_access.is_none());
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_import_cart_replaces_the_customers_cart() -> Result<()> {
+    let app = spawn_app().await;
+
+    let admin_client = build_http_client()?;
+    let admin = insert_default_customer(&app.db_pool).await?;
+    make_customer_admin(admin.private_id.unwrap(), &app.db_pool).await?;
+    login_customer(&admin_client, &app.address, &admin).await?;
+
+    let client = build_http_client()?;
+    let customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+    let body = json!({
+        "query": "mutation addItemsToCart($items: [UpdateCartItem!]!) { addItemsToCart(newItems: $items) { id } }",
+        "variables": { "items": [{ "sku": "12345678", "quantity": 5 }] }
+    });
+    send_request(&client, &app.address, &body).await?;
+
+    let import = r#"
+        mutation importCart($customerId: UUID!, $items: [UpdateCartItem!]!) {
+            importCart(customerId: $customerId, items: $items) {
+                id
+                items { sku quantity }
+                priceBeforeDiscounts
+                priceAfterDiscounts
+            }
+        }
+    "#;
+    let body = json!({
+        "query": import,
+        "variables": {
+            "customerId": customer.public_id.unwrap(),
+            "items": [
+                { "sku": "12345678", "quantity": 2 },
+                { "sku": "22345678", "quantity": 1 }
+            ]
+        }
+    });
+
+    // Customers shouldn't be able to import carts
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{ "extensions": { "status": 403 } }])
+    );
+
+    let response = send_request(&admin_client, &app.address, &body).await?;
+    let cart = response.data["data"]["importCart"].clone();
+    assert_json_include!(
+        actual: &cart,
+        expected: json!({
+            "id": customer.cart_id.unwrap(),
+            "items": [
+                { "sku": "12345678", "quantity": 2 },
+                { "sku": "22345678", "quantity": 1 }
+            ]
+        })
+    );
+    assert_on_decimal(cart["priceBeforeDiscounts"].as_f64().unwrap(), 12.48);
+    assert_on_decimal(cart["priceAfterDiscounts"].as_f64().unwrap(), 12.48);
+
+    let body = json!({
+        "query": import,
+        "variables": {
+            "customerId": customer.public_id.unwrap(),
+            "items": [{ "sku": "not-a-sku", "quantity": 1 }]
+        }
+    });
+    let response = send_request(&admin_client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 404,
+                "details": "not-a-sku"
+            }
+        }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_adjust_stock_updates_the_stock_level() -> Result<()> {
+    let app = spawn_app().await;
+
+    let admin_client = build_http_client()?;
+    let admin = insert_default_customer(&app.db_pool).await?;
+    make_customer_admin(admin.private_id.unwrap(), &app.db_pool).await?;
+    login_customer(&admin_client, &app.address, &admin).await?;
+
+    let adjust = |sku: &str, delta: i32| {
+        json!({
+            "query": "mutation adjustStock($sku: String!, $delta: Int!) { adjustStock(sku: $sku, delta: $delta) }",
+            "variables": { "sku": sku, "delta": delta }
+        })
+    };
+
+    // Customers shouldn't be able to adjust stock
+    let client = build_http_client()?;
+    let _customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+    let response = send_request(&client, &app.address, &adjust("42345678", 5)).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{ "extensions": { "status": 403 } }])
+    );
+
+    let response = send_request(&admin_client, &app.address, &adjust("42345678", 5)).await?;
+    assert_eq!(response.data["data"]["adjustStock"], json!(30));
+
+    let response = send_request(&admin_client, &app.address, &adjust("42345678", -31)).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "details": "Stock can't be reduced below zero"
+            }
+        }])
+    );
+
+    // The rejected adjustment shouldn't have changed anything
+    let response = send_request(&admin_client, &app.address, &adjust("42345678", -30)).await?;
+    assert_eq!(response.data["data"]["adjustStock"], json!(0));
+
+    let response = send_request(&admin_client, &app.address, &adjust("not-a-sku", 1)).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{ "extensions": { "status": 404, "details": "not-a-sku" } }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn mutation_set_cart_gift_options_works() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![InternalCartItem {
+            sku: "12345678".to_string(),
+            quantity: 1,
+            added_at: None,
+            gift_message: None,
+        }],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let set_gift_options = |gift_messages: serde_json::Value| {
+        json!({
+            "query": "mutation setCartGiftOptions($giftMessages: [GiftMessage!]) { setCartGiftOptions(giftWrap: true, giftMessages: $giftMessages) { giftWrap items { sku giftMessage } } }",
+            "variables": { "giftMessages": gift_messages }
+        })
+    };
+
+    let body = set_gift_options(json!([{ "sku": "12345678", "message": "Happy birthday!" }]));
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+
+    let query = json!({ "query": "query { cart { giftWrap items { sku giftMessage } } }" });
+    let response = send_request(&client, &app.address, &query).await?;
+    assert_eq!(
+        response.data["data"]["cart"],
+        json!({
+            "giftWrap": true,
+            "items": [{ "sku": "12345678", "giftMessage": "Happy birthday!" }]
+        })
+    );
+
+    let body = set_gift_options(json!([{ "sku": "12345678", "message": "a".repeat(201) }]));
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "details": "Gift messages can't be longer than 200 characters"
+            }
+        }])
+    );
+
+    let body = set_gift_options(json!([{ "sku": "22345678", "message": "Happy birthday!" }]));
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "extensions": {
+                "status": 400,
+                "details": "22345678 isn't in the cart"
+            }
+        }])
+    );
+
+    Ok(())
+}
@@ -1,7 +1,15 @@
 use anyhow::Result;
 use assert_json_diff::assert_json_include;
+use chrono::{DateTime, Duration, Utc};
 use serde_json::json;
 
+use bazaar::{
+    auth::encode_email_change_token,
+    database::{CartItemDatabase, ShoppingCartDatabase},
+    models::{cart_item::InternalCartItem, ShoppingCart},
+    routes::OperationAllowList,
+};
+
 mod helpers;
 use helpers::*;
 
@@ -135,6 +143,44 @@ async fn query_customer_with_known_user_includes_cart() -> Result<()> {
     Ok(())
 }
 
+#[actix_rt::test]
+async fn query_customer_recreates_a_missing_cart() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = sign_user_up_and_get_known_token(&client, &app.address).await?;
+    let original_cart_id = customer.cart_id.unwrap();
+
+    sqlx::query("DELETE FROM shopping_carts WHERE id = $1")
+        .bind(original_cart_id)
+        .execute(&app.db_pool)
+        .await?;
+
+    let body = json!({
+        "query": "query customer { customer { cart { id cartType currency items { sku } } } }",
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+    let cart = response.data["data"]["customer"]["cart"].clone();
+    let new_cart_id = cart["id"].clone();
+    assert_ne!(new_cart_id, json!(original_cart_id));
+    assert_json_include!(
+        actual: cart,
+        expected: json!({
+            "cartType": "KNOWN",
+            "currency": "GBP",
+            "items": [],
+        })
+    );
+
+    // The customer should now reference the new cart, rather than creating another
+    let response = send_request(&client, &app.address, &body).await?;
+    let cart = response.data["data"]["customer"]["cart"].clone();
+    assert_eq!(cart["id"], new_cart_id);
+
+    Ok(())
+}
+
 #[actix_rt::test]
 async fn query_cart_works_for_anonymous_user() -> Result<()> {
     let app = spawn_app().await;
@@ -213,6 +259,235 @@ async fn query_cart_works_for_known_user() -> Result<()> {
     Ok(())
 }
 
+#[actix_rt::test]
+async fn query_cart_items_reports_stock_levels() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![
+            InternalCartItem {
+                sku: "12345678".to_string(),
+                quantity: 2,
+                added_at: None,
+                gift_message: None,
+            },
+            InternalCartItem {
+                sku: "52345678".to_string(),
+                quantity: 1,
+                added_at: None,
+                gift_message: None,
+            },
+        ],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let body = json!({
+        "query": "query cart { cart { items { sku quantity available inStock } } }",
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    let data = response.data["data"]["cart"].clone();
+
+    assert_json_include!(
+        actual: &data,
+        expected: json!({
+            "items": [
+                {
+                    "sku": "12345678",
+                    "quantity": 2,
+                    "available": 100,
+                    "inStock": true
+                },
+                {
+                    "sku": "52345678",
+                    "quantity": 1,
+                    "available": 0,
+                    "inStock": false
+                }
+            ],
+        })
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_with_a_removed_item_returns_a_cart_warning() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![
+            InternalCartItem {
+                sku: "12345678".to_string(),
+                quantity: 1,
+                added_at: None,
+                gift_message: None,
+            },
+            InternalCartItem {
+                sku: "22345678".to_string(),
+                quantity: 1,
+                added_at: None,
+                gift_message: None,
+            },
+        ],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    sqlx::query("DELETE FROM items WHERE sku = $1")
+        .bind("12345678")
+        .execute(&app.db_pool)
+        .await?;
+
+    let body = json!({
+        "query": "query cart { cart { items { sku } } }",
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+    assert_eq!(
+        response.data["data"]["cart"]["items"],
+        json!([{ "sku": "22345678" }])
+    );
+    assert_eq!(
+        response.data["extensions"]["cartWarnings"],
+        json!([{
+            "sku": "12345678",
+            "message": "Item is no longer available"
+        }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_items_line_total_matches_the_cart_total() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![InternalCartItem {
+            sku: "12345678".to_string(),
+            quantity: 3,
+            added_at: None,
+            gift_message: None,
+        }],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let body = json!({
+        "query": "query cart { cart { priceBeforeDiscounts items { pricePerUnit quantity lineTotal } } }",
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    let cart = response.data["data"]["cart"].clone();
+    let item = &cart["items"][0];
+    let line_total = item["lineTotal"].as_f64().unwrap();
+
+    assert_eq!(item["quantity"], 3);
+    assert_on_decimal(line_total, item["pricePerUnit"].as_f64().unwrap() * 3.0);
+    // It's the only line in the cart, so it should account for the whole total
+    assert_on_decimal(line_total, cart["priceBeforeDiscounts"].as_f64().unwrap());
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_items_have_a_recent_added_at() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+    let before = Utc::now() - Duration::seconds(1);
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![InternalCartItem {
+            sku: "12345678".to_string(),
+            quantity: 1,
+            added_at: None,
+            gift_message: None,
+        }],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let body = json!({
+        "query": "query cart { cart { items { sku addedAt } } }",
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    let added_at = response.data["data"]["cart"]["items"][0]["addedAt"]
+        .as_str()
+        .expect("new items should have an addedAt");
+    let added_at =
+        DateTime::parse_from_rfc3339(added_at).expect("date should parse correctly with rfc3339");
+
+    assert!(added_at > before);
+    assert!(added_at <= Utc::now());
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_items_returns_an_error_when_the_items_lookup_fails() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![InternalCartItem {
+            sku: "12345678".to_string(),
+            quantity: 1,
+            added_at: None,
+            gift_message: None,
+        }],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    sqlx::query("ALTER TABLE items RENAME TO items_unavailable")
+        .execute(&app.db_pool)
+        .await?;
+
+    let body = json!({
+        "query": "query cart { cart { items { sku quantity } } }",
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    let errors = response.data["errors"].clone();
+
+    assert_json_include!(
+        actual: &errors,
+        expected: json!([
+            {
+                "message": "A server error occurred",
+                "extensions": {
+                    "status": 500,
+                    "statusText": "SERVER_ERROR"
+                }
+            }
+        ])
+    );
+
+    Ok(())
+}
+
 #[actix_rt::test]
 async fn query_health_check_works() -> Result<()> {
     let app = spawn_app().await;
@@ -226,3 +501,724 @@ async fn query_health_check_works() -> Result<()> {
 
     Ok(())
 }
+
+#[actix_rt::test]
+async fn query_server_time_returns_the_current_time() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let before = Utc::now();
+    let body = json!({ "query": "{ serverTime }" });
+    let response = send_request(&client, &app.address, &body).await?;
+
+    let server_time = response.data["data"]["serverTime"]
+        .as_str()
+        .expect("should return the server time");
+    let server_time = DateTime::parse_from_rfc3339(server_time)?.with_timezone(&Utc);
+    assert!(server_time >= before - Duration::seconds(1));
+    assert!(server_time <= Utc::now() + Duration::seconds(1));
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_token_status_reports_when_to_refresh() -> Result<()> {
+    let app = spawn_app().await;
+    let body = json!({
+        "query": "{ tokenStatus { accessExpiresInSeconds refreshExpiresInSeconds shouldRefresh } }"
+    });
+
+    // Freshly issued tokens don't need refreshing yet
+    let client = build_http_client()?;
+    let _customer = get_anonymous_token(&client, &app.address).await?;
+    let response = send_request(&client, &app.address, &body).await?;
+    let status = response.data["data"]["tokenStatus"].clone();
+    assert_eq!(status["shouldRefresh"], json!(false));
+    assert!(status["accessExpiresInSeconds"].as_i64().unwrap() > 0);
+    assert!(status["refreshExpiresInSeconds"].as_i64().unwrap() > Duration::days(27).num_seconds());
+
+    // A refresh token that's about to expire should be refreshed
+    let customer = insert_default_customer(&app.db_pool).await?;
+    let refresh_token = create_refresh_token(&customer, 0, Duration::days(1))?;
+    let response = send_request_with_refresh_token(&app.address, &body, &refresh_token).await?;
+    let status = response.data["data"]["tokenStatus"].clone();
+    assert_eq!(status["shouldRefresh"], json!(true));
+    assert!(status["accessExpiresInSeconds"].is_null());
+    assert!(status["refreshExpiresInSeconds"].as_i64().unwrap() <= Duration::days(1).num_seconds());
+
+    // Without a refresh token there's nothing to refresh with
+    let response = send_request(&build_http_client()?, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{ "extensions": { "status": 401 } }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_metadata_returns_enum_variants() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let body = json!({
+        "query": r#"
+            query metadata {
+                metadata {
+                    cartTypes {
+                        value
+                        label
+                    }
+                    currencies {
+                        value
+                    }
+                }
+            }
+        "#
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+
+    assert_eq!(
+        response.data["data"]["metadata"]["cartTypes"],
+        json!([
+            { "value": "ANONYMOUS", "label": "Anonymous" },
+            { "value": "KNOWN", "label": "Known" }
+        ])
+    );
+    assert_eq!(
+        response.data["data"]["metadata"]["currencies"],
+        json!([{ "value": "GBP" }, { "value": "USD" }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_from_share_link_returns_the_cart_items() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![("12345678".to_string(), 3).into()],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let body = json!({
+        "query": r#"
+            mutation createCartShareLink {
+                createCartShareLink
+            }
+        "#,
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    let token = response.data["data"]["createCartShareLink"].clone();
+    assert!(token.is_string());
+
+    // The link can be viewed without being logged in
+    let viewer = build_http_client()?;
+    let body = json!({
+        "query": r#"
+            query cartFromShareLink($token: String!) {
+                cartFromShareLink(token: $token) {
+                    currency
+                    items { sku quantity }
+                }
+            }
+        "#,
+        "variables": {
+            "token": token
+        }
+    });
+    let response = send_request(&viewer, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["data"]["cartFromShareLink"].clone(),
+        expected: json!({
+            "currency": "GBP",
+            "items": [{ "sku": "12345678", "quantity": 3 }]
+        })
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_from_an_expired_share_link_is_rejected() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+    let token = create_cart_share_token(customer.cart_id.unwrap(), Duration::minutes(-1))?;
+
+    let body = json!({
+        "query": r#"
+            query cartFromShareLink($token: String!) {
+                cartFromShareLink(token: $token) {
+                    items { sku }
+                }
+            }
+        "#,
+        "variables": {
+            "token": token
+        }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert_json_include!(
+        actual: response.data["errors"].clone(),
+        expected: json!([{
+            "message": "Invalid token provided",
+            "extensions": {
+                "status": 401,
+                "statusText": "TOKEN_EXPIRED",
+                "details": "Token has expired"
+            }
+        }])
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_account_activity_returns_events_newest_first() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = insert_default_customer(&app.db_pool).await?;
+    login_customer(&client, &app.address, &customer).await?;
+
+    sqlx::query("UPDATE customers SET pending_email = $1 WHERE id = $2")
+        .bind("new@test.com")
+        .bind(customer.private_id.unwrap())
+        .execute(&app.db_pool)
+        .await?;
+    let token = encode_email_change_token(customer.public_id.unwrap(), "new@test.com")?;
+    let body = json!({
+        "query": "mutation confirmEmailChange($token: String!) { confirmEmailChange(token: $token) { id } }",
+        "variables": { "token": token }
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    assert!(response.data["errors"].is_null());
+
+    let body = json!({
+        "query": r#"
+            query accountActivity {
+                accountActivity {
+                    kind
+                    ipAddress
+                    createdAt
+                }
+            }
+        "#
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    let events = response.data["data"]["accountActivity"]
+        .as_array()
+        .expect("should return the customer's events")
+        .clone();
+
+    let kinds: Vec<&str> = events.iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, vec!["EMAIL_CHANGE", "LOGIN"]);
+    assert!(events.iter().all(|e| e["ipAddress"].is_string()));
+
+    let newest = DateTime::parse_from_rfc3339(events[0]["createdAt"].as_str().unwrap())?;
+    let oldest = DateTime::parse_from_rfc3339(events[1]["createdAt"].as_str().unwrap())?;
+    assert!(newest >= oldest);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_estimated_delivery_is_sooner_for_express() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = get_anonymous_token(&client, &app.address).await?;
+
+    let body = json!({
+        "query": r#"
+            query cart {
+                cart {
+                    standard: estimatedDelivery { earliest latest }
+                    express: estimatedDelivery(method: EXPRESS) { earliest latest }
+                }
+            }
+        "#,
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    let cart = response.data["data"]["cart"].clone();
+    let date = |value: &serde_json::Value| {
+        chrono::NaiveDate::parse_from_str(value.as_str().unwrap(), "%Y-%m-%d").unwrap()
+    };
+
+    let today = Utc::now().naive_utc().date();
+    assert!(date(&cart["express"]["earliest"]) > today);
+    assert!(date(&cart["express"]["earliest"]) <= date(&cart["express"]["latest"]));
+    assert!(date(&cart["express"]["latest"]) < date(&cart["standard"]["earliest"]));
+    assert!(date(&cart["standard"]["earliest"]) <= date(&cart["standard"]["latest"]));
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_shipping_options_exclude_methods_the_cart_is_too_heavy_for() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = get_anonymous_token(&client, &app.address).await?;
+
+    let add_items = |sku: &str, quantity: u32| {
+        json!({
+            "query": "mutation addItemsToCart($newItems: [UpdateCartItem!]!) { addItemsToCart(newItems: $newItems) { id } }",
+            "variables": { "newItems": [{ "sku": sku, "quantity": quantity }] }
+        })
+    };
+    let shipping_options = json!({
+        "query": "query { cart { shippingOptions { method price estimatedDelivery { earliest } } } }"
+    });
+    let methods = |response: &serde_json::Value| {
+        response["data"]["cart"]["shippingOptions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|option| option["method"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // 100g
+    send_request(&client, &app.address, &add_items("12345678", 1)).await?;
+    let response = send_request(&client, &app.address, &shipping_options).await?;
+    assert_eq!(methods(&response.data), vec!["STANDARD", "EXPRESS"]);
+    let options = response.data["data"]["cart"]["shippingOptions"].clone();
+    assert_on_decimal(options[0]["price"].as_f64().unwrap(), 3.99);
+    assert_on_decimal(options[1]["price"].as_f64().unwrap(), 9.99);
+
+    // 7.6kg, over the 5kg limit on express
+    send_request(&client, &app.address, &add_items("32345678", 3)).await?;
+    let response = send_request(&client, &app.address, &shipping_options).await?;
+    assert_eq!(methods(&response.data), vec!["STANDARD"]);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_shipping_options_are_priced_in_the_carts_currency() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let sign_up = format!(
+        r#"
+        mutation {{
+            signUp(email: "usd@test.com", password: "Passw0rd!", firstName: "Tony", lastName: "Stark", acceptedTerms: true, acceptedPrivacyPolicy: true, preferredCurrency: USD) {{
+               {}
+            }}
+        }}
+    "#,
+        TOKEN_GRAPHQL_FIELDS
+    );
+    let response = send_request(&client, &app.address, &json!({ "query": sign_up })).await?;
+    assert!(response.data["errors"].is_null());
+
+    let body = json!({
+        "query": "query { cart { shippingOptions { method price currency } } }"
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    let options = response.data["data"]["cart"]["shippingOptions"].clone();
+    assert_json_include!(
+        actual: options.clone(),
+        expected: json!([
+            { "method": "STANDARD", "currency": "USD" },
+            { "method": "EXPRESS", "currency": "USD" }
+        ])
+    );
+    assert_on_decimal(options[0]["price"].as_f64().unwrap(), 4.99);
+    assert_on_decimal(options[1]["price"].as_f64().unwrap(), 12.99);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_search_customers_matches_name_and_email_fragments() -> Result<()> {
+    let app = spawn_app().await;
+    let admin_client = build_http_client()?;
+    let admin = insert_default_customer(&app.db_pool).await?;
+    make_customer_admin(admin.private_id.unwrap(), &app.db_pool).await?;
+    login_customer(&admin_client, &app.address, &admin).await?;
+
+    let diana = insert_customer(
+        "wonderwoman@test.com",
+        "Themyscira1",
+        "Diana",
+        "Prince",
+        &app.db_pool,
+    )
+    .await?;
+    insert_customer(
+        "superman@test.com",
+        "Krypton01",
+        "Clark",
+        "Kent",
+        &app.db_pool,
+    )
+    .await?;
+
+    let search = |query: &str| {
+        json!({
+            "query": r#"
+                query searchCustomers($query: String!) {
+                    searchCustomers(query: $query) {
+                        id
+                        email
+                    }
+                }
+            "#,
+            "variables": { "query": query }
+        })
+    };
+
+    let response = send_request(&admin_client, &app.address, &search("prin")).await?;
+    assert_eq!(
+        response.data["data"]["searchCustomers"],
+        json!([{ "id": diana.public_id.unwrap(), "email": "wonderwoman@test.com" }])
+    );
+
+    let response = send_request(&admin_client, &app.address, &search("MAN@TEST")).await?;
+    let emails: Vec<&str> = response.data["data"]["searchCustomers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["email"].as_str().unwrap())
+        .collect();
+    assert_eq!(emails, vec!["superman@test.com", "wonderwoman@test.com"]);
+
+    // Wildcards in the query are matched literally
+    let response = send_request(&admin_client, &app.address, &search("%%")).await?;
+    assert_eq!(response.data["data"]["searchCustomers"], json!([]));
+
+    // Only admins can search
+    let client = build_http_client()?;
+    login_customer(&client, &app.address, &diana).await?;
+    let response = send_request(&client, &app.address, &search("prin")).await?;
+    assert_eq!(response.data["errors"][0]["extensions"]["status"], 403);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_summary_matches_the_full_cart() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![
+            InternalCartItem {
+                sku: "12345678".to_string(),
+                quantity: 3,
+                added_at: None,
+                gift_message: None,
+            },
+            InternalCartItem {
+                sku: "22345678".to_string(),
+                quantity: 2,
+                added_at: None,
+                gift_message: None,
+            },
+        ],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let body = json!({
+        "query": r#"
+            query cart {
+                cart {
+                    priceAfterDiscounts
+                    currency
+                    items { sku quantity }
+                }
+                cartSummary {
+                    itemCount
+                    distinctItemCount
+                    total
+                    currency
+                }
+            }
+        "#,
+    });
+
+    let response = send_request(&client, &app.address, &body).await?;
+    let cart = response.data["data"]["cart"].clone();
+    let summary = response.data["data"]["cartSummary"].clone();
+
+    let items = cart["items"].as_array().unwrap();
+    let item_count: i64 = items.iter().map(|i| i["quantity"].as_i64().unwrap()).sum();
+
+    assert_json_include!(
+        actual: &summary,
+        expected: json!({
+            "itemCount": item_count,
+            "distinctItemCount": items.len(),
+            "currency": cart["currency"],
+        })
+    );
+    assert_on_decimal(
+        summary["total"].as_f64().unwrap(),
+        cart["priceAfterDiscounts"].as_f64().unwrap(),
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_summary_counts_are_kept_up_to_date() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    get_anonymous_token(&client, &app.address).await?;
+
+    let edit = |mutation: &str, items: serde_json::Value| {
+        let argument = match mutation {
+            "addItemsToCart" => "newItems",
+            _ => "removedItems",
+        };
+        json!({
+            "query": format!(
+                r#"
+                mutation edit($items: [UpdateCartItem!]!) {{
+                    {}({}: $items) {{ id }}
+                }}
+                "#,
+                mutation, argument
+            ),
+            "variables": { "items": items },
+        })
+    };
+    let summary = json!({ "query": "{ cartSummary { itemCount distinctItemCount } }" });
+
+    let steps = vec![
+        (
+            "addItemsToCart",
+            json!([{ "sku": "12345678", "quantity": 3 }, { "sku": "22345678", "quantity": 1 }]),
+            (4, 2),
+        ),
+        (
+            "addItemsToCart",
+            json!([{ "sku": "32345678", "quantity": 2 }]),
+            (6, 3),
+        ),
+        (
+            "removeItemsFromCart",
+            json!([{ "sku": "12345678", "quantity": 2 }]),
+            (4, 3),
+        ),
+        (
+            "removeItemsFromCart",
+            json!([{ "sku": "22345678", "quantity": 1 }]),
+            (3, 2),
+        ),
+        (
+            "removeItemsFromCart",
+            json!([{ "sku": "12345678", "quantity": 5 }, { "sku": "32345678", "quantity": 2 }]),
+            (0, 0),
+        ),
+    ];
+    for (mutation, items, (item_count, distinct_item_count)) in steps {
+        let response = send_request(&client, &app.address, &edit(mutation, items)).await?;
+        assert!(response.data["errors"].is_null(), "{:?}", response.data);
+
+        let response = send_request(&client, &app.address, &summary).await?;
+        assert_eq!(
+            response.data["data"]["cartSummary"],
+            json!({ "itemCount": item_count, "distinctItemCount": distinct_item_count })
+        );
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_cart_page_returns_the_cart_and_recommendations() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let customer = get_anonymous_token(&client, &app.address).await?;
+
+    ShoppingCart::edit_cart_items::<ShoppingCartDatabase, CartItemDatabase>(
+        customer.cart_id.unwrap(),
+        vec![InternalCartItem {
+            sku: "32345678".to_string(),
+            quantity: 1,
+            added_at: None,
+            gift_message: None,
+        }],
+        MAX_DISTINCT_CART_ITEMS,
+        &app.db_pool,
+    )
+    .await?;
+
+    let body = json!({
+        "query": r#"
+            query cartPage {
+                cartPage {
+                    cart {
+                        id
+                        items { sku quantity }
+                    }
+                    summary {
+                        itemCount
+                        total
+                    }
+                    recommendations { sku }
+                }
+            }
+        "#,
+    });
+    let response = send_request(&client, &app.address, &body).await?;
+    let page = response.data["data"]["cartPage"].clone();
+
+    assert_json_include!(
+        actual: &page,
+        expected: json!({
+            "cart": {
+                "id": customer.cart_id.unwrap(),
+                "items": [{ "sku": "32345678", "quantity": 1 }]
+            },
+            "summary": { "itemCount": 1 }
+        })
+    );
+    assert_on_decimal(page["summary"]["total"].as_f64().unwrap(), 100.30);
+    // Item 5 shares the tag but is out of stock
+    assert_eq!(page["recommendations"], json!([{ "sku": "42345678" }]));
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_without_accept_header_uses_legacy_json_responses() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let body = json!({ "query": "{ notAField }" });
+    let response = client.post(&app.address).json(&body).send().await?;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["content-type"].to_str()?,
+        "application/json"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_accepting_graphql_response_json_returns_400_for_request_errors() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let body = json!({ "query": "{ notAField }" });
+    let response = client
+        .post(&app.address)
+        .header(
+            "Accept",
+            "application/graphql-response+json, application/json;q=0.9",
+        )
+        .json(&body)
+        .send()
+        .await?;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["content-type"].to_str()?,
+        "application/graphql-response+json"
+    );
+    let data = response.json::<serde_json::Value>().await?;
+    assert!(data["errors"].is_array());
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_accepting_graphql_response_json_returns_200_for_field_errors() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+    let _customer = get_anonymous_token(&client, &app.address).await?;
+
+    // An anonymous customer is valid to query, but resolving `customer` errors
+    let body = json!({ "query": "{ healthCheck customer { id } }" });
+    let response = client
+        .post(&app.address)
+        .header("Accept", "application/graphql-response+json")
+        .json(&body)
+        .send()
+        .await?;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["content-type"].to_str()?,
+        "application/graphql-response+json"
+    );
+    let data = response.json::<serde_json::Value>().await?;
+    assert!(data["errors"].is_array());
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn query_allow_list_only_executes_listed_operations() -> Result<()> {
+    let allowed_query = "{ healthCheck }";
+    let path = std::env::temp_dir().join(format!("{}.txt", uuid::Uuid::new_v4()));
+    std::fs::write(&path, OperationAllowList::hash_operation(allowed_query))?;
+
+    let allow_list_path = path.to_string_lossy().to_string();
+    let app = spawn_app_with_configuration(|configuration| {
+        configuration.graphql.allow_list_enabled = true;
+        configuration.graphql.allow_list_path = Some(allow_list_path);
+    })
+    .await;
+    std::fs::remove_file(path)?;
+    let client = build_http_client()?;
+
+    let response = client
+        .post(&app.address)
+        .json(&json!({ "query": allowed_query }))
+        .send()
+        .await?;
+    assert_eq!(response.status().as_u16(), 200);
+    let data = response.json::<serde_json::Value>().await?;
+    assert_json_include!(actual: data["data"]["healthCheck"].clone(), expected: true);
+
+    let response = client
+        .post(&app.address)
+        .json(&json!({ "query": "{ healthCheck cartSummary { total } }" }))
+        .send()
+        .await?;
+    assert_eq!(response.status().as_u16(), 403);
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn readiness_reports_the_pool_stats() -> Result<()> {
+    let app = spawn_app().await;
+    let client = build_http_client()?;
+
+    let response = client.get(&format!("{}/ready", app.address)).send().await?;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = response.json::<serde_json::Value>().await?;
+    assert_json_include!(
+        actual: &body,
+        expected: json!({
+            "databaseReachable": true,
+            "poolExhausted": false
+        })
+    );
+    assert!(
+        body["poolSize"]
+            .as_u64()
+            .expect("should report the pool size")
+            >= 1
+    );
+    assert!(body["idleConnections"].is_u64());
+
+    Ok(())
+}